@@ -1,37 +1,137 @@
 use futures::future::LocalBoxFuture;
-use log::{debug, info};
-use serde::Serialize;
-use serde_json::json;
+use log::{debug, error, info};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use telegram_types::bot::inline_mode::InlineQuery;
 use telegram_types::bot::methods::{
-    ApiError, ChatTarget, DeleteWebhook, GetChat, GetChatMember, GetMe, Method, SetWebhook,
-    TelegramResult, UpdateTypes,
+    ApiError, ChatTarget, DeleteWebhook, GetChat, GetChatMember, GetMe, Method, SendMessage,
+    TelegramResult,
 };
 use telegram_types::bot::types::{
-    Chat, ChatMember, ChatMemberStatus, Message, Update, UpdateContent, User, UserId,
+    Chat, ChatId, ChatMember, ChatMemberStatus, Message, MessageEntity, MessageEntityKind,
+    MessageId, Update, UpdateContent, User, UserId,
 };
+use worker::js_sys::{self, Function, Promise, Reflect};
 use worker::kv::KvStore;
-use worker::wasm_bindgen::JsValue;
+use worker::wasm_bindgen::{JsCast, JsValue};
+use worker::wasm_bindgen_futures::JsFuture;
 use worker::{
-    Env, Error as WorkerError, Fetch, Headers, Method as RequestMethod, Request, RequestInit,
+    Date, Env, Error as WorkerError, Fetch, Headers, Method as RequestMethod, Request, RequestInit,
     Response, RouteContext,
 };
 
-use std::borrow::Cow;
-use std::collections::HashMap;
+use super::cache::{is_expired, CacheStore};
+use super::i18n::{translate, Language};
+
+use std::collections::{HashMap, HashSet};
 use std::future::Future;
 use std::rc::Rc;
 
-const ACCEPTED_TYPES: &[UpdateTypes] = &[UpdateTypes::Message];
+// `telegram_types` doesn't know about `my_chat_member` yet, so the names are spelled out here
+const ACCEPTED_TYPES: &[&str] = &["message", "channel_post", "inline_query", "my_chat_member"];
+const MAX_LOGGED_BODY_LENGTH: usize = 512;
+// Statuses of updates that aren't dispatched, Telegram delivers them again unless it's 200
+const UPDATE_ACKNOWLEDGED: u16 = 200;
+const UPDATE_NOT_READ: u16 = 500;
+// Chat types `telegram_types` reads with their fields, any other is read as `ChatType::Unknown`
+const KNOWN_CHAT_TYPES: &[&str] = &["private", "group", "supergroup", "channel"];
+// Seconds an admin check is trusted for
+const ADMIN_CACHE_TTL: u64 = 60;
+const DEFAULT_COMMAND_PREFIX: &str = "/";
+const SECRET_TOKEN_HEADER: &str = "X-Telegram-Bot-Api-Secret-Token";
 
 type CommandFn<'a> =
     Rc<dyn 'a + Fn(Message, Env, Bot<'a>) -> LocalBoxFuture<'a, Result<Response, WorkerError>>>;
+type InlineQueryFn<'a> =
+    Rc<dyn 'a + Fn(InlineQuery, Env, Bot<'a>) -> LocalBoxFuture<'a, Result<Response, WorkerError>>>;
+type ChatMemberFn<'a> = Rc<
+    dyn 'a
+        + Fn(ChatMemberUpdate, Env, Bot<'a>) -> LocalBoxFuture<'a, Result<Response, WorkerError>>,
+>;
 
 #[derive(Clone)]
 pub struct Bot<'a> {
     token: String,
     username: String,
     kv_store: String,
+    cache_store: Option<String>,
     commands: HashMap<String, CommandFn<'a>>,
+    descriptions: HashMap<String, String>,
+    inline_query: Option<InlineQueryFn<'a>>,
+    my_chat_member: Option<ChatMemberFn<'a>>,
+    cooldown: u64,
+    cooldown_exempt: HashSet<String>,
+    throttled: Option<CommandFn<'a>>,
+    command_prefixes: Vec<String>,
+    webhook_secret: Option<String>,
+}
+
+/// Change of the bot's own membership in a chat, e.g. being promoted or demoted
+#[derive(Clone, Debug, Deserialize)]
+pub struct ChatMemberUpdate {
+    pub chat: Chat,
+    pub from: User,
+    pub date: u64,
+    pub old_chat_member: ChatMember,
+    pub new_chat_member: ChatMember,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+struct MyChatMemberContent {
+    my_chat_member: Option<ChatMemberUpdate>,
+}
+
+/// `setWebhook` with the update types given as strings
+#[derive(Clone, Debug, Serialize)]
+pub struct SetWebhook<'a> {
+    pub url: &'a str,
+    pub allowed_updates: &'a [&'a str],
+    /// Sent back by Telegram in `X-Telegram-Bot-Api-Secret-Token` with every update
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub secret_token: Option<&'a str>,
+}
+
+impl Method for SetWebhook<'_> {
+    const NAME: &'static str = "setWebhook";
+    type Item = bool;
+}
+
+/// `pinChatMessage`, the bot needs the right to pin messages in groups
+#[derive(Clone, Debug, Serialize)]
+pub struct PinChatMessage<'a> {
+    pub chat_id: ChatTarget<'a>,
+    pub message_id: MessageId,
+    pub disable_notification: bool,
+}
+
+impl Method for PinChatMessage<'_> {
+    const NAME: &'static str = "pinChatMessage";
+    type Item = bool;
+}
+
+/// `unpinChatMessage`, unpins the most recent pinned message without `message_id`
+#[derive(Clone, Debug, Serialize)]
+pub struct UnpinChatMessage<'a> {
+    pub chat_id: ChatTarget<'a>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub message_id: Option<MessageId>,
+}
+
+impl Method for UnpinChatMessage<'_> {
+    const NAME: &'static str = "unpinChatMessage";
+    type Item = bool;
+}
+
+/// `getChatMemberCount`, not covered by `telegram_types` yet
+#[derive(Clone, Debug, Serialize)]
+pub struct GetChatMemberCount<'a> {
+    pub chat_id: ChatTarget<'a>,
+}
+
+impl Method for GetChatMemberCount<'_> {
+    const NAME: &'static str = "getChatMemberCount";
+    type Item = u64;
 }
 
 #[derive(Clone, Debug, Serialize)]
@@ -47,7 +147,16 @@ impl<'a> Bot<'a> {
             token: token.as_ref().to_string(),
             username: username.as_ref().to_string(),
             kv_store: kv_store.as_ref().to_string(),
+            cache_store: None,
             commands: HashMap::new(),
+            descriptions: HashMap::new(),
+            inline_query: None,
+            my_chat_member: None,
+            cooldown: 0,
+            cooldown_exempt: HashSet::new(),
+            throttled: None,
+            command_prefixes: vec![DEFAULT_COMMAND_PREFIX.to_string()],
+            webhook_secret: None,
         }
     }
 
@@ -94,29 +203,107 @@ impl<'a> Bot<'a> {
         let mut result = self
             .send_json_request(GetChat { chat_id }, RequestMethod::Post)
             .await?;
-        result
-            .json::<TelegramResult<Chat>>()
+        parse_telegram_json::<TelegramResult<Chat>>(&result.text().await?)
+            .map_err(|e| WorkerError::RustError(e.to_string()))?
+            .into_result()
+            .map_err(Bot::convert_error)
+    }
+
+    pub async fn send_message(
+        &self,
+        chat_id: ChatTarget<'_>,
+        text: &str,
+    ) -> Result<TelegramResult<Message>, WorkerError> {
+        self.send_json_request(SendMessage::new(chat_id, text), RequestMethod::Post)
+            .await?
+            .json::<TelegramResult<Message>>()
+            .await
+    }
+
+    pub async fn pin_chat_message(
+        &self,
+        chat_id: ChatTarget<'_>,
+        message_id: MessageId,
+    ) -> Result<TelegramResult<bool>, WorkerError> {
+        let payload = PinChatMessage {
+            chat_id,
+            message_id,
+            disable_notification: true,
+        };
+        self.send_json_request(payload, RequestMethod::Post)
+            .await?
+            .json::<TelegramResult<bool>>()
+            .await
+    }
+
+    pub async fn unpin_chat_message(
+        &self,
+        chat_id: ChatTarget<'_>,
+        message_id: MessageId,
+    ) -> Result<TelegramResult<bool>, WorkerError> {
+        let payload = UnpinChatMessage {
+            chat_id,
+            message_id: Some(message_id),
+        };
+        self.send_json_request(payload, RequestMethod::Post)
+            .await?
+            .json::<TelegramResult<bool>>()
+            .await
+    }
+
+    pub async fn get_chat_member_count(&self, chat_id: ChatTarget<'_>) -> Result<u64, WorkerError> {
+        self.send_json_request(GetChatMemberCount { chat_id }, RequestMethod::Post)
+            .await?
+            .json::<TelegramResult<u64>>()
             .await?
             .into_result()
             .map_err(Bot::convert_error)
     }
 
-    pub async fn is_admin(
+    pub async fn get_chat_member(
         &self,
         chat_id: ChatTarget<'_>,
         user_id: UserId,
-    ) -> Result<bool, WorkerError> {
-        let chat_member = self
-            .send_json_request(GetChatMember { chat_id, user_id }, RequestMethod::Post)
+    ) -> Result<ChatMember, WorkerError> {
+        self.send_json_request(GetChatMember { chat_id, user_id }, RequestMethod::Post)
             .await?
             .json::<TelegramResult<ChatMember>>()
             .await?
             .into_result()
-            .map_err(Bot::convert_error)?;
-        let member_status = chat_member.status;
-        info!("Member status: {:?}", member_status);
-        Ok(member_status == ChatMemberStatus::Creator
-            || member_status == ChatMemberStatus::Administrator)
+            .map_err(Bot::convert_error)
+    }
+
+    pub async fn is_admin(
+        &self,
+        chat_id: ChatTarget<'_>,
+        user_id: UserId,
+    ) -> Result<bool, WorkerError> {
+        let chat_member = self.get_chat_member(chat_id, user_id).await?;
+        info!("Member status: {:?}", chat_member.status);
+        Ok(is_admin_status(&chat_member.status))
+    }
+
+    /// Checks whether the user is an admin of the chat, the answers are cached per chat for
+    /// `ADMIN_CACHE_TTL` seconds if the cache is enabled
+    pub async fn is_admin_cached(
+        &self,
+        env: &Env,
+        chat_id: ChatId,
+        user_id: UserId,
+    ) -> Result<bool, WorkerError> {
+        let cache = self.get_cache(env);
+        let key = admin_cache_key(&chat_id);
+        let now = Date::now().as_millis();
+        let mut admins: AdminCache = cache.get(&key).await.unwrap_or_default();
+        if let Some(is_admin) = cached_admin_status(&admins, user_id, now) {
+            return Ok(is_admin);
+        }
+        let is_admin = self.is_admin(ChatTarget::Id(chat_id), user_id).await?;
+        if cache.is_enabled() {
+            cache_admin_status(&mut admins, user_id, is_admin, now);
+            cache.put(&key, admins, ADMIN_CACHE_TTL).await;
+        }
+        Ok(is_admin)
     }
 
     // fn get_kv(&self) -> Result<KvStore, WorkerError> {
@@ -137,16 +324,26 @@ impl<'a> Bot<'a> {
         self.username.clone()
     }
 
+    /// Reads the settings from the environment, the error names the first one that is missing
     pub fn new_with_env<S: AsRef<str>>(
         env: &Env,
         var_token: S,
         var_username: S,
         var_kv_store: S,
     ) -> Result<Self, WorkerError> {
+        let missing = |kind: &str, name: &str| {
+            WorkerError::RustError(format!("{} {} is not set", kind, name))
+        };
         Ok(Self::new(
-            env.secret(var_token.as_ref())?.to_string(),
-            env.var(var_username.as_ref())?.to_string(),
-            env.var(var_kv_store.as_ref())?.to_string(),
+            env.secret(var_token.as_ref())
+                .map_err(|_| missing("Secret", var_token.as_ref()))?
+                .to_string(),
+            env.var(var_username.as_ref())
+                .map_err(|_| missing("Variable", var_username.as_ref()))?
+                .to_string(),
+            env.var(var_kv_store.as_ref())
+                .map_err(|_| missing("Variable", var_kv_store.as_ref()))?
+                .to_string(),
         ))
     }
 
@@ -161,8 +358,11 @@ impl<'a> Bot<'a> {
             "Trying to delete previously set webhooks: {}",
             result.text().await?
         );
-        let mut payload = SetWebhook::new(url.as_ref());
-        payload.allowed_updates = Some(Cow::from(ACCEPTED_TYPES));
+        let payload = SetWebhook {
+            url: url.as_ref(),
+            allowed_updates: ACCEPTED_TYPES,
+            secret_token: self.webhook_secret.as_deref(),
+        };
         let mut result = self.send_json_request(payload, RequestMethod::Post).await?;
         info!("Set new webhook: {}", result.text().await?);
         Ok(())
@@ -182,23 +382,148 @@ impl<'a> Bot<'a> {
         );
     }
 
-    pub async fn run_commands(&self, m: Message, env: Env) -> Result<Response, WorkerError> {
+    /// Registers the command along with a one-line description listed by `/help`
+    pub fn register_command_with_desc<
+        S: AsRef<str>,
+        F: 'a + Future<Output = Result<Response, WorkerError>>,
+    >(
+        &mut self,
+        command: S,
+        description: S,
+        func: fn(Message, Env, Bot<'a>) -> F,
+    ) {
+        self.descriptions.insert(
+            command.as_ref().to_string(),
+            description.as_ref().to_string(),
+        );
+        self.register_command(command, func);
+    }
+
+    /// Names of the registered commands in order, with their descriptions if there are any
+    pub fn list_commands(&self) -> Vec<(&str, Option<&str>)> {
+        let mut commands = self
+            .commands
+            .keys()
+            .map(|command| {
+                (
+                    command.as_str(),
+                    self.descriptions.get(command).map(String::as_str),
+                )
+            })
+            .collect::<Vec<(&str, Option<&str>)>>();
+        commands.sort_unstable();
+        commands
+    }
+
+    pub fn register_inline_query<F: 'a + Future<Output = Result<Response, WorkerError>>>(
+        &mut self,
+        func: fn(InlineQuery, Env, Bot<'a>) -> F,
+    ) {
+        self.inline_query = Some(Rc::new(move |query, env, bot| {
+            Box::pin(func(query, env, bot))
+        }));
+    }
+
+    pub fn register_my_chat_member<F: 'a + Future<Output = Result<Response, WorkerError>>>(
+        &mut self,
+        func: fn(ChatMemberUpdate, Env, Bot<'a>) -> F,
+    ) {
+        self.my_chat_member = Some(Rc::new(move |update, env, bot| {
+            Box::pin(func(update, env, bot))
+        }));
+    }
+
+    /// Sets the seconds a chat has to wait between commands, `0` disables the limit
+    pub fn set_cooldown(&mut self, seconds: u64) {
+        self.cooldown = seconds;
+    }
+
+    /// Sets the prefixes commands are recognized by, e.g. `/` and `!`. Keeps the current ones if
+    /// there are none.
+    pub fn set_command_prefixes<S: AsRef<str>>(&mut self, prefixes: &[S]) {
+        let prefixes: Vec<String> = prefixes
+            .iter()
+            .map(|prefix| prefix.as_ref().trim().to_lowercase())
+            .filter(|prefix| !prefix.is_empty())
+            .collect();
+        if !prefixes.is_empty() {
+            self.command_prefixes = prefixes;
+        }
+    }
+
+    /// The prefix shown with commands, the first configured one
+    pub fn get_command_prefix(&self) -> &str {
+        &self.command_prefixes[0]
+    }
+
+    /// Lets the commands run regardless of the cooldown, meant for the read-only ones
+    pub fn exempt_from_cooldown(&mut self, commands: &[&str]) {
+        self.cooldown_exempt
+            .extend(commands.iter().map(|command| command.to_ascii_lowercase()));
+    }
+
+    /// Sets the handler for commands sent before the cooldown of the chat is over
+    pub fn register_throttled<F: 'a + Future<Output = Result<Response, WorkerError>>>(
+        &mut self,
+        func: fn(Message, Env, Bot<'a>) -> F,
+    ) {
+        self.throttled = Some(Rc::new(move |m, env, bot| Box::pin(func(m, env, bot))));
+    }
+
+    /// Checks whether the chat sent a command within the cooldown, and records this one if not.
+    /// The timestamps are kept in the cache namespace, or the main one if there's no cache.
+    async fn is_throttled(&self, env: &Env, command: &str, m: &Message) -> bool {
+        if self.cooldown == 0 || self.cooldown_exempt.contains(&command.to_ascii_lowercase()) {
+            return false;
+        }
+        let cache = self.get_cache(env);
+        let cache = if cache.is_enabled() {
+            cache
+        } else {
+            match self.get_kv(env) {
+                Ok(kv) => CacheStore::new(Some(kv)),
+                Err(e) => {
+                    error!("Unable to get KV store for the cooldown: {}", e);
+                    return false;
+                }
+            }
+        };
+        let key = cooldown_key(&m.chat.id);
+        if let Some(last_command) = cache.get::<u64>(&key).await {
+            info!("Last command of the chat was at {}", last_command);
+            return true;
+        }
+        cache
+            .put(&key, Date::now().as_millis(), self.cooldown)
+            .await;
+        false
+    }
+
+    pub async fn run_commands(&self, mut m: Message, env: Env) -> Result<Response, WorkerError> {
         let message_text = m.text.clone().unwrap_or_default();
         info!("Non empty message text: {}", message_text);
-        let message_command = message_text.split(' ').collect::<Vec<&str>>()[0]
-            .trim()
+        // Whatever comes before the command is dropped so handlers find it first
+        let command_text = command_text(&message_text, &m.entities, &self.command_prefixes);
+        let message_command = command_text
+            .split_whitespace()
+            .next()
+            .unwrap_or_default()
             .to_ascii_lowercase();
         debug!("First phrase extracted from text: {}", message_command);
+        if command_text.len() != message_text.len() {
+            m.text = Some(command_text.to_string());
+        }
+        let username = self.get_username().to_ascii_lowercase();
         for (command, func) in &self.commands {
-            // `/start bruh` and `/start@blablabot bruh`
-            let command_prefix = format!("/{}", command.to_ascii_lowercase());
-            let command_prefix_extended = format!(
-                "{}@{}",
-                command_prefix,
-                self.get_username().to_ascii_lowercase()
-            );
-            if (message_command == command_prefix) || (message_command == command_prefix_extended) {
+            if matches_command(&message_command, &self.command_prefixes, command, &username) {
                 info!("Command matched: {}", command);
+                if self.is_throttled(&env, command, &m).await {
+                    info!("Command sent within the cooldown");
+                    return match self.throttled.clone() {
+                        Some(throttled) => throttled(m, env, self.clone()).await,
+                        None => Response::empty(),
+                    };
+                }
                 return func(m, env, self.clone()).await;
             }
         }
@@ -210,15 +535,51 @@ impl<'a> Bot<'a> {
         req: &mut Request,
         ctx: RouteContext<Bot<'a>>,
     ) -> Result<Response, WorkerError> {
-        let update = req.json::<Update>().await?;
+        if !ctx.data.is_from_telegram(req)? {
+            error!("Rejected an update without the webhook secret");
+            return Response::error("Forbidden", 403);
+        }
+        let (update, body) = match parse_update(req.text().await) {
+            Ok(parsed) => parsed,
+            Err(UPDATE_ACKNOWLEDGED) => return Response::from_json(&json!({})),
+            Err(status) => return Response::error("Unable to read the update", status),
+        };
         debug!("Received update: {:?}", update);
         if update.content.is_none() {
+            if let Ok(MyChatMemberContent {
+                my_chat_member: Some(member_update),
+            }) = serde_json::from_str::<MyChatMemberContent>(&body)
+            {
+                debug!("Got chat member update: {:?}", member_update);
+                let bot = ctx.data;
+                return match bot.my_chat_member.clone() {
+                    Some(func) => func(member_update, ctx.env, bot).await,
+                    None => Response::from_json(&json!({})),
+                };
+            }
             debug!("No content found, ignoring...");
             return Response::from_json(&json!({}));
         }
         let update_content = update.content.unwrap();
-        if let UpdateContent::Message(m) = update_content {
+        if let UpdateContent::InlineQuery(query) = update_content {
+            debug!("Got inline query: {:?}", query);
+            let bot = ctx.data;
+            return match bot.inline_query.clone() {
+                Some(func) => func(query, ctx.env, bot).await,
+                None => Response::from_json(&json!({})),
+            };
+        }
+        // Commands posted in channels are handled like ones sent in groups
+        if let UpdateContent::Message(m) | UpdateContent::ChannelPost(m) = update_content {
             debug!("Got message: {:#?}", m);
+            if let Some(title) = &m.new_chat_title {
+                info!(
+                    "Title of chat {} changed to {} by {:?}",
+                    m.chat.id.0,
+                    title,
+                    m.from.as_ref().map(|user| user.id.0)
+                );
+            }
             if m.text.is_none() {
                 debug!("No text found, ignoring...");
                 return Response::from_json(&json!({}));
@@ -235,6 +596,255 @@ impl<'a> Bot<'a> {
     pub fn get_kv(&self, env: &Env) -> Result<KvStore, WorkerError> {
         env.kv(&self.kv_store)
     }
+
+    /// Sets the token Telegram has to send along with updates, any request is accepted if it's
+    /// `None`. Takes effect for Telegram once the webhook is set up again.
+    pub fn set_webhook_secret<S: AsRef<str>>(&mut self, secret: Option<S>) {
+        self.webhook_secret = secret
+            .map(|secret| secret.as_ref().to_string())
+            .filter(|secret| !secret.is_empty());
+    }
+
+    /// Whether the request carries the webhook secret, if there is one
+    fn is_from_telegram(&self, req: &Request) -> Result<bool, WorkerError> {
+        let provided = req.headers().get(SECRET_TOKEN_HEADER)?;
+        Ok(secret_matches(
+            self.webhook_secret.as_deref(),
+            provided.as_deref(),
+        ))
+    }
+
+    /// Sets the binding of the KV namespace used for caches, caching is disabled if it's `None`
+    pub fn set_cache_store<S: AsRef<str>>(&mut self, cache_store: Option<S>) {
+        self.cache_store = cache_store.map(|name| name.as_ref().to_string());
+    }
+
+    pub fn get_cache(&self, env: &Env) -> CacheStore {
+        let kv = self
+            .cache_store
+            .as_ref()
+            .and_then(|name| match env.kv(name) {
+                Ok(kv) => Some(kv),
+                Err(e) => {
+                    error!("Unable to get cache KV store {}: {}", name, e);
+                    None
+                }
+            });
+        CacheStore::new(kv)
+    }
+}
+
+/// Waits for `ms` milliseconds with the `setTimeout` of the runtime
+pub async fn sleep(ms: u64) -> Result<(), WorkerError> {
+    let global = js_sys::global();
+    let set_timeout =
+        Reflect::get(&global, &JsValue::from_str("setTimeout"))?.dyn_into::<Function>()?;
+    let promise = Promise::new(&mut |resolve, reject| {
+        if let Err(e) = set_timeout.call2(&global, &resolve, &JsValue::from_f64(ms as f64)) {
+            reject.call1(&JsValue::NULL, &e).ok();
+        }
+    });
+    JsFuture::from(promise).await?;
+    Ok(())
+}
+
+/// Whether the chat member went from being unable to change chat info to being able to
+pub fn gained_change_info(old: &ChatMember, new: &ChatMember) -> bool {
+    let can_change_info = |member: &ChatMember| {
+        member.status == ChatMemberStatus::Creator
+            || (member.status == ChatMemberStatus::Administrator
+                && member.can_change_info.unwrap_or(false))
+    };
+    !can_change_info(old) && can_change_info(new)
+}
+
+/// Whether the chat member is allowed to pin messages
+pub fn can_pin_messages(member: &ChatMember) -> bool {
+    member.status == ChatMemberStatus::Creator
+        || (member.status == ChatMemberStatus::Administrator
+            && member.can_pin_messages.unwrap_or(false))
+}
+
+/// Chats of types unknown to `telegram_types` lose their title, ones that have a title are
+/// group-like, so they're read as supergroups instead
+fn normalize_chat_types(value: &mut Value) {
+    match value {
+        Value::Object(object) => {
+            let unknown_titled = object.contains_key("id")
+                && object.get("title").is_some_and(Value::is_string)
+                && object
+                    .get("type")
+                    .and_then(Value::as_str)
+                    .is_some_and(|kind| !KNOWN_CHAT_TYPES.contains(&kind));
+            if unknown_titled {
+                info!("Reading chat of type {:?} as a supergroup", object["type"]);
+                object.insert("type".to_string(), json!("supergroup"));
+            }
+            object.values_mut().for_each(normalize_chat_types);
+        }
+        Value::Array(values) => values.iter_mut().for_each(normalize_chat_types),
+        _ => (),
+    }
+}
+
+/// Parses a payload from Telegram, keeping the titles of chats of unknown types
+pub fn parse_telegram_json<T: DeserializeOwned>(text: &str) -> serde_json::Result<T> {
+    let mut value = serde_json::from_str::<Value>(text)?;
+    normalize_chat_types(&mut value);
+    serde_json::from_value(value)
+}
+
+/// Parses the body of an update, or tells the status to answer with instead. Failing to read
+/// the body is transient and answered with 500 so Telegram delivers it again, but a body that
+/// can't be parsed will never succeed, so it's acknowledged to stop the redelivery.
+fn parse_update(body: Result<String, WorkerError>) -> Result<(Update, String), u16> {
+    let body = body.map_err(|e| {
+        error!("Unable to read update: {}", e);
+        UPDATE_NOT_READ
+    })?;
+    match parse_telegram_json::<Update>(&body) {
+        Ok(update) => Ok((update, body)),
+        Err(e) => {
+            error!(
+                "Unable to parse update: {}, raw body: {}",
+                e,
+                body.chars()
+                    .take(MAX_LOGGED_BODY_LENGTH)
+                    .collect::<String>()
+            );
+            Err(UPDATE_ACKNOWLEDGED)
+        }
+    }
+}
+
+/// Compares the whole token regardless of where the first mismatch is
+pub fn token_matches(expected: &str, provided: &str) -> bool {
+    expected.len() == provided.len()
+        && expected
+            .bytes()
+            .zip(provided.bytes())
+            .fold(0, |acc, (a, b)| acc | (a ^ b))
+            == 0
+}
+
+/// Whether the provided header carries the expected secret, anything goes if there's none
+pub fn secret_matches(expected: Option<&str>, provided: Option<&str>) -> bool {
+    match expected {
+        Some(expected) => provided.is_some_and(|provided| token_matches(expected, provided)),
+        None => true,
+    }
+}
+
+/// Whether the first word of the message, in lowercase, is the command with one of the prefixes
+fn matches_command(
+    message_command: &str,
+    prefixes: &[String],
+    command: &str,
+    username: &str,
+) -> bool {
+    prefixes.iter().any(|prefix| {
+        // `/start bruh` and `/start@blablabot bruh`
+        let command_prefix = format!("{}{}", prefix, command.to_ascii_lowercase());
+        let command_prefix_extended = format!("{}@{}", command_prefix, username);
+        (message_command == command_prefix) || (message_command == command_prefix_extended)
+    })
+}
+
+/// The text from the command on. Telegram marks `/` commands wherever they are, which are only
+/// used if `/` is one of the prefixes, otherwise the command is looked for at the start.
+fn command_text<'t>(text: &'t str, entities: &[MessageEntity], prefixes: &[String]) -> &'t str {
+    match command_offset(text, entities) {
+        Some(offset)
+            if prefixes
+                .iter()
+                .any(|prefix| text[offset..].starts_with(prefix.as_str())) =>
+        {
+            &text[offset..]
+        }
+        _ => text.trim_start(),
+    }
+}
+
+/// Byte offset of the first command Telegram found in the text, whose offsets are in UTF-16
+/// code units
+fn command_offset(text: &str, entities: &[MessageEntity]) -> Option<usize> {
+    let entity = entities
+        .iter()
+        .find(|entity| entity.kind == MessageEntityKind::BotCommand)?;
+    let mut units = 0;
+    for (index, c) in text.char_indices() {
+        if units == entity.offset as usize {
+            return Some(index);
+        }
+        units += c.len_utf16();
+    }
+    None
+}
+
+/// Creators and administrators both count as admins
+pub fn is_admin_status(status: &ChatMemberStatus) -> bool {
+    *status == ChatMemberStatus::Creator || *status == ChatMemberStatus::Administrator
+}
+
+/// Key of the time a chat last sent a command
+pub fn cooldown_key(chat_id: &ChatId) -> String {
+    let ChatId(raw_id) = *chat_id;
+    format!("cooldown-{}", raw_id)
+}
+
+/// Key of the cached admin checks of a chat
+pub fn admin_cache_key(chat_id: &ChatId) -> String {
+    let ChatId(raw_id) = *chat_id;
+    format!("admins-{}", raw_id)
+}
+
+/// User ID to whether they are an admin of the chat and when that expires
+type AdminCache = HashMap<i64, (bool, u64)>;
+
+fn cached_admin_status(admins: &AdminCache, user_id: UserId, now: u64) -> Option<bool> {
+    admins
+        .get(&user_id.0)
+        .filter(|(_, expires_at)| !is_expired(*expires_at, now))
+        .map(|(is_admin, _)| *is_admin)
+}
+
+/// Remembers the answer for `ADMIN_CACHE_TTL` seconds, dropping the expired ones
+fn cache_admin_status(admins: &mut AdminCache, user_id: UserId, is_admin: bool, now: u64) {
+    admins.retain(|_, (_, expires_at)| !is_expired(*expires_at, now));
+    admins.insert(user_id.0, (is_admin, now + ADMIN_CACHE_TTL * 1000));
+}
+
+/// Lists the administrator rights of a chat member in a human readable form
+pub fn describe_permissions(member: &ChatMember, language: Language) -> String {
+    if member.status != ChatMemberStatus::Administrator
+        && member.status != ChatMemberStatus::Creator
+    {
+        return translate(language, "bot 不是管理员，无法更改群标题").to_string();
+    }
+    let permissions = [
+        ("修改群组信息", member.can_change_info),
+        ("删除消息", member.can_delete_messages),
+        ("封禁成员", member.can_restrict_members),
+        ("邀请成员", member.can_invite_users),
+        ("置顶消息", member.can_pin_messages),
+        ("添加管理员", member.can_promote_members),
+        ("发布消息", member.can_post_messages),
+        ("编辑消息", member.can_edit_messages),
+    ];
+    permissions
+        .iter()
+        // Rights that don't apply to this kind of chat are absent
+        .filter_map(|(name, permission)| {
+            permission.map(|allowed| {
+                format!(
+                    "{}: {}",
+                    translate(language, name),
+                    if allowed { "✅" } else { "❌" }
+                )
+            })
+        })
+        .collect::<Vec<String>>()
+        .join("\n")
 }
 
 impl<T: Method> From<T> for WebhookReply<T> {
@@ -245,3 +855,256 @@ impl<T: Method> From<T> for WebhookReply<T> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn malformed_update_is_acknowledged() {
+        assert_eq!(
+            parse_update(Ok("{\"update_id\": ".to_string())).err(),
+            Some(UPDATE_ACKNOWLEDGED)
+        );
+        assert_eq!(
+            parse_update(Ok("not json".to_string())).err(),
+            Some(UPDATE_ACKNOWLEDGED)
+        );
+    }
+
+    #[test]
+    fn unread_update_is_retried() {
+        let status = parse_update(Err(WorkerError::RustError("reset".to_string()))).err();
+        assert_eq!(status, Some(UPDATE_NOT_READ));
+        assert!(status.unwrap() >= 500);
+    }
+
+    #[test]
+    fn valid_update_is_parsed() {
+        let body = "{\"update_id\": 42}".to_string();
+        let (update, raw) = parse_update(Ok(body.clone())).unwrap();
+        assert_eq!(update.update_id.0, 42);
+        assert!(update.content.is_none());
+        assert_eq!(raw, body);
+    }
+
+    fn stub_member(status: &str, rights: serde_json::Value) -> ChatMember {
+        let mut member = json!({
+            "user": {"id": 42, "is_bot": true, "first_name": "Title Bot"},
+            "status": status,
+        });
+        if let (Some(member), Some(rights)) = (member.as_object_mut(), rights.as_object()) {
+            member.extend(rights.clone());
+        }
+        serde_json::from_value(member).unwrap()
+    }
+
+    #[test]
+    fn admin_rights_are_described() {
+        let member = stub_member(
+            "administrator",
+            json!({
+                "can_change_info": true,
+                "can_delete_messages": false,
+                "can_pin_messages": true,
+            }),
+        );
+        assert_eq!(
+            describe_permissions(&member, Language::Zh),
+            "修改群组信息: ✅\n删除消息: ❌\n置顶消息: ✅"
+        );
+        assert_eq!(
+            describe_permissions(&member, Language::En),
+            "Change group info: ✅\nDelete messages: ❌\nPin messages: ✅"
+        );
+    }
+
+    #[test]
+    fn non_admin_bot_is_reported() {
+        let member = stub_member("member", json!({}));
+        assert_eq!(
+            describe_permissions(&member, Language::En),
+            "The bot isn't an admin and can't change the title"
+        );
+    }
+
+    #[test]
+    fn promotion_with_change_info_is_detected() {
+        let member = stub_member("member", json!({}));
+        let admin = stub_member("administrator", json!({"can_change_info": true}));
+        let limited_admin = stub_member("administrator", json!({"can_change_info": false}));
+        assert!(gained_change_info(&member, &admin));
+        assert!(gained_change_info(&limited_admin, &admin));
+        assert!(!gained_change_info(&member, &limited_admin));
+        assert!(!gained_change_info(&admin, &admin));
+        assert!(!gained_change_info(&admin, &member));
+    }
+
+    #[test]
+    fn refreshed_admin_status_is_read_again() {
+        let mut admins = AdminCache::new();
+        cache_admin_status(&mut admins, UserId(1), true, 0);
+        assert_eq!(cached_admin_status(&admins, UserId(1), 1_000), Some(true));
+        assert_eq!(cached_admin_status(&admins, UserId(2), 1_000), None);
+
+        // `/refresh_admins` deletes the entry of the chat, so the demotion is picked up
+        let mut admins = AdminCache::new();
+        assert_eq!(cached_admin_status(&admins, UserId(1), 1_000), None);
+        cache_admin_status(&mut admins, UserId(1), false, 1_000);
+        assert_eq!(cached_admin_status(&admins, UserId(1), 2_000), Some(false));
+    }
+
+    #[test]
+    fn admin_caches_are_kept_per_chat() {
+        assert_eq!(admin_cache_key(&ChatId(-100)), "admins--100");
+        assert_ne!(
+            admin_cache_key(&ChatId(-100)),
+            admin_cache_key(&ChatId(-200))
+        );
+    }
+
+    #[test]
+    fn pin_rights_are_checked() {
+        assert!(can_pin_messages(&stub_member("creator", json!({}))));
+        assert!(can_pin_messages(&stub_member(
+            "administrator",
+            json!({"can_pin_messages": true})
+        )));
+        assert!(!can_pin_messages(&stub_member("administrator", json!({}))));
+        assert!(!can_pin_messages(&stub_member("member", json!({}))));
+    }
+
+    #[test]
+    fn cached_admin_status_expires() {
+        let mut admins = AdminCache::new();
+        cache_admin_status(&mut admins, UserId(1), true, 0);
+        let expires_at = ADMIN_CACHE_TTL * 1000;
+        assert_eq!(
+            cached_admin_status(&admins, UserId(1), expires_at - 1),
+            Some(true)
+        );
+        assert_eq!(cached_admin_status(&admins, UserId(1), expires_at), None);
+
+        // Expired answers are dropped when another one is cached
+        cache_admin_status(&mut admins, UserId(2), false, expires_at);
+        assert!(!admins.contains_key(&1));
+        assert_eq!(
+            cached_admin_status(&admins, UserId(2), expires_at),
+            Some(false)
+        );
+    }
+
+    #[test]
+    fn creators_and_administrators_are_admins() {
+        assert!(is_admin_status(&ChatMemberStatus::Creator));
+        assert!(is_admin_status(&ChatMemberStatus::Administrator));
+        assert!(!is_admin_status(&ChatMemberStatus::Member));
+        assert!(!is_admin_status(&ChatMemberStatus::Restricted));
+        assert!(!is_admin_status(&ChatMemberStatus::Left));
+        assert!(!is_admin_status(&ChatMemberStatus::Kicked));
+    }
+
+    fn command_entity(offset: i64, length: i64) -> Vec<MessageEntity> {
+        serde_json::from_value(json!([{"type": "bot_command", "offset": offset, "length": length}]))
+            .unwrap()
+    }
+
+    #[test]
+    fn leading_whitespace_is_skipped() {
+        let prefixes = vec!["/".to_string()];
+        assert_eq!(
+            command_text("  /status", &command_entity(2, 7), &prefixes),
+            "/status"
+        );
+        assert_eq!(
+            command_text("\n!status", &[], &["!".to_string()]),
+            "!status"
+        );
+        // Offsets are in UTF-16 code units
+        assert_eq!(
+            command_text("😀 /status now", &command_entity(3, 7), &prefixes),
+            "/status now"
+        );
+    }
+
+    #[test]
+    fn commands_later_in_the_text_need_the_slash_prefix() {
+        let entities = command_entity(3, 7);
+        assert_eq!(
+            command_text("hi /status", &entities, &["/".to_string()]),
+            "/status"
+        );
+        assert_eq!(
+            command_text("hi /status", &entities, &["!".to_string()]),
+            "hi /status"
+        );
+    }
+
+    #[test]
+    fn mentioned_commands_are_matched() {
+        let prefixes = vec!["/".to_string(), "!".to_string()];
+        assert!(matches_command("/status", &prefixes, "status", "title_bot"));
+        assert!(matches_command(
+            "/status@title_bot",
+            &prefixes,
+            "status",
+            "title_bot"
+        ));
+        assert!(matches_command(
+            "!status@title_bot",
+            &prefixes,
+            "status",
+            "title_bot"
+        ));
+        assert!(!matches_command(
+            "/status@other_bot",
+            &prefixes,
+            "status",
+            "title_bot"
+        ));
+        assert!(!matches_command(
+            "/statuses",
+            &prefixes,
+            "status",
+            "title_bot"
+        ));
+        assert!(!matches_command("status", &prefixes, "status", "title_bot"));
+    }
+
+    #[test]
+    fn updates_without_the_webhook_secret_are_rejected() {
+        assert!(secret_matches(Some("secret"), Some("secret")));
+        assert!(!secret_matches(Some("secret"), Some("wrong!")));
+        assert!(!secret_matches(Some("secret"), Some("secret2")));
+        assert!(!secret_matches(Some("secret"), Some("")));
+        assert!(!secret_matches(Some("secret"), None));
+        // Without a secret configured there's nothing to check
+        assert!(secret_matches(None, None));
+        assert!(secret_matches(None, Some("anything")));
+    }
+
+    #[test]
+    fn updates_from_unknown_chat_types_keep_the_title() {
+        let body = json!({
+            "update_id": 42,
+            "message": {
+                "message_id": 1,
+                "date": 0,
+                "chat": {"id": -100, "type": "forum", "title": "Forum"},
+                "from": {"id": 7, "is_bot": false, "first_name": "Alice"},
+                "text": "/status",
+                "entities": [{"type": "bot_command", "offset": 0, "length": 7}],
+            },
+        });
+        let (update, _) = parse_update(Ok(body.to_string())).unwrap();
+        let m = match update.content {
+            Some(UpdateContent::Message(m)) => m,
+            other => panic!("unexpected {:?}", other),
+        };
+        assert_eq!(
+            crate::group::get_group_title(&m.chat.kind).as_deref(),
+            Some("Forum")
+        );
+        assert_eq!(m.entities[0].kind, MessageEntityKind::BotCommand);
+    }
+}
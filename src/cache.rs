@@ -0,0 +1,131 @@
+use log::error;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use worker::kv::KvStore;
+use worker::Date;
+
+// Workers KV refuses expiration TTLs shorter than a minute
+const MIN_KV_EXPIRATION_TTL: u64 = 60;
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct CacheEntry<T> {
+    expires_at: u64,
+    value: T,
+}
+
+/// Short-lived cache backed by a separate KV namespace, does nothing if it's not configured
+#[derive(Clone)]
+pub struct CacheStore {
+    kv: Option<KvStore>,
+}
+
+pub fn is_expired(expires_at: u64, now: u64) -> bool {
+    now >= expires_at
+}
+
+impl<T> CacheEntry<T> {
+    fn new(value: T, ttl: u64, now: u64) -> Self {
+        Self {
+            expires_at: now + ttl * 1000,
+            value,
+        }
+    }
+
+    /// The cached value, unless it has expired by `now`
+    fn into_value(self, now: u64) -> Option<T> {
+        if is_expired(self.expires_at, now) {
+            return None;
+        }
+        Some(self.value)
+    }
+}
+
+impl CacheStore {
+    pub fn new(kv: Option<KvStore>) -> Self {
+        Self { kv }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.kv.is_some()
+    }
+
+    pub async fn get<T: DeserializeOwned>(&self, key: &str) -> Option<T> {
+        let kv = self.kv.as_ref()?;
+        let entry = match kv.get(key).json::<CacheEntry<T>>().await {
+            Ok(entry) => entry?,
+            Err(e) => {
+                error!("Unable to read cache entry {}: {:?}", key, e);
+                return None;
+            }
+        };
+        // KV expiration is not precise, so the expiry time is checked here as well
+        entry.into_value(Date::now().as_millis())
+    }
+
+    /// Caches the value for `ttl` seconds
+    pub async fn put<T: Serialize>(&self, key: &str, value: T, ttl: u64) {
+        let kv = match self.kv.as_ref() {
+            Some(kv) => kv,
+            None => return,
+        };
+        let entry = CacheEntry::new(value, ttl, Date::now().as_millis());
+        let result = match kv.put(key, entry) {
+            Ok(builder) => {
+                builder
+                    .expiration_ttl(ttl.max(MIN_KV_EXPIRATION_TTL))
+                    .execute()
+                    .await
+            }
+            Err(e) => Err(e),
+        };
+        if let Err(e) = result {
+            error!("Unable to write cache entry {}: {:?}", key, e);
+        }
+    }
+
+    pub async fn delete(&self, key: &str) {
+        if let Some(kv) = self.kv.as_ref() {
+            if let Err(e) = kv.delete(key).await {
+                error!("Unable to delete cache entry {}: {:?}", key, e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn expiry_is_inclusive() {
+        assert!(!is_expired(1000, 999));
+        assert!(is_expired(1000, 1000));
+        assert!(is_expired(1000, 1001));
+    }
+
+    #[test]
+    fn entries_hit_until_they_expire() {
+        let entry = CacheEntry::new("value", 60, 1_000);
+        assert_eq!(entry.clone().into_value(1_000), Some("value"));
+        assert_eq!(entry.clone().into_value(60_999), Some("value"));
+        assert_eq!(entry.into_value(61_000), None);
+    }
+
+    #[test]
+    fn entries_survive_the_round_trip() {
+        let entry = CacheEntry::new(vec![1u64, 2], 60, 0);
+        let entry: CacheEntry<Vec<u64>> =
+            serde_json::from_str(&serde_json::to_string(&entry).unwrap()).unwrap();
+        assert_eq!(entry.into_value(0), Some(vec![1, 2]));
+    }
+
+    #[test]
+    fn unconfigured_cache_always_misses() {
+        let cache = CacheStore::new(None);
+        assert!(!cache.is_enabled());
+        futures::executor::block_on(async {
+            cache.put("key", 1u64, 60).await;
+            assert_eq!(cache.get::<u64>("key").await, None);
+        });
+    }
+}
@@ -1,16 +1,34 @@
-use chrono::{DateTime, Datelike, NaiveDateTime, TimeZone};
+use bincode::Options;
+use chrono::format::{Item, StrftimeItems};
+use chrono::{DateTime, Datelike, NaiveDate, NaiveDateTime, TimeZone, Timelike};
 use chrono_tz::Tz;
-use log::info;
+use lazy_static::lazy_static;
+use log::{error, info};
 use new_string_template::template::Template;
+use regex::Regex;
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
 use telegram_types::bot::methods::{ChatTarget, Method, TelegramResult};
-use telegram_types::bot::types::{ChatId, ChatType};
+use telegram_types::bot::types::{ChatId, ChatType, MessageId, UserId};
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthStr;
 use worker::kv::KvStore;
 use worker::{Date, Error as WorkerError, Method as RequestMethod};
 
-use super::bot::Bot;
+use super::bot::{sleep, Bot};
+use super::i18n::{translate, Language};
 
-use std::collections::HashMap;
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::{self, Write};
+
+const MAX_RECENT_ERRORS: usize = 5;
+const MAX_HISTORY_ENTRIES: usize = 10;
+pub const SCHEDULED_JOB: &str = "scheduled";
+pub const SCHEDULED_SOURCE: &str = "scheduled";
+// Telegram accepts chat titles of 1 to 128 characters
+pub const MAX_TITLE_LENGTH: usize = 128;
+pub const MAX_DESCRIPTION_LENGTH: usize = 255;
 
 const SET_CHAT_TITLE_FAILED: TelegramResult<bool> = TelegramResult {
     ok: false,
@@ -20,18 +38,178 @@ const SET_CHAT_TITLE_FAILED: TelegramResult<bool> = TelegramResult {
     parameters: None,
 };
 
-macro_rules! add_specifier {
-    ($hashmap:ident, $datetime:ident, $specifier:expr) => {
-        $hashmap.insert(
-            $specifier,
-            $datetime.format(&format!("%{}", $specifier)).to_string(),
-        );
-    };
-    ($hashmap:ident, $datetime:ident, $($specifier:expr),+) => {
-        $(
-            add_specifier!($hashmap, $datetime, $specifier);
-        )+
-    };
+pub const DEFAULT_DELIMITER: &str = " | ";
+const GROUP_KEY_PREFIX: &str = "group-";
+// Bump it along with a new step in `migrate` when the meaning of stored fields changes. Versions 0
+// to 2 are the bincode layouts, 3 is the first JSON one.
+pub const CURRENT_SCHEMA_VERSION: u32 = 3;
+pub const DEFAULT_FAILURE_MESSAGE: &str = "发生什么事了？未能成功更改群标题，请检查 bot 帐号权限";
+// Limit of the text of a message
+pub const MAX_FAILURE_MESSAGE_LENGTH: usize = 4096;
+const FIRST_STRONG_ISOLATE: char = '\u{2068}';
+const POP_DIRECTIONAL_ISOLATE: char = '\u{2069}';
+
+const STRFTIME_PREFIX: &str = "strftime:";
+const COUNTER_PREFIX: &str = "counter:";
+pub const MAX_COUNTERS: usize = 20;
+pub const MAX_NAMED_TEMPLATES: usize = 20;
+pub const MAX_ROTATION_ENTRIES: usize = 20;
+pub const MAX_CONSECUTIVE_FAILURES: u32 = 5;
+const MAX_TITLE_ATTEMPTS: u32 = 3;
+// Longer waits would eat up the time budget of the scheduled job
+const MAX_RETRY_AFTER: i32 = 5;
+const RETRY_BASE_DELAY: u64 = 500;
+pub const MAX_UPDATE_INTERVAL: u32 = 7 * 24 * 60;
+// Scheduled runs don't start at the exact same second every minute
+const UPDATE_INTERVAL_SLACK: u64 = 30_000;
+const MAX_NAME_LENGTH: usize = 32;
+const PROGRESS_BAR_KEY: &str = "progress_bar";
+const MEMBER_COUNT_KEY: &str = "member_count";
+// Milliseconds a fetched member count is shown before it's fetched again
+const MEMBER_COUNT_TTL: u64 = 10 * 60 * 1000;
+const PROGRESS_BAR_FILLED: char = '▓';
+const PROGRESS_BAR_EMPTY: char = '░';
+const DEFAULT_PROGRESS_BAR_WIDTH: usize = 8;
+const MAX_PROGRESS_BAR_WIDTH: usize = 32;
+const SECONDS_PER_DAY: u32 = 86400;
+// Seconds from now the template is test rendered at: now, in an hour, tomorrow and next month
+const VALIDATION_OFFSETS: [u64; 4] = [0, 3600, 86400, 31 * 86400];
+
+/// strftime specifiers available as placeholders
+const DATE_SPECIFIERS: &[&str] = &[
+    "Y", "C", "y", "m", "b", "B", "h", "d", "e", "a", "A", "w", "u", "U", "W", "G", "g", "V", "j",
+    "D", "x", "F", "v",
+];
+const TIME_SPECIFIERS: &[&str] = &[
+    "H", "k", "I", "l", "P", "p", "M", "S", "R", "T", "X", "r", "Z", "z", ":z",
+];
+/// strftime specifiers of little use in titles, only rendered if the template refers to them so
+/// older templates keep working. Titles are rendered from whole seconds, so `f` is always 0.
+const ON_DEMAND_SPECIFIERS: &[&str] = &["f", "c", "+", "s"];
+
+/// Custom placeholders rendering integers, which are affected by the number format of the group
+const NUMERIC_KEYS: &[&str] = &[
+    "yeshu",
+    "week_of_month",
+    "week_of_year",
+    "days_until",
+    "days_since",
+    "member_count",
+];
+
+const ZH_NAMES: LocaleNames = LocaleNames {
+    months: [
+        "一月",
+        "二月",
+        "三月",
+        "四月",
+        "五月",
+        "六月",
+        "七月",
+        "八月",
+        "九月",
+        "十月",
+        "十一月",
+        "十二月",
+    ],
+    short_months: [
+        "1月", "2月", "3月", "4月", "5月", "6月", "7月", "8月", "9月", "10月", "11月", "12月",
+    ],
+    weekdays: [
+        "星期日",
+        "星期一",
+        "星期二",
+        "星期三",
+        "星期四",
+        "星期五",
+        "星期六",
+    ],
+    short_weekdays: ["周日", "周一", "周二", "周三", "周四", "周五", "周六"],
+};
+const JA_NAMES: LocaleNames = LocaleNames {
+    months: [
+        "1月", "2月", "3月", "4月", "5月", "6月", "7月", "8月", "9月", "10月", "11月", "12月",
+    ],
+    short_months: [
+        "1月", "2月", "3月", "4月", "5月", "6月", "7月", "8月", "9月", "10月", "11月", "12月",
+    ],
+    weekdays: [
+        "日曜日",
+        "月曜日",
+        "火曜日",
+        "水曜日",
+        "木曜日",
+        "金曜日",
+        "土曜日",
+    ],
+    short_weekdays: ["日", "月", "火", "水", "木", "金", "土"],
+};
+const FR_NAMES: LocaleNames = LocaleNames {
+    months: [
+        "janvier",
+        "février",
+        "mars",
+        "avril",
+        "mai",
+        "juin",
+        "juillet",
+        "août",
+        "septembre",
+        "octobre",
+        "novembre",
+        "décembre",
+    ],
+    short_months: [
+        "janv.", "févr.", "mars", "avr.", "mai", "juin", "juil.", "août", "sept.", "oct.", "nov.",
+        "déc.",
+    ],
+    weekdays: [
+        "dimanche", "lundi", "mardi", "mercredi", "jeudi", "vendredi", "samedi",
+    ],
+    short_weekdays: ["dim.", "lun.", "mar.", "mer.", "jeu.", "ven.", "sam."],
+};
+// Specifiers rendering month or weekday names
+const LOCALIZED_KEYS: &[&str] = &["b", "B", "h", "a", "A"];
+
+const CJK_DIGITS: [&str; 10] = ["零", "一", "二", "三", "四", "五", "六", "七", "八", "九"];
+const CJK_UNITS: [&str; 4] = ["", "十", "百", "千"];
+const CJK_GROUP_UNITS: [&str; 5] = ["", "万", "亿", "兆", "京"];
+
+/// Lunar years from 1900 to 2100. Bits 15 to 4 tell whether the first to the twelfth month has
+/// 30 days instead of 29, bits 3 to 0 which month is followed by a leap month, if any, and bit 16
+/// whether the leap month has 30 days.
+const LUNAR_INFO: [u32; 201] = [
+    0x04bd8, 0x04ae0, 0x0a570, 0x054d5, 0x0d260, 0x0d950, 0x16554, 0x056a0, 0x09ad0, 0x055d2,
+    0x04ae0, 0x0a5b6, 0x0a4d0, 0x0d250, 0x1d255, 0x0b540, 0x0d6a0, 0x0ada2, 0x095b0, 0x14977,
+    0x04970, 0x0a4b0, 0x0b4b5, 0x06a50, 0x06d40, 0x1ab54, 0x02b60, 0x09570, 0x052f2, 0x04970,
+    0x06566, 0x0d4a0, 0x0ea50, 0x16a95, 0x05ad0, 0x02b60, 0x186e3, 0x092e0, 0x1c8d7, 0x0c950,
+    0x0d4a0, 0x1d8a6, 0x0b550, 0x056a0, 0x1a5b4, 0x025d0, 0x092d0, 0x0d2b2, 0x0a950, 0x0b557,
+    0x06ca0, 0x0b550, 0x15355, 0x04da0, 0x0a5b0, 0x14573, 0x052b0, 0x0a9a8, 0x0e950, 0x06aa0,
+    0x0aea6, 0x0ab50, 0x04b60, 0x0aae4, 0x0a570, 0x05260, 0x0f263, 0x0d950, 0x05b57, 0x056a0,
+    0x096d0, 0x04dd5, 0x04ad0, 0x0a4d0, 0x0d4d4, 0x0d250, 0x0d558, 0x0b540, 0x0b6a0, 0x195a6,
+    0x095b0, 0x049b0, 0x0a974, 0x0a4b0, 0x0b27a, 0x06a50, 0x06d40, 0x0af46, 0x0ab60, 0x09570,
+    0x04af5, 0x04970, 0x064b0, 0x074a3, 0x0ea50, 0x06b58, 0x05ac0, 0x0ab60, 0x096d5, 0x092e0,
+    0x0c960, 0x0d954, 0x0d4a0, 0x0da50, 0x07552, 0x056a0, 0x0abb7, 0x025d0, 0x092d0, 0x0cab5,
+    0x0a950, 0x0b4a0, 0x0baa4, 0x0ad50, 0x055d9, 0x04ba0, 0x0a5b0, 0x15176, 0x052b0, 0x0a930,
+    0x07954, 0x06aa0, 0x0ad50, 0x05b52, 0x04b60, 0x0a6e6, 0x0a4e0, 0x0d260, 0x0ea65, 0x0d530,
+    0x05aa0, 0x076a3, 0x096d0, 0x04afb, 0x04ad0, 0x0a4d0, 0x1d0b6, 0x0d250, 0x0d520, 0x0dd45,
+    0x0b5a0, 0x056d0, 0x055b2, 0x049b0, 0x0a577, 0x0a4b0, 0x0aa50, 0x1b255, 0x06d20, 0x0ada0,
+    0x14b63, 0x09370, 0x049f8, 0x04970, 0x064b0, 0x168a6, 0x0ea50, 0x06b20, 0x1a6c4, 0x0aae0,
+    0x092e0, 0x0d2e3, 0x0c960, 0x0d557, 0x0d4a0, 0x0da50, 0x05d55, 0x056a0, 0x0a6d0, 0x055d4,
+    0x052d0, 0x0a9b8, 0x0a950, 0x0b4a0, 0x0b6a6, 0x0ad50, 0x055a0, 0x0aba4, 0x0a5b0, 0x052b0,
+    0x0b273, 0x06930, 0x07337, 0x06aa0, 0x0ad50, 0x14b55, 0x04b60, 0x0a570, 0x054e4, 0x0d160,
+    0x0e968, 0x0d520, 0x0daa0, 0x16aa6, 0x056d0, 0x04ae0, 0x0a9d4, 0x0a2d0, 0x0d150, 0x0f252,
+    0x0d520,
+];
+const LUNAR_FIRST_YEAR: i32 = 1900;
+const LUNAR_MONTH_NAMES: [&str; 12] = [
+    "正", "二", "三", "四", "五", "六", "七", "八", "九", "十", "十一", "十二",
+];
+
+lazy_static! {
+    // Unlike the default pattern of `new_string_template`, placeholders can't contain braces, so
+    // adjacent placeholders like `{Y}年{m}月` are not merged into a single one.
+    static ref PLACEHOLDER_REGEX: Regex = Regex::new(r"\{\s*([^\s{}]+)\s*\}").unwrap();
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
@@ -40,20 +218,272 @@ pub struct SetChatTitle<'a> {
     pub title: &'a str,
 }
 
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct SetChatDescription<'a> {
+    pub chat_id: ChatTarget<'a>,
+    pub description: &'a str,
+}
+
 #[derive(Clone, Debug)]
 pub struct TemplateContext<'a> {
     inner: HashMap<&'a str, String>,
 }
 
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub enum NumberFormat {
+    /// `1234`
+    Plain,
+    /// `1,234`
+    Grouped,
+    /// `一千二百三十四`
+    Cjk,
+}
+
+/// Language of month and weekday names rendered in titles
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub enum TitleLocale {
+    /// `January`, `Sunday`, as chrono renders them
+    En,
+    /// `一月`, `星期日`
+    Zh,
+    /// `1月`, `日曜日`
+    Ja,
+    /// `janvier`, `dimanche`
+    Fr,
+}
+
+/// First day of the week when counting weeks of the year
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub enum WeekStart {
+    /// The week containing January 1st is week 1
+    Sunday,
+    /// ISO 8601, week 1 is the one containing the first Thursday of the year
+    #[default]
+    Monday,
+}
+
+struct LocaleNames {
+    months: [&'static str; 12],
+    short_months: [&'static str; 12],
+    /// Starting from Sunday
+    weekdays: [&'static str; 7],
+    short_weekdays: [&'static str; 7],
+}
+
+/// What to do when the rendered title is longer than `MAX_TITLE_LENGTH`
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub enum LengthPolicy {
+    /// Refuse to apply the title
+    Error,
+    /// Cut the title down to the limit
+    Truncate,
+    /// Keep the current title until the template renders to something shorter
+    Skip,
+}
+
+/// The title that would be applied after the length policy
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum FinalTitle {
+    Apply(String),
+    /// Carries the rendered title that was too long
+    Skip(String),
+}
+
+/// What happens to the cursor of a job iterating over all groups after a run
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum CursorUpdate {
+    /// The run stopped before this group
+    Save(String),
+    /// The run went through the rest of the groups
+    Clear,
+    /// The run went through every group without a cursor
+    Keep,
+}
+
+/// Request the scheduled job needs to make for the rendered title
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ScheduledUpdate {
+    Apply,
+    /// It's shown already, no request is needed
+    Unchanged,
+    /// It's shown already, but the live title has to be checked for manual renames
+    CheckDrift,
+}
+
+/// How a placeholder of the template is resolved
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Reference {
+    BuiltIn,
+    Counter,
+    /// Neither built-in nor defined by the group, e.g. a counter that was never set
+    Dangling,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct ApiErrorRecord {
+    pub error_code: i32,
+    pub description: String,
+    pub timestamp: u64,
+}
+
+/// A change to the template or the title, and what caused it
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct HistoryEntry {
+    pub timestamp: u64,
+    /// Command that made the change, or `SCHEDULED_SOURCE`
+    pub source: String,
+    /// `@username` or the user ID, if a user made the change
+    pub user: Option<String>,
+    /// The new template or title
+    pub value: String,
+}
+
+/// A template change scheduled for a future date
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct PendingTemplate {
+    /// Days since 0001-01-01 (CE) in the group's timezone
+    pub date: i32,
+    pub title_segment: Vec<String>,
+}
+
+/// Settings of a group that can be carried over to another deployment, state like the last
+/// applied title or the error log is left out
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct GroupConfig {
+    pub enable: bool,
+    pub title_segment: Vec<String>,
+    pub delimiter: String,
+    pub delimiters: Vec<String>,
+    pub timezone: String,
+    pub require_admin: bool,
+    pub editors: Vec<UserId>,
+    pub enforce: bool,
+    pub number_format: NumberFormat,
+    pub locale: TitleLocale,
+    /// Missing from exports made before it was added
+    #[serde(default)]
+    pub week_start: WeekStart,
+    pub length_policy: LengthPolicy,
+    pub bidi: bool,
+    pub dedupe_segments: bool,
+    pub pending_templates: Vec<PendingTemplate>,
+    pub named_templates: HashMap<String, Vec<String>>,
+    pub counters: BTreeMap<String, i64>,
+    pub freeze: Option<String>,
+    pub countdown: Option<i32>,
+    pub update_interval: u32,
+    pub active_hours: Option<(u8, u8)>,
+    pub auto_reenable_on_promotion: bool,
+    pub failure_message: Option<String>,
+    pub announcement_template: Option<String>,
+    pub announce: bool,
+    pub description_template: Option<String>,
+    pub language: Language,
+    #[serde(default)]
+    pub fallback_title: Option<String>,
+    #[serde(default)]
+    pub rotation: Vec<String>,
+    #[serde(default)]
+    pub rotate: bool,
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Group {
     pub enable: bool,
     pub id: ChatId,
     pub title_segment: Vec<String>,
     pub delimiter: String,
+    pub delimiters: Vec<String>,
     pub last_title: String,
     pub timezone: String,
     pub require_admin: bool,
+    pub notify_user: Option<UserId>,
+    pub enforce: bool,
+    pub recent_errors: Vec<ApiErrorRecord>,
+    pub number_format: NumberFormat,
+    pub pending_templates: Vec<PendingTemplate>,
+    pub pending_apply: bool,
+    pub auto_reenable_on_promotion: bool,
+    /// Whether automation was turned off because the bot lost its rights, rather than by `/disable`
+    pub disabled_by_permission: bool,
+    /// Wraps each segment in a bidi isolate so mixed-direction titles keep their order
+    pub bidi: bool,
+    pub length_policy: LengthPolicy,
+    pub history: Vec<HistoryEntry>,
+    /// Sent when the title can't be changed, `DEFAULT_FAILURE_MESSAGE` is used if it's not set
+    pub failure_message: Option<String>,
+    /// Group whose template is rendered instead of `title_segment`
+    pub template_source: Option<ChatId>,
+    /// Template of `template_source`, filled in by `DataStore::load_group`
+    #[serde(skip)]
+    pub source_segment: Option<Vec<String>>,
+    /// Applied instead of the rendered template while it's set
+    pub freeze: Option<String>,
+    /// Collapses consecutive identical segments when joining the template
+    pub dedupe_segments: bool,
+    /// Language of month and weekday names in the title
+    pub locale: TitleLocale,
+    /// Named counters for `{counter:<name>}`
+    pub counters: BTreeMap<String, i64>,
+    /// Message sent and pinned after the title changed, rendered like the title
+    pub announcement_template: Option<String>,
+    /// Whether `announcement_template` is sent, independent from `enable`
+    pub announce: bool,
+    /// The announcement pinned last, unpinned once the next one is pinned
+    pub pinned_announcement: Option<MessageId>,
+    /// Saved templates to switch to, by name
+    pub named_templates: HashMap<String, Vec<String>>,
+    /// Scheduled runs in a row that failed to change the title, reset by a successful one
+    pub consecutive_failures: u32,
+    /// Target of `{days_until}` and `{days_since}`, in days since 0001-01-01 (CE)
+    pub countdown: Option<i32>,
+    /// Title before the last one applied, restored by `/undo`
+    pub previous_title: Option<String>,
+    /// Minutes between scheduled updates, 0 to update on every run
+    pub update_interval: u32,
+    /// When the bot last applied a title, in milliseconds
+    pub last_updated: u64,
+    /// Title of the group when the bot first saw it, restored by `/reset`
+    pub original_title: String,
+    /// Template of the group description, applied along with the title
+    pub description_template: Option<String>,
+    /// Description applied last by the bot
+    pub last_description: String,
+    /// Language of the replies in this group
+    pub language: Language,
+    /// Non-admins allowed to change the settings while `require_admin` is on
+    pub editors: Vec<UserId>,
+    /// Local hours the scheduled job may update the title in, from the start up to the end
+    pub active_hours: Option<(u8, u8)>,
+    /// Layout version of the record, upgraded to `CURRENT_SCHEMA_VERSION` by `migrate`
+    #[serde(default)]
+    pub schema_version: u32,
+    /// First day of the week for `{week_of_year}`
+    #[serde(default)]
+    pub week_start: WeekStart,
+    /// Applied as is by scheduled runs when the template fails to render
+    #[serde(default)]
+    pub fallback_title: Option<String>,
+    /// Whether the group was told the scheduled job can't rename it, re-armed by the next rename
+    #[serde(default)]
+    pub failure_notified: bool,
+    /// Titles applied in turn by scheduled runs while `rotate` is on
+    #[serde(default)]
+    pub rotation: Vec<String>,
+    /// Entry of `rotation` currently applied
+    #[serde(default)]
+    pub rotation_index: u32,
+    /// Whether scheduled runs cycle through `rotation` instead of rendering the template
+    #[serde(default)]
+    pub rotate: bool,
+    /// Member count shown by `{member_count}` and when it was fetched, in milliseconds
+    #[serde(default)]
+    pub member_count: Option<(u64, u64)>,
+    /// Title written ahead of a rename that wasn't confirmed yet, settled by
+    /// `reconcile_pending_title`
+    #[serde(default)]
+    pub pending_title: Option<String>,
+    // New fields go here with `#[serde(default)]`
 }
 
 #[derive(Clone)]
@@ -61,216 +491,3776 @@ pub struct DataStore<'a> {
     kv: &'a KvStore,
 }
 
-pub fn get_group_title(chat: &ChatType) -> Option<&str> {
+/// Title of group-like chats. Chats of types unknown to `telegram_types` keep it only if they're
+/// read with `parse_telegram_json`.
+pub fn get_group_title(chat: &ChatType) -> Option<Cow<'_, str>> {
     match chat {
-        ChatType::Group { title, .. } => Some(title),
-        ChatType::Supergroup { title, .. } => Some(title),
-        ChatType::Channel { title, .. } => Some(title),
-        _ => None,
+        ChatType::Group { title, .. } => Some(Cow::from(title)),
+        ChatType::Supergroup { title, .. } => Some(Cow::from(title)),
+        ChatType::Channel { title, .. } => Some(Cow::from(title)),
+        ChatType::Private { .. } => None,
+        _ => {
+            info!("Unhandled chat type: {:?}", chat);
+            None
+        }
     }
 }
 
-pub fn get_raw_chat_id(chat_id: &ChatId) -> i64 {
-    let ChatId(raw_id) = *chat_id;
-    raw_id
+pub fn get_placeholders(template: &str) -> Vec<&str> {
+    PLACEHOLDER_REGEX
+        .captures_iter(template)
+        .filter_map(|captures| captures.get(1))
+        .map(|key| key.as_str())
+        .collect()
 }
 
-impl<'a> Method for SetChatTitle<'a> {
-    const NAME: &'static str = "setChatTitle";
-    type Item = bool;
+/// Splits a placeholder like `Y+1` into the key, the operator and the operand. Only `+`, `-`
+/// and `*` with a non-negative integer operand are supported.
+pub fn parse_arithmetic(key: &str) -> Option<(&str, char, i64)> {
+    let position = key.rfind(['+', '-', '*'])?;
+    let (base, operand) = (&key[..position], &key[position + 1..]);
+    if base.is_empty() || operand.is_empty() || !operand.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    Some((base, key[position..].chars().next()?, operand.parse().ok()?))
 }
 
-impl<'a> TemplateContext<'a> {
-    pub fn generate(datetime: DateTime<Tz>) -> Self {
-        let mut ret = HashMap::new();
-        add_specifier!(
-            ret, datetime, "Y", "C", "y", "m", "b", "B", "h", "d", "e", "a", "A", "w", "u", "U",
-            "W", "G", "g", "V", "j", "D", "x", "F", "v", "H", "k", "I", "l", "P", "p", "M", "S",
-            "f", "R", "T", "X", "r", "Z", "z", ":z", "c", "+", "s"
-        );
-        ret.insert("yeshu", (datetime.date().year() - 1988).to_string());
-        Self { inner: ret }
+pub fn format_strftime(datetime: &DateTime<Tz>, format: &str) -> Result<String, WorkerError> {
+    let items = StrftimeItems::new(format).collect::<Vec<Item>>();
+    if items.contains(&Item::Error) {
+        return Err(WorkerError::RustError(format!(
+            "Invalid strftime format: {}",
+            format
+        )));
     }
+    let mut ret = String::new();
+    write!(ret, "{}", datetime.format_with_items(items.into_iter()))
+        .map_err(|_| WorkerError::RustError(format!("Unable to format time with {}", format)))?;
+    Ok(ret)
 }
 
-impl<'a> From<TemplateContext<'a>> for HashMap<&'a str, String> {
-    fn from(context: TemplateContext<'a>) -> HashMap<&'a str, String> {
-        context.inner
+/// Renders `fraction` (clamped to `0.0..=1.0`) as a bar of `width` blocks
+pub fn progress_bar(fraction: f64, width: usize) -> String {
+    let filled = (fraction.clamp(0.0, 1.0) * width as f64).round() as usize;
+    let mut ret = String::new();
+    ret.extend(std::iter::repeat_n(PROGRESS_BAR_FILLED, filled));
+    ret.extend(std::iter::repeat_n(PROGRESS_BAR_EMPTY, width - filled));
+    ret
+}
+
+/// Fraction of the local day that has passed
+pub fn day_progress(datetime: &DateTime<Tz>) -> f64 {
+    datetime.num_seconds_from_midnight() as f64 / SECONDS_PER_DAY as f64
+}
+
+/// Fraction of the local year that has passed
+pub fn year_progress(datetime: &DateTime<Tz>) -> f64 {
+    let year = datetime.year();
+    let days_in_year = if NaiveDate::from_ymd_opt(year, 2, 29).is_some() {
+        366
+    } else {
+        365
+    };
+    (datetime.ordinal0() as f64 + day_progress(datetime)) / days_in_year as f64
+}
+
+/// Renders `{progress_bar}` with its arguments (e.g. `:year:12`), the period defaults to the day
+fn format_progress_bar(datetime: &DateTime<Tz>, arguments: &str) -> Result<String, WorkerError> {
+    let mut fraction = day_progress(datetime);
+    let mut width = DEFAULT_PROGRESS_BAR_WIDTH;
+    for argument in arguments.split(':').filter(|argument| !argument.is_empty()) {
+        match argument {
+            "day" => fraction = day_progress(datetime),
+            "year" => fraction = year_progress(datetime),
+            _ => {
+                width = argument
+                    .parse()
+                    .ok()
+                    .filter(|width| (1..=MAX_PROGRESS_BAR_WIDTH).contains(width))
+                    .ok_or_else(|| {
+                        WorkerError::RustError(format!(
+                            "Invalid progress bar argument: {}",
+                            argument
+                        ))
+                    })?;
+            }
+        }
     }
+    Ok(progress_bar(fraction, width))
 }
 
-impl Group {
-    pub fn new(chat_id: &ChatId, chat_type: &ChatType) -> Self {
-        let title = get_group_title(chat_type);
-        let title_str = title.unwrap().to_string();
-        Self {
-            enable: false,
-            id: *chat_id,
-            title_segment: vec![title_str.clone()],
-            delimiter: " | ".to_string(),
-            last_title: title_str,
-            timezone: Tz::UTC.to_string(),
-            require_admin: true,
+/// Date in the Chinese lunar calendar
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct LunarDate {
+    pub year: i32,
+    pub month: u32,
+    pub day: u32,
+    /// Whether it's in the leap month following `month`
+    pub leap: bool,
+}
+
+impl LunarDate {
+    /// `正`, `二`, ..., `十二`, prefixed with `闰` in leap months
+    pub fn month_name(&self) -> String {
+        let name = LUNAR_MONTH_NAMES[self.month as usize - 1];
+        if self.leap {
+            format!("闰{}", name)
+        } else {
+            name.to_string()
         }
     }
 
-    pub fn push_title_template<S: AsRef<str>>(&mut self, new_segment: S) {
-        self.title_segment.push(new_segment.as_ref().to_string());
+    /// `初一`, ..., `初十`, `十一`, ..., `二十`, `廿一`, ..., `三十`
+    pub fn day_name(&self) -> String {
+        let ones = CJK_DIGITS[(self.day % 10) as usize];
+        match self.day {
+            10 => "初十".to_string(),
+            20 => "二十".to_string(),
+            30 => "三十".to_string(),
+            day if day < 10 => format!("初{}", ones),
+            day if day < 20 => format!("十{}", ones),
+            _ => format!("廿{}", ones),
+        }
     }
+}
 
-    pub fn push_front_title_template<S: AsRef<str>>(&mut self, new_segment: S) {
-        self.title_segment
-            .insert(0, new_segment.as_ref().to_string());
+fn lunar_month_days(info: u32, month: u32) -> i64 {
+    if info & (0x10000 >> month) != 0 {
+        30
+    } else {
+        29
     }
+}
 
-    pub fn pop_title_template(&mut self) {
-        if self.title_segment.len() > 1 {
-            self.title_segment.pop();
+fn lunar_leap_month_days(info: u32) -> i64 {
+    match (info & 0xf, info & 0x10000) {
+        (0, _) => 0,
+        (_, 0) => 29,
+        _ => 30,
+    }
+}
+
+/// Converts the date to the lunar calendar, `None` if it's out of the years covered by the table
+pub fn to_lunar(date: NaiveDate) -> Option<LunarDate> {
+    // Lunar new year of 1900
+    let mut offset = (date - NaiveDate::from_ymd(1900, 1, 31)).num_days();
+    if offset < 0 {
+        return None;
+    }
+    for (index, info) in LUNAR_INFO.iter().enumerate() {
+        let year = LUNAR_FIRST_YEAR + index as i32;
+        let leap_month = info & 0xf;
+        for month in 1..=12 {
+            let days = lunar_month_days(*info, month);
+            if offset < days {
+                let day = offset as u32 + 1;
+                return Some(LunarDate {
+                    year,
+                    month,
+                    day,
+                    leap: false,
+                });
+            }
+            offset -= days;
+            if month == leap_month {
+                let days = lunar_leap_month_days(*info);
+                if offset < days {
+                    let day = offset as u32 + 1;
+                    return Some(LunarDate {
+                        year,
+                        month,
+                        day,
+                        leap: true,
+                    });
+                }
+                offset -= days;
+            }
         }
     }
+    None
+}
 
-    pub fn pop_front_title_template(&mut self) {
-        if self.title_segment.len() > 1 {
-            self.title_segment.drain(0..1);
+/// Counts weeks from the first day of the month regardless of the weekday, i.e. days 1-7 are in
+/// week 1, days 8-14 in week 2, and days 29-31 in week 5
+pub fn week_of_month(day: u32) -> u32 {
+    (day - 1) / 7 + 1
+}
+
+/// Week of the year the date falls in. ISO weeks around New Year may belong to the previous or
+/// the next year, e.g. 2021-01-01 is in week 53.
+pub fn week_of_year(date: NaiveDate, week_start: WeekStart) -> u32 {
+    match week_start {
+        WeekStart::Monday => date.iso_week().week(),
+        WeekStart::Sunday => {
+            let new_year = NaiveDate::from_yo(date.year(), 1);
+            (date.ordinal0() + new_year.weekday().num_days_from_sunday()) / 7 + 1
         }
     }
+}
 
-    pub fn get_time(&self, time: NaiveDateTime) -> DateTime<Tz> {
-        let tz: Tz = self.timezone.parse().unwrap_or(Tz::UTC);
-        DateTime::from_utc(time, tz.offset_from_utc_datetime(&time))
+/// Wraps the text in a first strong isolate, so its direction doesn't leak into the delimiters
+pub fn isolate_bidi(text: &str) -> String {
+    format!(
+        "{}{}{}",
+        FIRST_STRONG_ISOLATE, text, POP_DIRECTIONAL_ISOLATE
+    )
+}
+
+/// Length of the title as Telegram counts it, in UTF-16 code units
+pub fn title_length(title: &str) -> usize {
+    title.encode_utf16().count()
+}
+
+/// Width of the text in columns, East Asian wide and fullwidth characters take two of them.
+///
+/// Each grapheme cluster takes at most two columns, so emoji sequences count like a single emoji.
+pub fn display_width(text: &str) -> usize {
+    text.graphemes(true)
+        .map(|grapheme| grapheme.width().min(2))
+        .sum()
+}
+
+/// Cuts the title down to `MAX_TITLE_LENGTH` on grapheme cluster boundaries, so emoji sequences
+/// like flags and ZWJ families are either kept whole or dropped
+pub fn truncate_title(title: &str) -> String {
+    let mut length = 0;
+    let mut ret = String::new();
+    for grapheme in title.graphemes(true) {
+        length += title_length(grapheme);
+        if length > MAX_TITLE_LENGTH {
+            break;
+        }
+        ret.push_str(grapheme);
     }
+    ret
+}
 
-    pub fn get_last_title(&self) -> &str {
-        &self.last_title
+pub fn check_description_length(description: &str) -> Result<(), WorkerError> {
+    if title_length(description) > MAX_DESCRIPTION_LENGTH {
+        return Err(WorkerError::RustError(
+            "Invalid description length".to_string(),
+        ));
     }
+    Ok(())
+}
 
-    pub fn join_title_template(&self) -> String {
-        self.title_segment.join(&format!("{}", self.delimiter))
+pub fn check_title_length(title: &str) -> Result<(), WorkerError> {
+    if !(1..=MAX_TITLE_LENGTH).contains(&title_length(title)) {
+        return Err(WorkerError::RustError("Invalid title length".to_string()));
     }
+    Ok(())
+}
 
-    pub fn clear_title_template(&mut self) {
-        self.title_segment.clear();
+fn to_cjk_group(value: u64) -> String {
+    let digits = [value / 1000, value / 100 % 10, value / 10 % 10, value % 10];
+    let mut ret = String::new();
+    let mut pending_zero = false;
+    for (i, digit) in digits.iter().enumerate() {
+        if *digit == 0 {
+            pending_zero = !ret.is_empty();
+            continue;
+        }
+        if pending_zero {
+            ret.push_str(CJK_DIGITS[0]);
+            pending_zero = false;
+        }
+        ret.push_str(CJK_DIGITS[*digit as usize]);
+        ret.push_str(CJK_UNITS[3 - i]);
     }
+    ret
+}
 
-    pub fn get_new_title<S: AsRef<str>>(
-        &self,
-        context: &HashMap<&str, S>,
-    ) -> Result<String, WorkerError> {
-        let template = Template::new(self.join_title_template());
-        template
-            .render(context)
-            .map_err(|e| WorkerError::RustError(e.to_string()))
+/// Converts an integer to Chinese numerals, e.g. `10203` to `一万零二百零三`
+pub fn to_cjk_numeral(value: i64) -> String {
+    if value == 0 {
+        return CJK_DIGITS[0].to_string();
+    }
+    let mut remaining = value.unsigned_abs();
+    let mut groups = Vec::new();
+    while remaining > 0 {
+        groups.push(remaining % 10000);
+        remaining /= 10000;
+    }
+    let mut ret = String::new();
+    let mut pending_zero = false;
+    for (i, group) in groups.iter().enumerate().rev() {
+        if *group == 0 {
+            pending_zero = !ret.is_empty();
+            continue;
+        }
+        if !ret.is_empty() && (pending_zero || *group < 1000) {
+            ret.push_str(CJK_DIGITS[0]);
+        }
+        pending_zero = false;
+        ret.push_str(&to_cjk_group(*group));
+        ret.push_str(CJK_GROUP_UNITS[i]);
+    }
+    // `十二` rather than `一十二`
+    if ret.starts_with("一十") {
+        ret = ret.replacen("一十", "十", 1);
     }
+    if value < 0 {
+        ret.insert(0, '负');
+    }
+    ret
+}
 
-    pub async fn update_title<S: AsRef<str>>(
-        &self,
-        bot: &Bot<'_>,
-        title: S,
-    ) -> Result<bool, WorkerError> {
-        let set_chat_title = SetChatTitle {
-            chat_id: ChatTarget::Id(self.id),
-            title: title.as_ref(),
-        };
-        let response = bot
-            .send_json_request(set_chat_title, RequestMethod::Post)
-            .await;
-        match response {
-            Ok(mut res) => Ok(res
-                .json::<TelegramResult<bool>>()
-                .await
-                .unwrap_or(SET_CHAT_TITLE_FAILED)
-                .ok),
-            Err(e) => Err(e),
+pub fn to_grouped_numeral(value: i64) -> String {
+    let digits = value.unsigned_abs().to_string();
+    let mut ret = String::new();
+    for (i, digit) in digits.chars().enumerate() {
+        if i > 0 && (digits.len() - i).is_multiple_of(3) {
+            ret.push(',');
         }
+        ret.push(digit);
+    }
+    if value < 0 {
+        ret.insert(0, '-');
     }
+    ret
+}
 
-    pub async fn apply_template(
-        &mut self,
-        bot: &Bot<'_>,
-        date: &Date,
-    ) -> Result<bool, WorkerError> {
-        let naive_date = NaiveDateTime::from_timestamp((date.as_millis() / 1000) as i64, 0);
-        info!("Got naive time: {}", naive_date);
-        let local_time = self.get_time(naive_date);
-        info!("Local time: {}", local_time);
-        let context = TemplateContext::generate(local_time);
-        info!("Generated context: {:?}", context);
-        let new_title = self.get_new_title(&HashMap::from(context))?;
-        let title_template_length = new_title.len();
-        if !(1..=255).contains(&title_template_length) {
-            return Err(WorkerError::RustError("Invalid title length".to_string()));
+/// Converts the UTC time from the clock of the worker to the local time of the timezone
+pub fn to_local_time(utc_time: NaiveDateTime, tz: Tz) -> DateTime<Tz> {
+    tz.from_utc_datetime(&utc_time)
+}
+
+/// Renders a template against the given local time
+#[allow(clippy::too_many_arguments)]
+pub fn render_template_at(
+    template: &str,
+    local_time: DateTime<Tz>,
+    number_format: NumberFormat,
+    locale: TitleLocale,
+    week_start: WeekStart,
+    counters: &BTreeMap<String, i64>,
+    countdown: Option<NaiveDate>,
+    member_count: Option<u64>,
+) -> Result<String, WorkerError> {
+    let mut context = TemplateContext::generate(local_time);
+    if let Some(target) = countdown {
+        context.resolve_countdown(&local_time, target);
+    }
+    if let Some(count) = member_count {
+        context.inner.insert(MEMBER_COUNT_KEY, count.to_string());
+    }
+    context.apply_week_start(week_start, &local_time);
+    context.resolve_placeholders(template, &local_time, locale)?;
+    context.resolve_counters(template, counters, number_format);
+    context.resolve_arithmetic(template, counters, number_format)?;
+    context.apply_locale(locale, &local_time);
+    context.apply_number_format(number_format);
+    info!("Generated context: {:?}", context);
+    Template::new(template)
+        .with_regex(&PLACEHOLDER_REGEX)
+        .render(&HashMap::from(context))
+        .map_err(|e| WorkerError::RustError(e.to_string()))
+}
+
+/// Tells for each distinct placeholder of the template how it would be resolved, without
+/// rendering it
+pub fn check_references<'a>(
+    template: &'a str,
+    counters: &BTreeMap<String, i64>,
+    countdown: Option<NaiveDate>,
+) -> Vec<(&'a str, Reference)> {
+    let mut context = TemplateContext::generate(Tz::UTC.timestamp(0, 0));
+    if let Some(target) = countdown {
+        context.resolve_countdown(&Tz::UTC.timestamp(0, 0), target);
+    }
+    let built_in = context.inner;
+    let mut references: Vec<(&str, Reference)> = Vec::new();
+    for placeholder in get_placeholders(template) {
+        if references.iter().any(|(seen, _)| *seen == placeholder) {
+            continue;
         }
-        info!("Applying title: {}", new_title);
-        self.update_title(bot, &new_title).await?;
-        self.last_title = new_title;
-        Ok(true)
+        // `{Y+1}` refers to `Y`
+        let key = match parse_arithmetic(placeholder) {
+            Some((base, _, _))
+                if !placeholder.starts_with(STRFTIME_PREFIX)
+                    && !built_in.contains_key(placeholder) =>
+            {
+                base
+            }
+            _ => placeholder,
+        };
+        let reference = if let Some(name) = key.strip_prefix(COUNTER_PREFIX) {
+            if counters.contains_key(name) {
+                Reference::Counter
+            } else {
+                Reference::Dangling
+            }
+        } else if built_in.contains_key(key)
+            || key == MEMBER_COUNT_KEY
+            || ON_DEMAND_SPECIFIERS.contains(&key)
+            || key.starts_with(STRFTIME_PREFIX)
+            || key == PROGRESS_BAR_KEY
+            || key.starts_with(&format!("{}:", PROGRESS_BAR_KEY))
+        {
+            Reference::BuiltIn
+        } else {
+            Reference::Dangling
+        };
+        references.push((placeholder, reference));
     }
+    references
 }
 
-impl<'a> DataStore<'a> {
-    pub fn new(kv: &'a KvStore) -> Self {
-        Self { kv }
+/// A group record in the layout it was saved with
+enum StoredGroup {
+    V0(LegacyGroupV0),
+    V1(LegacyGroupV1),
+    V2(Box<LegacyGroupV2>),
+    /// Any JSON record, its version is kept in `schema_version`
+    Json(Box<Group>),
+}
+
+/// Brings a record saved by an older version up to `CURRENT_SCHEMA_VERSION`, one version at a
+/// time. Returns whether it changed and should be saved again.
+fn migrate(mut stored: StoredGroup) -> (Group, bool) {
+    let mut steps = 0;
+    loop {
+        stored = match stored {
+            StoredGroup::V0(legacy) => StoredGroup::V1(legacy.into()),
+            StoredGroup::V1(legacy) => StoredGroup::V2(Box::new(legacy.into())),
+            StoredGroup::V2(legacy) => StoredGroup::Json(Box::new((*legacy).into())),
+            StoredGroup::Json(mut group) => {
+                // JSON records saved before the bincode layouts were counted carry 0 or 1, they
+                // have every field of version 3 already
+                if group.schema_version < CURRENT_SCHEMA_VERSION {
+                    group.schema_version = CURRENT_SCHEMA_VERSION;
+                    steps += 1;
+                }
+                if steps > 0 {
+                    info!(
+                        "Migrated group {} to schema version {} in {} steps",
+                        get_raw_chat_id(&group.id),
+                        CURRENT_SCHEMA_VERSION,
+                        steps
+                    );
+                }
+                return (*group, steps > 0);
+            }
+        };
+        steps += 1;
     }
+}
 
-    pub async fn get_group_keys(&self) -> Result<Vec<String>, WorkerError> {
-        let list_result = self
-            .kv
-            .list()
-            .prefix("group-".to_string())
-            .execute()
-            .await?;
-        let mut ret: Vec<String> = list_result
-            .keys
-            .into_iter()
-            .map(|k| k.name[6..].to_string())
-            .collect();
-        let mut cursor = list_result.cursor;
-        let mut complete = list_result.list_complete;
-        while (!complete) && cursor.is_some() {
-            let list_result = self
-                .kv
-                .list()
-                .prefix("group-".to_string())
-                .cursor(cursor.unwrap())
-                .execute()
-                .await?;
-            let mut new_result: Vec<String> = list_result
-                .keys
-                .into_iter()
-                .map(|k| k.name[6..].to_string())
-                .collect();
-            ret.append(&mut new_result);
-            cursor = list_result.cursor;
-            complete = list_result.list_complete;
+/// Reads a group record, returns whether it was migrated and should be rewritten
+fn deserialize_group(data: &[u8]) -> Result<(Group, bool), WorkerError> {
+    decode_group(data).map(migrate)
+}
+
+fn decode_group(data: &[u8]) -> Result<StoredGroup, WorkerError> {
+    // Bincode records start with `enable`, a 0 or 1 byte, so they can't be mistaken for JSON
+    if data.first() == Some(&b'{') {
+        return serde_json::from_slice(data)
+            .map(|group| StoredGroup::Json(Box::new(group)))
+            .map_err(|e| WorkerError::RustError(e.to_string()));
+    }
+    if let Ok(legacy) = decode_exact::<LegacyGroupV0>(data) {
+        return Ok(StoredGroup::V0(legacy));
+    }
+    if let Ok(legacy) = decode_exact::<LegacyGroupV1>(data) {
+        return Ok(StoredGroup::V1(legacy));
+    }
+    LegacyGroupV2::decode(data)
+        .map(|legacy| StoredGroup::V2(Box::new(legacy)))
+        .map_err(|e| WorkerError::RustError(e.to_string()))
+}
+
+/// Reads the record stored under the key of `id`. A record under the wrong key would apply
+/// another group's settings, so it's treated as corrupt.
+fn deserialize_group_for(id: &ChatId, data: &[u8]) -> Result<(Group, bool), WorkerError> {
+    let (group, migrated) = deserialize_group(data)?;
+    if group.id != *id {
+        return Err(WorkerError::RustError(format!(
+            "Group {} is stored with the mismatched ID {}",
+            get_raw_chat_id(id),
+            get_raw_chat_id(&group.id)
+        )));
+    }
+    Ok((group, migrated))
+}
+
+/// Bincode layout of the records saved before `Group` gained any field
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct LegacyGroupV0 {
+    enable: bool,
+    id: ChatId,
+    title_segment: Vec<String>,
+    delimiter: String,
+    last_title: String,
+    timezone: String,
+    require_admin: bool,
+}
+
+impl From<LegacyGroupV0> for LegacyGroupV1 {
+    fn from(legacy: LegacyGroupV0) -> Self {
+        LegacyGroupV1 {
+            enable: legacy.enable,
+            id: legacy.id,
+            title_segment: legacy.title_segment,
+            delimiter: legacy.delimiter,
+            last_title: legacy.last_title,
+            timezone: legacy.timezone,
+            require_admin: legacy.require_admin,
+            notify_user: None,
         }
-        Ok(ret)
     }
+}
 
-    pub async fn load_group(&self, id: &ChatId) -> Result<Group, WorkerError> {
-        let raw_id = get_raw_chat_id(id);
-        let key = format!("group-{}", raw_id);
-        let data =
-            self.kv.get(&key).bytes().await?.ok_or_else(|| {
-                WorkerError::RustError("Group info not found in KvStore".to_string())
-            })?;
-        bincode::deserialize(&data).map_err(|e| WorkerError::RustError(e.to_string()))
+/// Bincode layout once `/subscribe` appended `notify_user` to the original fields, before
+/// `delimiters` was inserted in the middle
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct LegacyGroupV1 {
+    enable: bool,
+    id: ChatId,
+    title_segment: Vec<String>,
+    delimiter: String,
+    last_title: String,
+    timezone: String,
+    require_admin: bool,
+    notify_user: Option<UserId>,
+}
+
+impl From<LegacyGroupV1> for LegacyGroupV2 {
+    fn from(legacy: LegacyGroupV1) -> Self {
+        LegacyGroupV2 {
+            enable: legacy.enable,
+            id: legacy.id,
+            title_segment: legacy.title_segment,
+            delimiter: legacy.delimiter,
+            delimiters: Vec::new(),
+            last_title: legacy.last_title,
+            timezone: legacy.timezone,
+            require_admin: legacy.require_admin,
+            notify_user: legacy.notify_user,
+            enforce: false,
+            recent_errors: Vec::new(),
+            number_format: NumberFormat::Plain,
+            pending_templates: Vec::new(),
+            pending_apply: false,
+            auto_reenable_on_promotion: false,
+            disabled_by_permission: false,
+            bidi: false,
+            length_policy: LengthPolicy::Error,
+            history: Vec::new(),
+            failure_message: None,
+            template_source: None,
+            freeze: None,
+            dedupe_segments: false,
+            locale: TitleLocale::En,
+            counters: BTreeMap::new(),
+            announcement_template: None,
+            announce: false,
+            pinned_announcement: None,
+            named_templates: HashMap::new(),
+            consecutive_failures: 0,
+            countdown: None,
+            previous_title: None,
+            update_interval: 0,
+            last_updated: 0,
+            // Unknown, the title had been changed by the bot already
+            original_title: String::new(),
+            description_template: None,
+            last_description: String::new(),
+            language: Language::default(),
+            editors: Vec::new(),
+            active_hours: None,
+        }
     }
+}
 
-    pub async fn load_group_or_create(&self, id: &ChatId, chat_type: &ChatType) -> Group {
-        let stored_group = self.load_group(id).await;
-        if let Ok(group) = stored_group {
-            group
-        } else {
-            let new_group = Group::new(id, chat_type);
-            self.save_group(&new_group).await.ok();
-            new_group
+/// Bincode layout once `delimiters` was inserted after `delimiter`, with the fields appended
+/// since up to the move to JSON. Records saved in between end after the fields known then.
+#[derive(Clone, Debug, Serialize)]
+struct LegacyGroupV2 {
+    enable: bool,
+    id: ChatId,
+    title_segment: Vec<String>,
+    delimiter: String,
+    delimiters: Vec<String>,
+    last_title: String,
+    timezone: String,
+    require_admin: bool,
+    notify_user: Option<UserId>,
+    enforce: bool,
+    recent_errors: Vec<ApiErrorRecord>,
+    number_format: NumberFormat,
+    pending_templates: Vec<PendingTemplate>,
+    pending_apply: bool,
+    auto_reenable_on_promotion: bool,
+    disabled_by_permission: bool,
+    bidi: bool,
+    length_policy: LengthPolicy,
+    history: Vec<HistoryEntry>,
+    failure_message: Option<String>,
+    template_source: Option<ChatId>,
+    freeze: Option<String>,
+    dedupe_segments: bool,
+    locale: TitleLocale,
+    counters: BTreeMap<String, i64>,
+    announcement_template: Option<String>,
+    announce: bool,
+    pinned_announcement: Option<MessageId>,
+    named_templates: HashMap<String, Vec<String>>,
+    consecutive_failures: u32,
+    countdown: Option<i32>,
+    previous_title: Option<String>,
+    update_interval: u32,
+    last_updated: u64,
+    original_title: String,
+    description_template: Option<String>,
+    last_description: String,
+    language: Language,
+    editors: Vec<UserId>,
+    active_hours: Option<(u8, u8)>,
+}
+
+impl LegacyGroupV2 {
+    /// Reads the fields the layout had when `delimiters` was inserted, then the ones appended
+    /// since as far as the record goes
+    fn decode(data: &[u8]) -> Result<Self, bincode::Error> {
+        let mut fields = LegacyFields { data };
+        let enable = fields.read()?;
+        let id = fields.read()?;
+        let title_segment = fields.read()?;
+        let delimiter = fields.read()?;
+        let delimiters = fields.read()?;
+        let last_title = fields.read()?;
+        let timezone = fields.read()?;
+        let require_admin = fields.read()?;
+        let notify_user = fields.read()?;
+        let mut legacy = LegacyGroupV2::from(LegacyGroupV1 {
+            enable,
+            id,
+            title_segment,
+            delimiter,
+            last_title,
+            timezone,
+            require_admin,
+            notify_user,
+        });
+        legacy.delimiters = delimiters;
+        fields.read_appended(&mut legacy.enforce)?;
+        fields.read_appended(&mut legacy.recent_errors)?;
+        fields.read_appended(&mut legacy.number_format)?;
+        fields.read_appended(&mut legacy.pending_templates)?;
+        fields.read_appended(&mut legacy.pending_apply)?;
+        fields.read_appended(&mut legacy.auto_reenable_on_promotion)?;
+        fields.read_appended(&mut legacy.disabled_by_permission)?;
+        fields.read_appended(&mut legacy.bidi)?;
+        fields.read_appended(&mut legacy.length_policy)?;
+        fields.read_appended(&mut legacy.history)?;
+        fields.read_appended(&mut legacy.failure_message)?;
+        fields.read_appended(&mut legacy.template_source)?;
+        fields.read_appended(&mut legacy.freeze)?;
+        fields.read_appended(&mut legacy.dedupe_segments)?;
+        fields.read_appended(&mut legacy.locale)?;
+        fields.read_appended(&mut legacy.counters)?;
+        fields.read_appended(&mut legacy.announcement_template)?;
+        fields.read_appended(&mut legacy.announce)?;
+        fields.read_appended(&mut legacy.pinned_announcement)?;
+        fields.read_appended(&mut legacy.named_templates)?;
+        fields.read_appended(&mut legacy.consecutive_failures)?;
+        fields.read_appended(&mut legacy.countdown)?;
+        fields.read_appended(&mut legacy.previous_title)?;
+        fields.read_appended(&mut legacy.update_interval)?;
+        fields.read_appended(&mut legacy.last_updated)?;
+        fields.read_appended(&mut legacy.original_title)?;
+        fields.read_appended(&mut legacy.description_template)?;
+        fields.read_appended(&mut legacy.last_description)?;
+        fields.read_appended(&mut legacy.language)?;
+        fields.read_appended(&mut legacy.editors)?;
+        fields.read_appended(&mut legacy.active_hours)?;
+        fields.finish()?;
+        Ok(legacy)
+    }
+}
+
+impl From<LegacyGroupV2> for Group {
+    fn from(legacy: LegacyGroupV2) -> Self {
+        let mut group = Group::with_title(&legacy.id, &legacy.last_title);
+        group.enable = legacy.enable;
+        group.title_segment = legacy.title_segment;
+        group.delimiter = legacy.delimiter;
+        group.delimiters = legacy.delimiters;
+        group.timezone = legacy.timezone;
+        group.require_admin = legacy.require_admin;
+        group.notify_user = legacy.notify_user;
+        group.enforce = legacy.enforce;
+        group.recent_errors = legacy.recent_errors;
+        group.number_format = legacy.number_format;
+        group.pending_templates = legacy.pending_templates;
+        group.pending_apply = legacy.pending_apply;
+        group.auto_reenable_on_promotion = legacy.auto_reenable_on_promotion;
+        group.disabled_by_permission = legacy.disabled_by_permission;
+        group.bidi = legacy.bidi;
+        group.length_policy = legacy.length_policy;
+        group.history = legacy.history;
+        group.failure_message = legacy.failure_message;
+        group.template_source = legacy.template_source;
+        group.freeze = legacy.freeze;
+        group.dedupe_segments = legacy.dedupe_segments;
+        group.locale = legacy.locale;
+        group.counters = legacy.counters;
+        group.announcement_template = legacy.announcement_template;
+        group.announce = legacy.announce;
+        group.pinned_announcement = legacy.pinned_announcement;
+        group.named_templates = legacy.named_templates;
+        group.consecutive_failures = legacy.consecutive_failures;
+        group.countdown = legacy.countdown;
+        group.previous_title = legacy.previous_title;
+        group.update_interval = legacy.update_interval;
+        group.last_updated = legacy.last_updated;
+        group.original_title = legacy.original_title;
+        group.description_template = legacy.description_template;
+        group.last_description = legacy.last_description;
+        group.language = legacy.language;
+        group.editors = legacy.editors;
+        group.active_hours = legacy.active_hours;
+        group
+    }
+}
+
+/// Reads the fields of a bincode record one by one, bincode has no notion of missing fields
+struct LegacyFields<'a> {
+    data: &'a [u8],
+}
+
+impl LegacyFields<'_> {
+    fn read<T: Serialize + DeserializeOwned>(&mut self) -> Result<T, bincode::Error> {
+        let value = bincode::deserialize(self.data)?;
+        let size = bincode::serialized_size(&value)? as usize;
+        self.data = &self.data[size..];
+        Ok(value)
+    }
+
+    /// Reads a field appended to the layout, the field is left as is if the record ended before
+    fn read_appended<T: Serialize + DeserializeOwned>(
+        &mut self,
+        field: &mut T,
+    ) -> Result<(), bincode::Error> {
+        if !self.data.is_empty() {
+            *field = self.read()?;
         }
+        Ok(())
     }
 
-    pub async fn save_group(&self, group: &Group) -> Result<(), WorkerError> {
-        let raw_id = get_raw_chat_id(&group.id);
-        let key = format!("group-{}", raw_id);
-        let data = bincode::serialize(&group).map_err(|e| WorkerError::RustError(e.to_string()))?;
-        Ok(self.kv.put_bytes(&key, &data)?.execute().await?)
+    /// Fails if the record goes on after the last field of the layout
+    fn finish(self) -> Result<(), bincode::Error> {
+        if self.data.is_empty() {
+            return Ok(());
+        }
+        Err(Box::new(bincode::ErrorKind::Custom(format!(
+            "{} bytes left after the last field",
+            self.data.len()
+        ))))
+    }
+}
+
+/// Reads a record in a fixed bincode layout, failing unless it's exactly that long
+fn decode_exact<T: DeserializeOwned>(data: &[u8]) -> Result<T, bincode::Error> {
+    bincode::options()
+        .with_fixint_encoding()
+        .reject_trailing_bytes()
+        .deserialize(data)
+}
+
+/// Every job iterating over all groups keeps its own cursor
+fn cursor_key(job: &str) -> String {
+    format!("cursor-{}", job)
+}
+
+/// Names of counters and templates are short and made of ASCII letters, digits and underscores
+pub fn is_valid_name(name: &str) -> bool {
+    (1..=MAX_NAME_LENGTH).contains(&name.len())
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// How long to wait before trying again after Telegram refused a request, `None` if the error
+/// isn't transient, it would take too long or there were enough attempts already
+pub fn retry_delay<T>(result: &TelegramResult<T>, attempt: u32) -> Option<u64> {
+    if attempt >= MAX_TITLE_ATTEMPTS {
+        return None;
+    }
+    match result.error_code {
+        Some(429) => {
+            let retry_after = result
+                .parameters
+                .as_ref()
+                .and_then(|parameters| parameters.retry_after)
+                .unwrap_or(1);
+            (0..=MAX_RETRY_AFTER)
+                .contains(&retry_after)
+                .then(|| retry_after as u64 * 1000)
+        }
+        Some(code) if code >= 500 => Some(RETRY_BASE_DELAY << (attempt - 1)),
+        _ => None,
+    }
+}
+
+/// Strips the prefix from KV key names, dropping and logging the ones that aren't a group ID
+pub fn parse_group_keys<I: IntoIterator<Item = String>>(names: I) -> Vec<String> {
+    names
+        .into_iter()
+        .filter_map(|name| match name.strip_prefix(GROUP_KEY_PREFIX) {
+            Some(id) if id.parse::<i64>().is_ok() => Some(id.to_string()),
+            _ => {
+                error!("Ignoring stray key {} in the group namespace", name);
+                None
+            }
+        })
+        .collect()
+}
+
+/// Orders the group keys so the ones from `cursor` onwards come first, then the rest
+pub fn resume_from_cursor(mut keys: Vec<String>, cursor: Option<&str>) -> Vec<String> {
+    keys.sort();
+    if let Some(cursor) = cursor {
+        let start = keys.partition_point(|key| key.as_str() < cursor);
+        keys.rotate_left(start);
+    }
+    keys
+}
+
+/// The cursor of a job once a run is over. It points at the first group the run didn't get
+/// to, and is cleared once a resumed run went through the rest.
+pub fn next_cursor(stopped_at: Option<&str>, resuming: bool) -> CursorUpdate {
+    match stopped_at {
+        Some(group_name) => CursorUpdate::Save(group_name.to_string()),
+        None if resuming => CursorUpdate::Clear,
+        None => CursorUpdate::Keep,
+    }
+}
+
+pub fn get_raw_chat_id(chat_id: &ChatId) -> i64 {
+    let ChatId(raw_id) = *chat_id;
+    raw_id
+}
+
+impl<'a> Method for SetChatTitle<'a> {
+    const NAME: &'static str = "setChatTitle";
+    type Item = bool;
+}
+
+impl<'a> Method for SetChatDescription<'a> {
+    const NAME: &'static str = "setChatDescription";
+    type Item = bool;
+}
+
+impl NumberFormat {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name.trim().to_ascii_lowercase().as_str() {
+            "plain" => Some(Self::Plain),
+            "grouped" => Some(Self::Grouped),
+            "cjk" => Some(Self::Cjk),
+            _ => None,
+        }
+    }
+
+    pub fn format(&self, value: i64) -> String {
+        match self {
+            Self::Plain => value.to_string(),
+            Self::Grouped => to_grouped_numeral(value),
+            Self::Cjk => to_cjk_numeral(value),
+        }
+    }
+}
+
+impl fmt::Display for NumberFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Plain => write!(f, "plain"),
+            Self::Grouped => write!(f, "grouped"),
+            Self::Cjk => write!(f, "cjk"),
+        }
+    }
+}
+
+impl LengthPolicy {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name.trim().to_ascii_lowercase().as_str() {
+            "error" => Some(Self::Error),
+            "truncate" => Some(Self::Truncate),
+            "skip" => Some(Self::Skip),
+            _ => None,
+        }
+    }
+
+    /// Applies the policy to a rendered title
+    pub fn finalize(&self, title: String) -> Result<FinalTitle, WorkerError> {
+        if title_length(&title) > MAX_TITLE_LENGTH {
+            match self {
+                Self::Error => {}
+                Self::Truncate => return Ok(FinalTitle::Apply(truncate_title(&title))),
+                Self::Skip => return Ok(FinalTitle::Skip(title)),
+            }
+        }
+        check_title_length(&title)?;
+        Ok(FinalTitle::Apply(title))
+    }
+}
+
+impl TitleLocale {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name.trim().to_ascii_lowercase().as_str() {
+            "en" => Some(Self::En),
+            "zh" => Some(Self::Zh),
+            "ja" => Some(Self::Ja),
+            "fr" => Some(Self::Fr),
+            _ => None,
+        }
+    }
+
+    fn names(&self) -> Option<&'static LocaleNames> {
+        match self {
+            Self::En => None,
+            Self::Zh => Some(&ZH_NAMES),
+            Self::Ja => Some(&JA_NAMES),
+            Self::Fr => Some(&FR_NAMES),
+        }
+    }
+
+    /// Renders a name specifier (e.g. `B` for the month) in this locale, `None` if chrono's own
+    /// rendering should be kept
+    pub fn localize_specifier(&self, datetime: &DateTime<Tz>, specifier: &str) -> Option<String> {
+        let names = self.names()?;
+        let month = datetime.month0() as usize;
+        let weekday = datetime.weekday().num_days_from_sunday() as usize;
+        let name = match specifier {
+            "B" => names.months[month],
+            "b" | "h" => names.short_months[month],
+            "A" => names.weekdays[weekday],
+            "a" => names.short_weekdays[weekday],
+            _ => return None,
+        };
+        Some(name.to_string())
+    }
+
+    /// Replaces the name specifiers in a strftime format with the names in this locale
+    pub fn localize_format(&self, datetime: &DateTime<Tz>, format: &str) -> String {
+        let mut ret = String::new();
+        let mut chars = format.chars();
+        while let Some(c) = chars.next() {
+            if c != '%' {
+                ret.push(c);
+                continue;
+            }
+            match chars.next() {
+                Some(specifier) => {
+                    match self.localize_specifier(datetime, &specifier.to_string()) {
+                        Some(name) => ret.push_str(&name),
+                        // Including `%%`, so the character after it isn't taken as a specifier
+                        None => {
+                            ret.push('%');
+                            ret.push(specifier);
+                        }
+                    }
+                }
+                None => ret.push('%'),
+            }
+        }
+        ret
+    }
+}
+
+impl fmt::Display for TitleLocale {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::En => write!(f, "en"),
+            Self::Zh => write!(f, "zh"),
+            Self::Ja => write!(f, "ja"),
+            Self::Fr => write!(f, "fr"),
+        }
+    }
+}
+
+impl WeekStart {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name.trim().to_ascii_lowercase().as_str() {
+            "sun" | "sunday" => Some(Self::Sunday),
+            "mon" | "monday" => Some(Self::Monday),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for WeekStart {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Sunday => write!(f, "sun"),
+            Self::Monday => write!(f, "mon"),
+        }
+    }
+}
+
+impl fmt::Display for LengthPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Error => write!(f, "error"),
+            Self::Truncate => write!(f, "truncate"),
+            Self::Skip => write!(f, "skip"),
+        }
+    }
+}
+
+impl<'a> TemplateContext<'a> {
+    pub fn generate(datetime: DateTime<Tz>) -> Self {
+        let mut ret = HashMap::new();
+        for specifier in DATE_SPECIFIERS.iter().chain(TIME_SPECIFIERS) {
+            // Formatting an unsupported specifier would panic, so a chrono upgrade that drops
+            // one only loses that placeholder
+            match format_strftime(&datetime, &format!("%{}", specifier)) {
+                Ok(value) => {
+                    ret.insert(*specifier, value);
+                }
+                Err(e) => error!("Unable to format specifier {}: {}", specifier, e),
+            }
+        }
+        ret.insert("yeshu", (datetime.date().year() - 1988).to_string());
+        ret.insert("week_of_month", week_of_month(datetime.day()).to_string());
+        ret.insert(
+            "week_of_year",
+            week_of_year(datetime.date().naive_local(), WeekStart::default()).to_string(),
+        );
+        if let Some(lunar) = to_lunar(datetime.date().naive_local()) {
+            ret.insert("lunar_month", lunar.month_name());
+            ret.insert("lunar_day", lunar.day_name());
+        }
+        Self { inner: ret }
+    }
+
+    /// Splits the placeholders into strftime date specifiers, strftime time specifiers and the
+    /// custom ones, with their values
+    pub fn grouped(&self) -> [Vec<(&'a str, &str)>; 3] {
+        let entries = |keys: &[&'a str]| {
+            keys.iter()
+                .filter_map(|key| Some((*key, self.inner.get(key)?.as_str())))
+                .collect::<Vec<(&'a str, &str)>>()
+        };
+        let mut custom = self
+            .inner
+            .iter()
+            .filter(|(key, _)| !DATE_SPECIFIERS.contains(key) && !TIME_SPECIFIERS.contains(key))
+            .map(|(key, value)| (*key, value.as_str()))
+            .collect::<Vec<(&'a str, &str)>>();
+        custom.sort_unstable();
+        [entries(DATE_SPECIFIERS), entries(TIME_SPECIFIERS), custom]
+    }
+
+    /// Resolves the placeholders in the template which carry their own arguments
+    pub fn resolve_placeholders(
+        &mut self,
+        template: &'a str,
+        datetime: &DateTime<Tz>,
+        locale: TitleLocale,
+    ) -> Result<(), WorkerError> {
+        for key in get_placeholders(template) {
+            if let Some(format) = key.strip_prefix(STRFTIME_PREFIX) {
+                let format = locale.localize_format(datetime, format);
+                self.inner.insert(key, format_strftime(datetime, &format)?);
+            } else if ON_DEMAND_SPECIFIERS.contains(&key) {
+                let format = locale.localize_format(datetime, &format!("%{}", key));
+                self.inner.insert(key, format_strftime(datetime, &format)?);
+            } else if let Some(arguments) = key.strip_prefix(PROGRESS_BAR_KEY) {
+                if arguments.is_empty() || arguments.starts_with(':') {
+                    self.inner
+                        .insert(key, format_progress_bar(datetime, arguments)?);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Counts days between the local date and `target`, so it changes at local midnight. Either
+    /// key is negative when the target is on the other side.
+    pub fn resolve_countdown(&mut self, datetime: &DateTime<Tz>, target: NaiveDate) {
+        let days_until = (target - datetime.date().naive_local()).num_days();
+        self.inner.insert("days_until", days_until.to_string());
+        self.inner.insert("days_since", (-days_until).to_string());
+    }
+
+    pub fn apply_week_start(&mut self, week_start: WeekStart, datetime: &DateTime<Tz>) {
+        let week = week_of_year(datetime.date().naive_local(), week_start);
+        self.inner.insert("week_of_year", week.to_string());
+    }
+
+    /// Resolves `{counter:<name>}`, counters that aren't defined are rendered as 0
+    pub fn resolve_counters(
+        &mut self,
+        template: &'a str,
+        counters: &BTreeMap<String, i64>,
+        format: NumberFormat,
+    ) {
+        for key in get_placeholders(template) {
+            if let Some(name) = key.strip_prefix(COUNTER_PREFIX) {
+                // `{counter:<name>+1}` is left to `resolve_arithmetic`
+                if parse_arithmetic(key).is_some() {
+                    continue;
+                }
+                let value = counters.get(name).copied().unwrap_or_default();
+                self.inner.insert(key, format.format(value));
+            }
+        }
+    }
+
+    /// Resolves `{<key>+<n>}`, `{<key>-<n>}` and `{<key>*<n>}` for keys with integer values, e.g.
+    /// `{Y+1}`. Has to run before the number format is applied, so the values can be parsed.
+    pub fn resolve_arithmetic(
+        &mut self,
+        template: &'a str,
+        counters: &BTreeMap<String, i64>,
+        format: NumberFormat,
+    ) -> Result<(), WorkerError> {
+        for key in get_placeholders(template) {
+            if self.inner.contains_key(key) {
+                continue;
+            }
+            let (base, operator, operand) = match parse_arithmetic(key) {
+                Some(expression) => expression,
+                None => continue,
+            };
+            let value = match base.strip_prefix(COUNTER_PREFIX) {
+                Some(name) => counters.get(name).copied().unwrap_or_default(),
+                None => match self.inner.get(base) {
+                    Some(value) => value.trim().parse::<i64>().map_err(|_| {
+                        WorkerError::RustError(format!("Placeholder {} is not a number", base))
+                    })?,
+                    // Unknown keys are reported when the template is rendered
+                    None => continue,
+                },
+            };
+            let result = match operator {
+                '+' => value.checked_add(operand),
+                '-' => value.checked_sub(operand),
+                _ => value.checked_mul(operand),
+            }
+            .ok_or_else(|| WorkerError::RustError(format!("Overflow in {{{}}}", key)))?;
+            let result = if base.starts_with(COUNTER_PREFIX) || NUMERIC_KEYS.contains(&base) {
+                format.format(result)
+            } else {
+                result.to_string()
+            };
+            self.inner.insert(key, result);
+        }
+        Ok(())
+    }
+
+    pub fn apply_locale(&mut self, locale: TitleLocale, datetime: &DateTime<Tz>) {
+        for key in LOCALIZED_KEYS {
+            if let Some(name) = locale.localize_specifier(datetime, key) {
+                self.inner.insert(key, name);
+            }
+        }
+    }
+
+    pub fn apply_number_format(&mut self, format: NumberFormat) {
+        for key in NUMERIC_KEYS {
+            if let Some(value) = self.inner.get_mut(key) {
+                if let Ok(number) = value.parse::<i64>() {
+                    *value = format.format(number);
+                }
+            }
+        }
+    }
+}
+
+impl<'a> From<TemplateContext<'a>> for HashMap<&'a str, String> {
+    fn from(context: TemplateContext<'a>) -> HashMap<&'a str, String> {
+        context.inner
+    }
+}
+
+impl Group {
+    pub fn new(chat_id: &ChatId, chat_type: &ChatType) -> Self {
+        let title = get_group_title(chat_type);
+        Self::with_title(chat_id, &title.unwrap())
+    }
+
+    /// A group with the default settings, using the title as its template
+    fn with_title(chat_id: &ChatId, title: &str) -> Self {
+        let title_str = title.to_string();
+        Self {
+            enable: false,
+            id: *chat_id,
+            title_segment: vec![title_str.clone()],
+            delimiter: DEFAULT_DELIMITER.to_string(),
+            delimiters: Vec::new(),
+            last_title: title_str.clone(),
+            timezone: Tz::UTC.to_string(),
+            require_admin: true,
+            notify_user: None,
+            enforce: false,
+            recent_errors: Vec::new(),
+            number_format: NumberFormat::Plain,
+            pending_templates: Vec::new(),
+            pending_apply: false,
+            auto_reenable_on_promotion: false,
+            disabled_by_permission: false,
+            bidi: false,
+            length_policy: LengthPolicy::Error,
+            history: Vec::new(),
+            failure_message: None,
+            template_source: None,
+            source_segment: None,
+            freeze: None,
+            dedupe_segments: false,
+            locale: TitleLocale::En,
+            counters: BTreeMap::new(),
+            announcement_template: None,
+            announce: false,
+            pinned_announcement: None,
+            named_templates: HashMap::new(),
+            consecutive_failures: 0,
+            countdown: None,
+            previous_title: None,
+            update_interval: 0,
+            last_updated: 0,
+            original_title: title_str,
+            description_template: None,
+            last_description: String::new(),
+            language: Language::default(),
+            editors: Vec::new(),
+            active_hours: None,
+            schema_version: CURRENT_SCHEMA_VERSION,
+            week_start: WeekStart::default(),
+            fallback_title: None,
+            failure_notified: false,
+            rotation: Vec::new(),
+            rotation_index: 0,
+            rotate: false,
+            member_count: None,
+            pending_title: None,
+        }
+    }
+
+    /// Whether the segment is the same as the one it would be pushed next to
+    pub fn duplicates_adjacent(&self, new_segment: &str, front: bool) -> bool {
+        let adjacent = if front {
+            self.title_segment.first()
+        } else {
+            self.title_segment.last()
+        };
+        adjacent.is_some_and(|segment| segment == new_segment)
+    }
+
+    pub fn push_title_template<S: AsRef<str>>(&mut self, new_segment: S) {
+        self.title_segment.push(new_segment.as_ref().to_string());
+    }
+
+    pub fn push_front_title_template<S: AsRef<str>>(&mut self, new_segment: S) {
+        self.title_segment
+            .insert(0, new_segment.as_ref().to_string());
+    }
+
+    pub fn pop_title_template(&mut self) {
+        if self.title_segment.len() > 1 {
+            self.title_segment.pop();
+        }
+    }
+
+    pub fn pop_front_title_template(&mut self) {
+        if self.title_segment.len() > 1 {
+            self.title_segment.drain(0..1);
+        }
+    }
+
+    /// Moves a segment to another position, both 1-based. Returns false if either is out of range.
+    pub fn move_title_segment(&mut self, from: usize, to: usize) -> bool {
+        let len = self.title_segment.len();
+        if from == 0 || to == 0 || from > len || to > len {
+            return false;
+        }
+        let segment = self.title_segment.remove(from - 1);
+        self.title_segment.insert(to - 1, segment);
+        true
+    }
+
+    /// Replaces the segment at a 1-based position. Returns false if it's out of range.
+    pub fn edit_title_segment<S: AsRef<str>>(&mut self, index: usize, new_segment: S) -> bool {
+        match index
+            .checked_sub(1)
+            .and_then(|index| self.title_segment.get_mut(index))
+        {
+            Some(segment) => {
+                *segment = new_segment.as_ref().to_string();
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn export_config(&self) -> GroupConfig {
+        GroupConfig {
+            enable: self.enable,
+            title_segment: self.title_segment.clone(),
+            delimiter: self.delimiter.clone(),
+            delimiters: self.delimiters.clone(),
+            timezone: self.timezone.clone(),
+            require_admin: self.require_admin,
+            editors: self.editors.clone(),
+            enforce: self.enforce,
+            number_format: self.number_format,
+            locale: self.locale,
+            week_start: self.week_start,
+            length_policy: self.length_policy,
+            bidi: self.bidi,
+            dedupe_segments: self.dedupe_segments,
+            pending_templates: self.pending_templates.clone(),
+            named_templates: self.named_templates.clone(),
+            counters: self.counters.clone(),
+            freeze: self.freeze.clone(),
+            countdown: self.countdown,
+            update_interval: self.update_interval,
+            active_hours: self.active_hours,
+            auto_reenable_on_promotion: self.auto_reenable_on_promotion,
+            failure_message: self.failure_message.clone(),
+            announcement_template: self.announcement_template.clone(),
+            announce: self.announce,
+            description_template: self.description_template.clone(),
+            language: self.language,
+            fallback_title: self.fallback_title.clone(),
+            rotation: self.rotation.clone(),
+            rotate: self.rotate,
+        }
+    }
+
+    /// Overwrites the settings with imported ones, the state of the group is kept
+    pub fn import_config(&mut self, config: GroupConfig) {
+        let GroupConfig {
+            enable,
+            title_segment,
+            delimiter,
+            delimiters,
+            timezone,
+            require_admin,
+            editors,
+            enforce,
+            number_format,
+            locale,
+            week_start,
+            length_policy,
+            bidi,
+            dedupe_segments,
+            pending_templates,
+            named_templates,
+            counters,
+            freeze,
+            countdown,
+            update_interval,
+            active_hours,
+            auto_reenable_on_promotion,
+            failure_message,
+            announcement_template,
+            announce,
+            description_template,
+            language,
+            fallback_title,
+            rotation,
+            rotate,
+        } = config;
+        self.enable = enable;
+        self.title_segment = title_segment;
+        self.delimiter = delimiter;
+        self.delimiters = delimiters;
+        self.timezone = timezone;
+        self.require_admin = require_admin;
+        self.editors = editors;
+        self.enforce = enforce;
+        self.number_format = number_format;
+        self.locale = locale;
+        self.week_start = week_start;
+        self.length_policy = length_policy;
+        self.bidi = bidi;
+        self.dedupe_segments = dedupe_segments;
+        self.pending_templates = pending_templates;
+        self.named_templates = named_templates;
+        self.counters = counters;
+        self.freeze = freeze;
+        self.countdown = countdown;
+        self.update_interval = update_interval;
+        self.active_hours = active_hours;
+        self.auto_reenable_on_promotion = auto_reenable_on_promotion;
+        self.failure_message = failure_message;
+        self.announcement_template = announcement_template;
+        self.announce = announce;
+        self.description_template = description_template;
+        self.language = language;
+        self.fallback_title = fallback_title;
+        self.rotation = rotation;
+        self.rotation_index = 0;
+        self.rotate = rotate;
+    }
+
+    /// Returns false if the user already is an editor
+    pub fn add_editor(&mut self, user_id: UserId) -> bool {
+        if self.editors.contains(&user_id) {
+            return false;
+        }
+        self.editors.push(user_id);
+        true
+    }
+
+    /// Returns false if the user isn't an editor
+    pub fn remove_editor(&mut self, user_id: UserId) -> bool {
+        let len = self.editors.len();
+        self.editors.retain(|editor| *editor != user_id);
+        self.editors.len() != len
+    }
+
+    pub fn reset_delimiter(&mut self) {
+        self.delimiter = DEFAULT_DELIMITER.to_string();
+        self.delimiters.clear();
+    }
+
+    pub fn reset_timezone(&mut self) {
+        self.timezone = Tz::UTC.to_string();
+    }
+
+    /// Adds one to the counter, an undefined one starts from 0
+    pub fn increment_counter(&mut self, name: &str) {
+        let counter = self.counters.entry(name.to_string()).or_default();
+        *counter = counter.saturating_add(1);
+    }
+
+    /// Returns false if there is no such counter
+    pub fn reset_counter(&mut self, name: &str) -> bool {
+        match self.counters.get_mut(name) {
+            Some(counter) => {
+                *counter = 0;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Queues a template change, keeping the queue ordered by date
+    pub fn schedule_template(&mut self, date: NaiveDate, title_segment: Vec<String>) {
+        let date = date.num_days_from_ce();
+        let position = self
+            .pending_templates
+            .iter()
+            .position(|pending| pending.date > date)
+            .unwrap_or(self.pending_templates.len());
+        self.pending_templates.insert(
+            position,
+            PendingTemplate {
+                date,
+                title_segment,
+            },
+        );
+    }
+
+    /// Activates the scheduled template changes due by `today`, returns whether any was activated
+    pub fn activate_pending_templates(&mut self, today: NaiveDate) -> bool {
+        let today = today.num_days_from_ce();
+        let due = self
+            .pending_templates
+            .iter()
+            .take_while(|pending| pending.date <= today)
+            .count();
+        if due == 0 {
+            return false;
+        }
+        // Only the latest due change matters, since each of them replaces the whole template
+        let activated = self.pending_templates.drain(0..due).next_back().unwrap();
+        self.title_segment = activated.title_segment;
+        true
+    }
+
+    /// Detaches the group from its template source and keeps the template of the source as its
+    /// own, returns `false` if the source was gone and the own template (or the title) is kept
+    pub fn unlink_source(&mut self, fallback_title: &str) -> bool {
+        self.template_source = None;
+        match self.source_segment.take() {
+            Some(source_segment) => {
+                self.title_segment = source_segment;
+                true
+            }
+            None => {
+                self.repair_title_segment(fallback_title);
+                false
+            }
+        }
+    }
+
+    pub fn repair_title_segment(&mut self, fallback_title: &str) -> bool {
+        if self.title_segment.iter().any(|segment| !segment.is_empty()) {
+            return false;
+        }
+        let title = if self.last_title.is_empty() {
+            fallback_title
+        } else {
+            &self.last_title
+        };
+        self.title_segment = vec![title.to_string()];
+        true
+    }
+
+    pub fn repair_timezone(&mut self) -> bool {
+        if self.timezone.parse::<Tz>().is_ok() {
+            return false;
+        }
+        self.reset_timezone();
+        true
+    }
+
+    pub fn repair_delimiter(&mut self, utc_time: NaiveDateTime) -> bool {
+        if !self.exceeds_length_budget_at(self.get_time(utc_time))
+            || (self.delimiter == DEFAULT_DELIMITER && self.delimiters.is_empty())
+        {
+            return false;
+        }
+        self.reset_delimiter();
+        true
+    }
+
+    pub fn repair_title_length(&mut self, fallback_title: &str, utc_time: NaiveDateTime) -> bool {
+        if !self.exceeds_length_budget_at(self.get_time(utc_time)) {
+            return false;
+        }
+        self.title_segment = vec![fallback_title.to_string()];
+        true
+    }
+
+    /// Fixes invariant violations with defaults, returns the descriptions of what was repaired
+    pub fn repair(&mut self, fallback_title: &str, utc_time: NaiveDateTime) -> Vec<&'static str> {
+        let mut repaired = Vec::new();
+        if self.repair_title_segment(fallback_title) {
+            repaired.push(translate(self.language, "标题模板为空，已恢复为群标题"));
+        }
+        if self.repair_timezone() {
+            repaired.push(translate(self.language, "时区无法解析，已重置为 UTC"));
+        }
+        if self.repair_delimiter(utc_time) {
+            repaired.push(translate(self.language, "标题超出长度限制，已重置分隔符"));
+        }
+        if self.repair_title_length(fallback_title, utc_time) {
+            repaired.push(translate(
+                self.language,
+                "标题仍超出长度限制，已恢复为群标题",
+            ));
+        }
+        repaired
+    }
+
+    /// Local time of the group at the given UTC time
+    pub fn get_time(&self, utc_time: NaiveDateTime) -> DateTime<Tz> {
+        let tz: Tz = self.timezone.parse().unwrap_or(Tz::UTC);
+        to_local_time(utc_time, tz)
+    }
+
+    pub fn get_local_time(&self, date: &Date) -> DateTime<Tz> {
+        self.get_time(NaiveDateTime::from_timestamp(
+            (date.as_millis() / 1000) as i64,
+            0,
+        ))
+    }
+
+    pub fn get_local_date(&self, date: &Date) -> NaiveDate {
+        self.get_local_time(date).date().naive_local()
+    }
+
+    pub fn get_last_title(&self) -> &str {
+        &self.last_title
+    }
+
+    pub fn join_title_template(&self) -> String {
+        self.join_segments(|segment| segment.to_string())
+    }
+
+    /// The template that actually gets rendered, which may differ from the one shown to users
+    pub fn assemble_title_template(&self) -> String {
+        if self.bidi {
+            self.join_segments(isolate_bidi)
+        } else {
+            self.join_title_template()
+        }
+    }
+
+    /// Segments of the template source if the group is linked to one, otherwise its own
+    pub fn template_segments(&self) -> &[String] {
+        match (&self.template_source, &self.source_segment) {
+            (Some(_), Some(source_segment)) => source_segment,
+            _ => &self.title_segment,
+        }
+    }
+
+    fn join_segments<F: Fn(&str) -> String>(&self, wrap: F) -> String {
+        let segments = self.template_segments();
+        let segments = segments
+            .iter()
+            .enumerate()
+            .filter(|(i, segment)| !self.dedupe_segments || *i == 0 || segments[i - 1] != **segment)
+            .map(|(_, segment)| segment);
+        if self.delimiters.is_empty() {
+            return segments
+                .map(|segment| wrap(segment))
+                .collect::<Vec<String>>()
+                .join(&self.delimiter);
+        }
+        // Delimiters cycle by position, the one between segment `i` and `i + 1` is `i % len`
+        let mut ret = String::new();
+        for (i, segment) in segments.enumerate() {
+            if i > 0 {
+                ret.push_str(&self.delimiters[(i - 1) % self.delimiters.len()]);
+            }
+            ret.push_str(&wrap(segment));
+        }
+        ret
+    }
+
+    /// Collapses the segments into a single one that renders to the same title. The isolates are
+    /// kept in the segment if bidi isolation is on, so it's turned off to not isolate it again.
+    pub fn flatten_title_template(&mut self) {
+        self.title_segment = vec![self.assemble_title_template()];
+        self.bidi = false;
+    }
+
+    pub fn clear_title_template(&mut self) {
+        self.title_segment.clear();
+    }
+
+    /// Whether there are no segments to render, which is a mistake in the settings rather than
+    /// something going wrong in Telegram
+    pub fn is_template_empty(&self) -> bool {
+        self.template_segments().is_empty()
+    }
+
+    pub fn get_new_title<S: AsRef<str>>(
+        &self,
+        context: &HashMap<&str, S>,
+    ) -> Result<String, WorkerError> {
+        let template = Template::new(self.assemble_title_template()).with_regex(&PLACEHOLDER_REGEX);
+        template
+            .render(context)
+            .map_err(|e| WorkerError::RustError(e.to_string()))
+    }
+
+    /// Keeps the most recent `MAX_RECENT_ERRORS` errors, evicting the oldest ones
+    pub fn record_error(&mut self, record: ApiErrorRecord) {
+        self.recent_errors.push(record);
+        if self.recent_errors.len() > MAX_RECENT_ERRORS {
+            let excess = self.recent_errors.len() - MAX_RECENT_ERRORS;
+            self.recent_errors.drain(0..excess);
+        }
+    }
+
+    /// Keeps the most recent `MAX_HISTORY_ENTRIES` changes, evicting the oldest ones
+    pub fn record_history(&mut self, entry: HistoryEntry) {
+        self.history.push(entry);
+        if self.history.len() > MAX_HISTORY_ENTRIES {
+            let excess = self.history.len() - MAX_HISTORY_ENTRIES;
+            self.history.drain(0..excess);
+        }
+    }
+
+    /// Lists the recorded errors, oldest first, in the group's timezone
+    pub fn describe_errors(&self) -> String {
+        self.recent_errors
+            .iter()
+            .map(|record| {
+                let time = self.get_time(NaiveDateTime::from_timestamp(
+                    (record.timestamp / 1000) as i64,
+                    0,
+                ));
+                format!(
+                    "{} [{}] {}",
+                    time.format("%Y-%m-%d %H:%M:%S"),
+                    record.error_code,
+                    record.description
+                )
+            })
+            .collect::<Vec<String>>()
+            .join("\n")
+    }
+
+    /// Lists the recorded changes, latest first, in the group's timezone
+    pub fn describe_history(&self) -> String {
+        self.history
+            .iter()
+            .rev()
+            .map(|entry| {
+                let time = self.get_time(NaiveDateTime::from_timestamp(
+                    (entry.timestamp / 1000) as i64,
+                    0,
+                ));
+                match &entry.user {
+                    Some(user) => format!(
+                        "{} {} by {} → {}",
+                        time.format("%Y-%m-%d %H:%M"),
+                        entry.source,
+                        user,
+                        entry.value
+                    ),
+                    None => format!(
+                        "{} {} → {}",
+                        time.format("%Y-%m-%d %H:%M"),
+                        entry.source,
+                        entry.value
+                    ),
+                }
+            })
+            .collect::<Vec<String>>()
+            .join("\n")
+    }
+
+    pub fn last_error_time(&self) -> Option<u64> {
+        self.recent_errors.last().map(|record| record.timestamp)
+    }
+
+    /// Whether Telegram refused a request since the error recorded at `since`, which usually
+    /// means the bot lacks the rights or was removed
+    pub fn was_refused_since(&self, since: Option<u64>) -> bool {
+        self.last_error_time() != since
+            && self
+                .recent_errors
+                .last()
+                .is_some_and(|record| matches!(record.error_code, 400 | 403))
+    }
+
+    /// Sets the title, retrying a few times when Telegram is flooded or unavailable. Returns
+    /// `Ok(false)` if it refused to, e.g. for lack of permissions.
+    pub async fn update_title<S: AsRef<str>>(
+        &mut self,
+        bot: &Bot<'_>,
+        title: S,
+    ) -> Result<bool, WorkerError> {
+        let mut attempt = 1;
+        loop {
+            let set_chat_title = SetChatTitle {
+                chat_id: ChatTarget::Id(self.id),
+                title: title.as_ref(),
+            };
+            let response = bot
+                .send_json_request(set_chat_title, RequestMethod::Post)
+                .await;
+            let result = match response {
+                Ok(mut res) => res
+                    .json::<TelegramResult<bool>>()
+                    .await
+                    .unwrap_or(SET_CHAT_TITLE_FAILED),
+                Err(e) if attempt < MAX_TITLE_ATTEMPTS => {
+                    error!("Unable to set title, retrying: {}", e);
+                    sleep(RETRY_BASE_DELAY << (attempt - 1)).await?;
+                    attempt += 1;
+                    continue;
+                }
+                Err(e) => {
+                    self.record_error(ApiErrorRecord {
+                        error_code: 0,
+                        description: e.to_string(),
+                        timestamp: Date::now().as_millis(),
+                    });
+                    return Err(e);
+                }
+            };
+            if result.ok {
+                return Ok(true);
+            }
+            match retry_delay(&result, attempt) {
+                Some(delay) => {
+                    info!(
+                        "Telegram refused to set the title with {:?}, retrying in {} ms",
+                        result.error_code, delay
+                    );
+                    sleep(delay).await?;
+                    attempt += 1;
+                }
+                None => {
+                    self.record_failed_result(result);
+                    return Ok(false);
+                }
+            }
+        }
+    }
+
+    /// Renders the template without checking whether the result is a valid title
+    pub fn render_template(&self, date: &Date) -> Result<String, WorkerError> {
+        let naive_date = NaiveDateTime::from_timestamp((date.as_millis() / 1000) as i64, 0);
+        info!("Got naive time: {}", naive_date);
+        let local_time = self.get_time(naive_date);
+        info!("Local time: {}", local_time);
+        self.render_local(local_time)
+    }
+
+    /// Renders the template at the local time of the group
+    pub fn render_local(&self, local_time: DateTime<Tz>) -> Result<String, WorkerError> {
+        if self.is_template_empty() {
+            return Err(WorkerError::RustError("Template is empty".to_string()));
+        }
+        self.render_text(&self.assemble_title_template(), local_time)
+    }
+
+    /// Renders any text with the placeholders and settings of the group
+    pub fn render_text(&self, text: &str, local_time: DateTime<Tz>) -> Result<String, WorkerError> {
+        render_template_at(
+            text,
+            local_time,
+            self.number_format,
+            self.locale,
+            self.week_start,
+            &self.counters,
+            self.countdown_date(),
+            self.member_count.map(|(count, _)| count),
+        )
+    }
+
+    /// Every placeholder without arguments, rendered with the settings of the group
+    pub fn generate_context(&self, local_time: DateTime<Tz>) -> TemplateContext<'static> {
+        let mut context = TemplateContext::generate(local_time);
+        if let Some(target) = self.countdown_date() {
+            context.resolve_countdown(&local_time, target);
+        }
+        context.apply_week_start(self.week_start, &local_time);
+        context.apply_locale(self.locale, &local_time);
+        context.apply_number_format(self.number_format);
+        context
+    }
+
+    pub fn countdown_date(&self) -> Option<NaiveDate> {
+        self.countdown
+            .and_then(NaiveDate::from_num_days_from_ce_opt)
+    }
+
+    /// Placeholders of the template that can't be resolved. Counters that aren't defined yet are
+    /// left out, they are rendered as 0.
+    pub fn validate_template(&self) -> Vec<String> {
+        let template = self.assemble_title_template();
+        check_references(&template, &self.counters, self.countdown_date())
+            .into_iter()
+            .filter(|(key, reference)| {
+                *reference == Reference::Dangling && !key.starts_with(COUNTER_PREFIX)
+            })
+            .map(|(key, _)| key.to_string())
+            .collect()
+    }
+
+    /// Renders the template at a few upcoming times, so placeholders that only break at certain
+    /// times are caught before the template is applied
+    pub fn validate_render(
+        &self,
+        utc_time: NaiveDateTime,
+    ) -> Result<(), (DateTime<Tz>, WorkerError)> {
+        // The member count is only fetched when the title is applied, any number does here
+        let with_member_count;
+        let group = if self.member_count.is_none() && self.uses_member_count() {
+            with_member_count = Group {
+                member_count: Some((0, 0)),
+                ..self.clone()
+            };
+            &with_member_count
+        } else {
+            self
+        };
+        for offset in VALIDATION_OFFSETS {
+            let sample = self.get_time(utc_time + chrono::Duration::seconds(offset as i64));
+            if let Err(e) = group.render_local(sample) {
+                return Err((sample, e));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn render_title(&self, date: &Date) -> Result<String, WorkerError> {
+        let new_title = self.render_template(date)?;
+        check_title_length(&new_title)?;
+        Ok(new_title)
+    }
+
+    /// Renders the template and applies the length policy, exactly as it would be applied
+    pub fn finalize_title(&self, date: &Date) -> Result<FinalTitle, WorkerError> {
+        self.finalize_title_at(self.get_local_time(date))
+    }
+
+    /// The title that would be applied at the given local time
+    pub fn finalize_title_at(&self, local_time: DateTime<Tz>) -> Result<FinalTitle, WorkerError> {
+        match self.fixed_title() {
+            Some(title) => Ok(FinalTitle::Apply(title.to_string())),
+            None => self.length_policy.finalize(self.render_local(local_time)?),
+        }
+    }
+
+    /// The title applied instead of rendering the template, if it's frozen or rotating
+    pub fn fixed_title(&self) -> Option<&str> {
+        // The frozen title is applied once, after that it's unchanged and nothing happens
+        if let Some(frozen) = &self.freeze {
+            return Some(frozen);
+        }
+        self.current_rotation_entry()
+    }
+
+    /// Whether the title template shows `{member_count}`, possibly with arithmetic
+    pub fn uses_member_count(&self) -> bool {
+        get_placeholders(&self.assemble_title_template())
+            .into_iter()
+            .any(|key| parse_arithmetic(key).map_or(key, |(base, _, _)| base) == MEMBER_COUNT_KEY)
+    }
+
+    /// Fetches the member count if the template shows it and the one fetched before is older
+    /// than `MEMBER_COUNT_TTL`. The old count is kept if it can't be fetched.
+    pub async fn refresh_member_count(&mut self, bot: &Bot<'_>, date: &Date) {
+        if !self.uses_member_count() {
+            return;
+        }
+        if let Some((_, fetched_at)) = self.member_count {
+            if date.as_millis() < fetched_at + MEMBER_COUNT_TTL {
+                return;
+            }
+        }
+        match bot.get_chat_member_count(ChatTarget::Id(self.id)).await {
+            Ok(count) => self.member_count = Some((count, date.as_millis())),
+            Err(e) => error!(
+                "Unable to get the member count of group {}: {}",
+                get_raw_chat_id(&self.id),
+                e
+            ),
+        }
+    }
+
+    /// The rotation entry applied instead of the template, if rotating
+    pub fn current_rotation_entry(&self) -> Option<&str> {
+        if !self.rotate || self.rotation.is_empty() {
+            return None;
+        }
+        let index = self.rotation_index as usize % self.rotation.len();
+        Some(&self.rotation[index])
+    }
+
+    /// Moves on to the next rotation entry, wrapping around at the end. A frozen title stops the
+    /// rotation.
+    pub fn advance_rotation(&mut self) {
+        if self.freeze.is_none() && self.current_rotation_entry().is_some() {
+            self.rotation_index = ((self.rotation_index as usize + 1) % self.rotation.len()) as u32;
+        }
+    }
+
+    /// Whether the current template renders to a title longer than allowed
+    pub fn exceeds_length_budget(&self, date: &Date) -> bool {
+        self.render_template(date)
+            .map(|title| title_length(&title) > MAX_TITLE_LENGTH)
+            .unwrap_or(false)
+    }
+
+    /// Like `exceeds_length_budget`, at the given local time
+    pub fn exceeds_length_budget_at(&self, local_time: DateTime<Tz>) -> bool {
+        self.render_text(&self.assemble_title_template(), local_time)
+            .map(|title| title_length(&title) > MAX_TITLE_LENGTH)
+            .unwrap_or(false)
+    }
+
+    /// Renames the group, the title is written ahead so a rename that happened right before the
+    /// worker got killed can be told apart from one that didn't
+    pub async fn apply_title(
+        &mut self,
+        bot: &Bot<'_>,
+        store: &DataStore<'_>,
+        new_title: String,
+        date: &Date,
+    ) -> Result<bool, WorkerError> {
+        info!("Applying title: {}", new_title);
+        self.pending_title = Some(new_title.clone());
+        store.save_group(self).await?;
+        // If the request failed the rename may still have gone through, so the pending title is
+        // left for the next load to reconcile. Otherwise the outcome is known.
+        let updated = self.update_title(bot, &new_title).await?;
+        self.pending_title = None;
+        if !updated {
+            return Err(WorkerError::RustError(
+                "Telegram refused to change the title".to_string(),
+            ));
+        }
+        self.record_applied_title(new_title, date.as_millis());
+        self.announce(bot, date).await;
+        Ok(true)
+    }
+
+    /// Remembers the title Telegram accepted at `now`, in milliseconds. Changes waiting to be
+    /// applied are in it, so they're settled.
+    pub fn record_applied_title(&mut self, new_title: String, now: u64) {
+        if new_title != self.last_title {
+            self.previous_title = Some(std::mem::replace(&mut self.last_title, new_title));
+        }
+        self.last_updated = now;
+        self.pending_apply = false;
+        self.failure_notified = false;
+    }
+
+    fn record_failed_result<T>(&mut self, result: TelegramResult<T>) {
+        self.record_error(ApiErrorRecord {
+            error_code: result.error_code.unwrap_or_default(),
+            description: result.description.unwrap_or_default(),
+            timestamp: Date::now().as_millis(),
+        });
+    }
+
+    /// Keeps track of the announcement pinned last, returns the previous one to unpin. If the
+    /// new one couldn't be pinned, e.g. for lack of rights, the previous one stays pinned.
+    pub fn track_pinned_announcement(
+        &mut self,
+        message_id: MessageId,
+        pinned: bool,
+    ) -> Option<MessageId> {
+        if !pinned {
+            return None;
+        }
+        self.pinned_announcement
+            .replace(message_id)
+            .filter(|previous| *previous != message_id)
+    }
+
+    /// Sends and pins the announcement, then unpins the previous one. Failures, e.g. the bot
+    /// lacking the right to pin, are recorded without failing the rename.
+    pub async fn announce(&mut self, bot: &Bot<'_>, date: &Date) {
+        let template = match &self.announcement_template {
+            Some(template) if self.announce => template,
+            _ => return,
+        };
+        let text = match self.render_text(template, self.get_local_time(date)) {
+            Ok(text) => text,
+            Err(e) => {
+                error!("Unable to render announcement: {}", e);
+                self.record_error(ApiErrorRecord {
+                    error_code: 0,
+                    description: e.to_string(),
+                    timestamp: Date::now().as_millis(),
+                });
+                return;
+            }
+        };
+        let result = bot.send_message(ChatTarget::Id(self.id), &text).await;
+        let message_id = match result {
+            Ok(TelegramResult {
+                ok: true,
+                result: Some(message),
+                ..
+            }) => message.message_id,
+            Ok(result) => return self.record_failed_result(result),
+            Err(e) => {
+                error!("Unable to send announcement: {}", e);
+                return;
+            }
+        };
+        // The new one is pinned first, so the previous one stays pinned if that fails
+        let pinned = match bot
+            .pin_chat_message(ChatTarget::Id(self.id), message_id)
+            .await
+        {
+            Ok(result) if result.ok => true,
+            Ok(result) => {
+                self.record_failed_result(result);
+                false
+            }
+            Err(e) => {
+                error!("Unable to pin announcement: {}", e);
+                false
+            }
+        };
+        let previous = match self.track_pinned_announcement(message_id, pinned) {
+            Some(previous) => previous,
+            None => return,
+        };
+        match bot
+            .unpin_chat_message(ChatTarget::Id(self.id), previous)
+            .await
+        {
+            Ok(result) if !result.ok => self.record_failed_result(result),
+            Ok(_) => {}
+            Err(e) => error!("Unable to unpin previous announcement: {}", e),
+        }
+    }
+
+    pub async fn apply_template(
+        &mut self,
+        bot: &Bot<'_>,
+        store: &DataStore<'_>,
+        date: &Date,
+    ) -> Result<bool, WorkerError> {
+        self.refresh_member_count(bot, date).await;
+        match self.finalize_title(date)? {
+            FinalTitle::Apply(new_title) if self.is_title_current(bot, &new_title).await => {
+                info!("Title {} is already set, skipping...", new_title);
+                self.pending_apply = false;
+                self.failure_notified = false;
+                Ok(true)
+            }
+            FinalTitle::Apply(new_title) => self.apply_title(bot, store, new_title, date).await,
+            FinalTitle::Skip(rendered) => {
+                info!("Title {} is too long, skipping...", rendered);
+                Ok(false)
+            }
+        }
+    }
+
+    /// Whether the group already has the title, so renaming it would only post another service
+    /// message. The live title is checked as well in case someone renamed the group by hand.
+    async fn is_title_current(&self, bot: &Bot<'_>, title: &str) -> bool {
+        if title != self.last_title {
+            return false;
+        }
+        match bot.get_chat(ChatTarget::Id(self.id)).await {
+            Ok(chat) => get_group_title(&chat.kind).as_deref() == Some(title),
+            Err(e) => {
+                error!(
+                    "Unable to get the title of group {}: {}",
+                    get_raw_chat_id(&self.id),
+                    e
+                );
+                false
+            }
+        }
+    }
+
+    /// Renders the description template, if there's one
+    pub fn render_description(&self, date: &Date) -> Option<Result<String, WorkerError>> {
+        let template = self.description_template.as_ref()?;
+        Some(
+            self.render_text(template, self.get_local_time(date))
+                .and_then(|description| {
+                    check_description_length(&description)?;
+                    Ok(description)
+                }),
+        )
+    }
+
+    /// Applies the description template, returns `Ok(false)` if there's none or it's unchanged
+    pub async fn apply_description(
+        &mut self,
+        bot: &Bot<'_>,
+        date: &Date,
+    ) -> Result<bool, WorkerError> {
+        let description = match self.render_description(date) {
+            Some(description) => description?,
+            None => return Ok(false),
+        };
+        if description == self.last_description {
+            return Ok(false);
+        }
+        let set_chat_description = SetChatDescription {
+            chat_id: ChatTarget::Id(self.id),
+            description: &description,
+        };
+        let result = bot
+            .send_json_request(set_chat_description, RequestMethod::Post)
+            .await?
+            .json::<TelegramResult<bool>>()
+            .await?;
+        if !result.ok {
+            let description = result.description.clone().unwrap_or_default();
+            self.record_failed_result(result);
+            return Err(WorkerError::RustError(description));
+        }
+        info!(
+            "Description of group {} changed to {}",
+            get_raw_chat_id(&self.id),
+            description
+        );
+        self.last_description = description;
+        Ok(true)
+    }
+
+    pub fn failure_message(&self) -> &str {
+        self.failure_message
+            .as_deref()
+            .unwrap_or_else(|| translate(self.language, DEFAULT_FAILURE_MESSAGE))
+    }
+
+    /// Counts a failed scheduled run, turning off automation once `MAX_CONSECUTIVE_FAILURES` of
+    /// them happened in a row. Returns whether it was turned off.
+    pub fn record_scheduled_failure(&mut self) -> bool {
+        self.consecutive_failures += 1;
+        if self.consecutive_failures < MAX_CONSECUTIVE_FAILURES {
+            return false;
+        }
+        self.consecutive_failures = 0;
+        self.disable_for_permission();
+        true
+    }
+
+    /// Turns off automation after the bot failed to change the title
+    pub fn disable_for_permission(&mut self) {
+        self.enable = false;
+        self.disabled_by_permission = true;
+    }
+
+    /// Turns off automation on request, a later promotion won't turn it back on
+    pub fn disable_manually(&mut self) {
+        self.enable = false;
+        self.disabled_by_permission = false;
+    }
+
+    /// Whether automation should be turned back on after the bot regained its rights
+    pub fn should_reenable(&self) -> bool {
+        self.auto_reenable_on_promotion
+            && self.disabled_by_permission
+            && !self.enable
+            && !self.is_template_empty()
+    }
+
+    /// Whether the interval since the last update has passed, changes to the template waiting
+    /// to be applied don't wait for it
+    pub fn is_due(&self, now: u64) -> bool {
+        let interval = self.update_interval as u64 * 60_000;
+        self.pending_apply || now + UPDATE_INTERVAL_SLACK >= self.last_updated + interval
+    }
+
+    /// Whether the local hour is within `active_hours`, windows ending before they start wrap
+    /// past midnight
+    pub fn is_active_at(&self, hour: u32) -> bool {
+        match self.active_hours {
+            None => true,
+            Some((start, end)) if start <= end => (start as u32..end as u32).contains(&hour),
+            Some((start, end)) => hour >= start as u32 || hour < end as u32,
+        }
+    }
+
+    /// Whether a live title that differs from the expected one should be overwritten
+    pub fn should_enforce(&self, live_title: &str, expected_title: &str) -> bool {
+        self.enforce && live_title != expected_title
+    }
+
+    /// What the scheduled job has to do to show the rendered title, a title that is already shown
+    /// is only checked against the live one if enforcement is on.
+    pub fn scheduled_update(&self, new_title: &str) -> ScheduledUpdate {
+        match (new_title == self.last_title, self.enforce) {
+            (false, _) => ScheduledUpdate::Apply,
+            (true, false) => ScheduledUpdate::Unchanged,
+            (true, true) => ScheduledUpdate::CheckDrift,
+        }
+    }
+
+    /// Applies the template from the scheduled job, returns `Ok(false)` if nothing was changed.
+    ///
+    /// The title is only touched when the template renders to something new, so manual renames
+    /// are respected until then, unless enforcement is on and the live title has drifted.
+    pub async fn apply_scheduled(
+        &mut self,
+        bot: &Bot<'_>,
+        store: &DataStore<'_>,
+        date: &Date,
+    ) -> Result<bool, WorkerError> {
+        self.refresh_member_count(bot, date).await;
+        let new_title = match self.finalize_title(date) {
+            Ok(FinalTitle::Apply(new_title)) => new_title,
+            Ok(FinalTitle::Skip(rendered)) => {
+                info!("Title {} is too long, skipping...", rendered);
+                return Ok(false);
+            }
+            Err(e) => return self.apply_fallback(bot, store, date, e).await,
+        };
+        // The next run applies the next entry, whether this one goes through or not
+        self.advance_rotation();
+        match self.scheduled_update(&new_title) {
+            ScheduledUpdate::Apply => (),
+            ScheduledUpdate::Unchanged => {
+                self.pending_apply = false;
+                return Ok(false);
+            }
+            ScheduledUpdate::CheckDrift => {
+                self.pending_apply = false;
+                let chat = bot.get_chat(ChatTarget::Id(self.id)).await?;
+                let live_title = get_group_title(&chat.kind).unwrap_or_default();
+                if !self.should_enforce(&live_title, &new_title) {
+                    return Ok(false);
+                }
+                info!(
+                    "Title of group {} drifted to {}, enforcing {}",
+                    get_raw_chat_id(&self.id),
+                    live_title,
+                    new_title
+                );
+            }
+        }
+        self.apply_title(bot, store, new_title, date).await
+    }
+
+    /// Applies the fallback title after the template failed to render, the error is returned if
+    /// there's none. Returns `Ok(false)` if the group already shows it.
+    async fn apply_fallback(
+        &mut self,
+        bot: &Bot<'_>,
+        store: &DataStore<'_>,
+        date: &Date,
+        error: WorkerError,
+    ) -> Result<bool, WorkerError> {
+        let fallback_title = match self.fallback_title.clone() {
+            Some(fallback_title) => fallback_title,
+            None => return Err(error),
+        };
+        error!(
+            "Unable to render title for group {}, falling back to {}: {}",
+            get_raw_chat_id(&self.id),
+            fallback_title,
+            error
+        );
+        if fallback_title == self.last_title {
+            return Ok(false);
+        }
+        self.apply_title(bot, store, fallback_title, date).await
+    }
+
+    /// Settles a rename that was written ahead but never confirmed: it's committed if the live
+    /// title shows it went through, otherwise discarded. Returns whether there was one.
+    pub fn reconcile_pending_title(&mut self, live_title: &str) -> bool {
+        let pending_title = match self.pending_title.take() {
+            Some(pending_title) => pending_title,
+            None => return false,
+        };
+        if pending_title == live_title {
+            info!(
+                "Committing unconfirmed title {} of group {}",
+                pending_title,
+                get_raw_chat_id(&self.id)
+            );
+            self.last_title = pending_title;
+        } else {
+            info!(
+                "Discarding unconfirmed title {} of group {}",
+                pending_title,
+                get_raw_chat_id(&self.id)
+            );
+        }
+        true
+    }
+}
+
+impl<'a> DataStore<'a> {
+    pub fn new(kv: &'a KvStore) -> Self {
+        Self { kv }
+    }
+
+    pub async fn get_group_keys(&self) -> Result<Vec<String>, WorkerError> {
+        let list_result = self
+            .kv
+            .list()
+            .prefix(GROUP_KEY_PREFIX.to_string())
+            .execute()
+            .await?;
+        let mut ret = parse_group_keys(list_result.keys.into_iter().map(|k| k.name));
+        let mut cursor = list_result.cursor;
+        let mut complete = list_result.list_complete;
+        while (!complete) && cursor.is_some() {
+            let list_result = self
+                .kv
+                .list()
+                .prefix(GROUP_KEY_PREFIX.to_string())
+                .cursor(cursor.unwrap())
+                .execute()
+                .await?;
+            let mut new_result = parse_group_keys(list_result.keys.into_iter().map(|k| k.name));
+            ret.append(&mut new_result);
+            cursor = list_result.cursor;
+            complete = list_result.list_complete;
+        }
+        Ok(ret)
+    }
+
+    /// The group a job iterating over all groups should resume from, if its last run was cut short
+    pub async fn load_cursor(&self, job: &str) -> Result<Option<String>, WorkerError> {
+        Ok(self.kv.get(&cursor_key(job)).text().await?)
+    }
+
+    pub async fn save_cursor(&self, job: &str, group_name: &str) -> Result<(), WorkerError> {
+        Ok(self.kv.put(&cursor_key(job), group_name)?.execute().await?)
+    }
+
+    pub async fn clear_cursor(&self, job: &str) -> Result<(), WorkerError> {
+        Ok(self.kv.delete(&cursor_key(job)).await?)
+    }
+
+    pub async fn update_cursor(&self, job: &str, update: CursorUpdate) -> Result<(), WorkerError> {
+        match update {
+            CursorUpdate::Save(group_name) => self.save_cursor(job, &group_name).await,
+            CursorUpdate::Clear => self.clear_cursor(job).await,
+            CursorUpdate::Keep => Ok(()),
+        }
+    }
+
+    /// Keys of all groups ordered for `job`, starting from where its last run stopped, and
+    /// whether it's resuming
+    pub async fn get_group_keys_for(&self, job: &str) -> Result<(Vec<String>, bool), WorkerError> {
+        let cursor = self.load_cursor(job).await?;
+        if let Some(cursor) = &cursor {
+            info!("Resuming job {} from group {}", job, cursor);
+        }
+        let keys = resume_from_cursor(self.get_group_keys().await?, cursor.as_deref());
+        Ok((keys, cursor.is_some()))
+    }
+
+    /// Loads the group along with the template of its source, sources aren't followed further
+    /// and a missing source falls back to the group's own template
+    pub async fn load_group(&self, id: &ChatId) -> Result<Group, WorkerError> {
+        let mut group = self.load_stored_group(id).await?;
+        if let Some(source_id) = group.template_source {
+            match self.load_stored_group(&source_id).await {
+                Ok(source) => group.source_segment = Some(source.title_segment),
+                Err(e) => info!(
+                    "Template source {} of group {} is unavailable: {}",
+                    get_raw_chat_id(&source_id),
+                    get_raw_chat_id(id),
+                    e
+                ),
+            }
+        }
+        Ok(group)
+    }
+
+    async fn load_stored_group(&self, id: &ChatId) -> Result<Group, WorkerError> {
+        let raw_id = get_raw_chat_id(id);
+        let key = format!("{}{}", GROUP_KEY_PREFIX, raw_id);
+        let data =
+            self.kv.get(&key).bytes().await?.ok_or_else(|| {
+                WorkerError::RustError("Group info not found in KvStore".to_string())
+            })?;
+        // A record that can't be used would be replaced by a new group, so it's kept aside
+        let (group, migrated) = match deserialize_group_for(id, &data) {
+            Ok(decoded) => decoded,
+            Err(e) => {
+                error!("Unable to read group {}: {}", raw_id, e);
+                self.back_up_record(&key, raw_id, &data).await?;
+                return Err(e);
+            }
+        };
+        if migrated {
+            info!("Rewriting record of group {}", raw_id);
+            if let Err(e) = self.put_group(&key, &group).await {
+                error!("Unable to rewrite group {}: {}", raw_id, e);
+            }
+        }
+        Ok(group)
+    }
+
+    /// Moves a record that can't be used out of the way, so it can be recreated
+    async fn back_up_record(&self, key: &str, raw_id: i64, data: &[u8]) -> Result<(), WorkerError> {
+        let backup_key = format!("corrupt-group-{}-{}", raw_id, Date::now().as_millis());
+        error!("Backing up group {} as {}", raw_id, backup_key);
+        self.kv.put_bytes(&backup_key, data)?.execute().await?;
+        Ok(self.kv.delete(key).await?)
+    }
+
+    async fn put_group(&self, key: &str, group: &Group) -> Result<(), WorkerError> {
+        let data = serde_json::to_vec(group).map_err(|e| WorkerError::RustError(e.to_string()))?;
+        Ok(self.kv.put_bytes(key, &data)?.execute().await?)
+    }
+
+    pub async fn load_group_or_create(&self, id: &ChatId, chat_type: &ChatType) -> Group {
+        let stored_group = self.load_group(id).await;
+        if let Ok(mut group) = stored_group {
+            if let Some(live_title) = get_group_title(chat_type) {
+                if group.reconcile_pending_title(&live_title) {
+                    self.save_group(&group).await.ok();
+                }
+            }
+            group
+        } else {
+            let new_group = Group::new(id, chat_type);
+            self.save_group(&new_group).await.ok();
+            new_group
+        }
+    }
+
+    pub async fn save_group(&self, group: &Group) -> Result<(), WorkerError> {
+        let raw_id = get_raw_chat_id(&group.id);
+        let key = format!("{}{}", GROUP_KEY_PREFIX, raw_id);
+        self.put_group(&key, group).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_group() -> Group {
+        Group::with_title(&ChatId(-100), "Title")
+    }
+
+    // A group saved by the original version, encoded with its `Group`
+    const ORIGINAL_RECORD: &[u8] = &[
+        1, 46, 237, 196, 225, 22, 255, 255, 255, 2, 0, 0, 0, 0, 0, 0, 0, 9, 0, 0, 0, 0, 0, 0, 0,
+        230, 145, 184, 233, 177, 188, 231, 190, 164, 10, 0, 0, 0, 0, 0, 0, 0, 37, 109, 230, 156,
+        136, 37, 100, 230, 151, 165, 3, 0, 0, 0, 0, 0, 0, 0, 32, 124, 32, 22, 0, 0, 0, 0, 0, 0, 0,
+        230, 145, 184, 233, 177, 188, 231, 190, 164, 32, 124, 32, 48, 53, 230, 156, 136, 49, 55,
+        230, 151, 165, 13, 0, 0, 0, 0, 0, 0, 0, 65, 115, 105, 97, 47, 83, 104, 97, 110, 103, 104,
+        97, 105, 0,
+    ];
+
+    #[test]
+    fn original_layout_is_migrated() {
+        let (group, legacy) = deserialize_group(ORIGINAL_RECORD).unwrap();
+        assert!(legacy);
+        assert!(group.enable);
+        assert_eq!(group.id, ChatId(-1001234567890));
+        assert_eq!(group.title_segment, vec!["摸鱼群", "%m月%d日"]);
+        assert_eq!(group.delimiter, " | ");
+        assert_eq!(group.last_title, "摸鱼群 | 05月17日");
+        assert_eq!(group.timezone, "Asia/Shanghai");
+        assert!(!group.require_admin);
+        assert_eq!(group.notify_user, None);
+        assert!(group.delimiters.is_empty());
+        assert!(group.history.is_empty());
+    }
+
+    #[test]
+    fn delimiters_cycle_by_position() {
+        let mut group = test_group();
+        group.delimiters = vec![" | ".to_string(), " · ".to_string()];
+        let expected = ["a | b", "a | b · c", "a | b · c | d"];
+        for (count, expected) in (2..=4).zip(expected) {
+            group.title_segment = ["a", "b", "c", "d"][..count]
+                .iter()
+                .map(ToString::to_string)
+                .collect();
+            assert_eq!(group.join_title_template(), expected);
+        }
+    }
+
+    #[test]
+    fn subscribe_layout_is_migrated() {
+        let legacy = LegacyGroupV1 {
+            enable: true,
+            id: ChatId(-100),
+            title_segment: vec!["a".to_string(), "%H".to_string()],
+            delimiter: " · ".to_string(),
+            last_title: "a · 12".to_string(),
+            timezone: "Asia/Shanghai".to_string(),
+            require_admin: false,
+            notify_user: Some(UserId(7)),
+        };
+        let data = bincode::serialize(&legacy).unwrap();
+        let (group, legacy) = deserialize_group(&data).unwrap();
+        assert!(legacy);
+        assert!(group.enable);
+        assert_eq!(group.id, ChatId(-100));
+        assert_eq!(group.title_segment, vec!["a", "%H"]);
+        assert_eq!(group.delimiter, " · ");
+        assert!(group.delimiters.is_empty());
+        assert_eq!(group.last_title, "a · 12");
+        assert_eq!(group.timezone, "Asia/Shanghai");
+        assert!(!group.require_admin);
+        assert_eq!(group.notify_user, Some(UserId(7)));
+        assert_eq!(group.original_title, "");
+        assert_eq!(group.number_format, test_group().number_format);
+    }
+
+    #[test]
+    fn subscribe_layout_needs_every_byte() {
+        let legacy = LegacyGroupV1 {
+            enable: false,
+            id: ChatId(-100),
+            title_segment: vec!["a".to_string()],
+            delimiter: " | ".to_string(),
+            last_title: "a".to_string(),
+            timezone: "UTC".to_string(),
+            require_admin: true,
+            notify_user: None,
+        };
+        let mut data = bincode::serialize(&legacy).unwrap();
+        data.push(0);
+        assert!(decode_exact::<LegacyGroupV1>(&data).is_err());
+        data.truncate(data.len() - 2);
+        assert!(decode_exact::<LegacyGroupV1>(&data).is_err());
+    }
+
+    #[test]
+    fn drifted_title_is_only_restored_when_enforced() {
+        let mut group = test_group();
+        assert!(!group.should_enforce("Renamed", "Title"));
+        group.enforce = true;
+        assert!(group.should_enforce("Renamed", "Title"));
+        assert!(!group.should_enforce("Title", "Title"));
+    }
+
+    fn error_record(error_code: i32, timestamp: u64) -> ApiErrorRecord {
+        ApiErrorRecord {
+            error_code,
+            description: format!("error {}", error_code),
+            timestamp,
+        }
+    }
+
+    #[test]
+    fn errors_are_recorded_and_listed() {
+        let mut group = test_group();
+        group.timezone = "Asia/Shanghai".to_string();
+        group.record_error(error_record(400, 0));
+        group.record_error(error_record(403, 60_000));
+        assert_eq!(group.last_error_time(), Some(60_000));
+        assert_eq!(
+            group.describe_errors(),
+            "1970-01-01 08:00:00 [400] error 400\n1970-01-01 08:01:00 [403] error 403"
+        );
+    }
+
+    #[test]
+    fn error_buffer_evicts_oldest_entries() {
+        let mut group = test_group();
+        for code in 0..(MAX_RECENT_ERRORS as i32 + 2) {
+            group.record_error(error_record(code, code as u64));
+        }
+        let codes: Vec<i32> = group.recent_errors.iter().map(|r| r.error_code).collect();
+        assert_eq!(codes, vec![2, 3, 4, 5, 6]);
+    }
+
+    fn local_time(timezone: &str, utc: &str) -> DateTime<Tz> {
+        let tz: Tz = timezone.parse().unwrap();
+        to_local_time(utc.parse::<NaiveDateTime>().unwrap(), tz)
+    }
+
+    #[test]
+    fn strftime_placeholder_composes_formats() {
+        let group = test_group();
+        let time = local_time("Asia/Shanghai", "2022-05-16T18:30:00");
+        let render = |text: &str| group.render_text(text, time).unwrap();
+        assert_eq!(render("{strftime:%Y年%m月%d日}"), "2022年05月17日");
+        assert_eq!(render("{strftime:%m/%d_%H:%M}"), "05/17_02:30");
+        assert_eq!(
+            render("摸鱼 {strftime:%Y-%m-%d}・{strftime:%H時}"),
+            "摸鱼 2022-05-17・02時"
+        );
+        assert_eq!(render("{strftime:100%%}"), "100%");
+    }
+
+    #[test]
+    fn invalid_strftime_format_is_an_error() {
+        let group = test_group();
+        let time = local_time("UTC", "2022-05-16T18:30:00");
+        assert!(group.render_text("{strftime:%Q}", time).is_err());
+    }
+
+    #[test]
+    fn reset_delimiter_only_resets_the_delimiters() {
+        let mut group = test_group();
+        group.title_segment = vec!["a".to_string(), "b".to_string(), "c".to_string()];
+        group.delimiter = " / ".to_string();
+        group.delimiters = vec![" - ".to_string()];
+        group.timezone = "Asia/Tokyo".to_string();
+        let time = local_time("UTC", "2022-05-16T18:30:00");
+        assert_eq!(
+            group
+                .render_text(&group.assemble_title_template(), time)
+                .unwrap(),
+            "a - b - c"
+        );
+
+        let mut expected = group.clone();
+        expected.delimiter = DEFAULT_DELIMITER.to_string();
+        expected.delimiters.clear();
+        group.reset_delimiter();
+        assert_eq!(
+            serde_json::to_value(&group).unwrap(),
+            serde_json::to_value(&expected).unwrap()
+        );
+        assert_eq!(
+            group
+                .render_text(&group.assemble_title_template(), time)
+                .unwrap(),
+            "a | b | c"
+        );
+    }
+
+    #[test]
+    fn reset_timezone_only_resets_the_timezone() {
+        let mut group = test_group();
+        group.title_segment = vec!["{H}".to_string()];
+        group.delimiter = " / ".to_string();
+        group.timezone = "Asia/Tokyo".to_string();
+        let utc = "2022-05-16T18:30:00".parse::<NaiveDateTime>().unwrap();
+        assert_eq!(group.render_text("{H}", group.get_time(utc)).unwrap(), "03");
+
+        let mut expected = group.clone();
+        expected.timezone = "UTC".to_string();
+        group.reset_timezone();
+        assert_eq!(
+            serde_json::to_value(&group).unwrap(),
+            serde_json::to_value(&expected).unwrap()
+        );
+        assert_eq!(group.render_text("{H}", group.get_time(utc)).unwrap(), "18");
+    }
+
+    #[test]
+    fn long_delimiter_exceeds_the_length_budget() {
+        let mut group = test_group();
+        group.title_segment = vec!["a".to_string(), "b".to_string(), "c".to_string()];
+        let time = local_time("UTC", "2022-05-16T18:30:00");
+        assert!(!group.exceeds_length_budget_at(time));
+
+        group.delimiter = "-".repeat(64);
+        assert!(group.exceeds_length_budget_at(time));
+        let rendered = group
+            .render_text(&group.assemble_title_template(), time)
+            .unwrap();
+        assert!(group.length_policy.finalize(rendered.clone()).is_err());
+        match LengthPolicy::Truncate.finalize(rendered) {
+            Ok(FinalTitle::Apply(title)) => assert_eq!(title_length(&title), MAX_TITLE_LENGTH),
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn group_like_chats_have_titles() {
+        let group = ChatType::Group {
+            title: "Group".to_string(),
+            username: None,
+            all_members_are_administrators: false,
+        };
+        assert_eq!(get_group_title(&group).as_deref(), Some("Group"));
+        let chat = |kind: &str| -> ChatType {
+            serde_json::from_value(serde_json::json!({"type": kind, "title": kind})).unwrap()
+        };
+        assert_eq!(
+            get_group_title(&chat("supergroup")).as_deref(),
+            Some("supergroup")
+        );
+        assert_eq!(
+            get_group_title(&chat("channel")).as_deref(),
+            Some("channel")
+        );
+    }
+
+    #[test]
+    fn private_chats_have_no_title() {
+        let private = ChatType::Private {
+            username: None,
+            first_name: "User".to_string(),
+            last_name: None,
+        };
+        assert_eq!(get_group_title(&private), None);
+        let unknown: ChatType =
+            serde_json::from_value(serde_json::json!({"type": "secret"})).unwrap();
+        assert_eq!(get_group_title(&unknown), None);
+    }
+
+    #[test]
+    fn unknown_chats_with_a_title_are_group_like() {
+        let chat: telegram_types::bot::types::Chat =
+            crate::bot::parse_telegram_json(r#"{"id": -100, "type": "forum", "title": "Forum"}"#)
+                .unwrap();
+        assert_eq!(get_group_title(&chat.kind).as_deref(), Some("Forum"));
+        let chat: telegram_types::bot::types::Chat =
+            crate::bot::parse_telegram_json(r#"{"id": -100, "type": "secret"}"#).unwrap();
+        assert_eq!(chat.kind, ChatType::Unknown);
+    }
+
+    #[test]
+    fn numbers_are_formatted() {
+        let cases: [(i64, &str, &str, &str); 7] = [
+            (0, "0", "0", "零"),
+            (7, "7", "7", "七"),
+            (1234, "1234", "1,234", "一千二百三十四"),
+            (10086, "10086", "10,086", "一万零八十六"),
+            (-1000, "-1000", "-1,000", "负一千"),
+            (
+                123456789,
+                "123456789",
+                "123,456,789",
+                "一亿二千三百四十五万六千七百八十九",
+            ),
+            (
+                i64::MIN,
+                "-9223372036854775808",
+                "-9,223,372,036,854,775,808",
+                "",
+            ),
+        ];
+        for (value, plain, grouped, cjk) in cases {
+            assert_eq!(NumberFormat::Plain.format(value), plain);
+            assert_eq!(NumberFormat::Grouped.format(value), grouped);
+            if !cjk.is_empty() {
+                assert_eq!(NumberFormat::Cjk.format(value), cjk);
+            }
+        }
+        assert!(NumberFormat::Cjk.format(i64::MIN).starts_with('负'));
+    }
+
+    #[test]
+    fn pending_template_activates_on_its_date() {
+        let date = |day: u32| NaiveDate::from_ymd(2022, 12, day);
+        let mut group = test_group();
+        group.schedule_template(date(24), vec!["Eve".to_string()]);
+        group.schedule_template(date(20), vec!["Holiday".to_string()]);
+        group.schedule_template(date(31), vec!["New year".to_string()]);
+
+        assert!(!group.activate_pending_templates(date(19)));
+        assert_eq!(group.title_segment, vec!["Title".to_string()]);
+        assert!(group.activate_pending_templates(date(20)));
+        assert_eq!(group.title_segment, vec!["Holiday".to_string()]);
+        assert!(!group.activate_pending_templates(date(20)));
+        assert_eq!(group.pending_templates.len(), 2);
+    }
+
+    #[test]
+    fn overdue_templates_activate_the_latest() {
+        let date = |day: u32| NaiveDate::from_ymd(2022, 12, day);
+        let mut group = test_group();
+        group.schedule_template(date(20), vec!["Holiday".to_string()]);
+        group.schedule_template(date(24), vec!["Eve".to_string()]);
+        group.schedule_template(date(31), vec!["New year".to_string()]);
+
+        assert!(group.activate_pending_templates(date(25)));
+        assert_eq!(group.title_segment, vec!["Eve".to_string()]);
+        assert_eq!(group.pending_templates.len(), 1);
+        assert_eq!(group.pending_templates[0].date, date(31).num_days_from_ce());
+    }
+
+    #[test]
+    fn week_of_month_counts_from_the_first() {
+        let cases = [
+            (1, 1),
+            (7, 1),
+            (8, 2),
+            (14, 2),
+            (15, 3),
+            (28, 4),
+            (29, 5),
+            (31, 5),
+        ];
+        for (day, week) in cases {
+            assert_eq!(week_of_month(day), week, "day {}", day);
+        }
+        let group = test_group();
+        let time = local_time("Asia/Shanghai", "2022-05-07T16:00:00");
+        assert_eq!(
+            group.render_text("第{week_of_month}周", time).unwrap(),
+            "第2周"
+        );
+    }
+
+    #[test]
+    fn corrupted_group_is_repaired() {
+        let now = "2022-05-16T18:30:00".parse::<NaiveDateTime>().unwrap();
+        let mut group = test_group();
+        group.timezone = "Mars/Olympus_Mons".to_string();
+        group.delimiter = "-".repeat(200);
+        group.title_segment = vec![String::new(), String::new()];
+        group.last_title = String::new();
+
+        let repaired = group.repair("Fallback", now);
+        assert_eq!(
+            repaired,
+            vec![
+                translate(group.language, "标题模板为空，已恢复为群标题"),
+                translate(group.language, "时区无法解析，已重置为 UTC"),
+            ]
+        );
+        assert_eq!(group.title_segment, vec!["Fallback".to_string()]);
+        assert_eq!(group.timezone, "UTC");
+        // A single segment doesn't use the delimiter, so it's kept
+        assert_eq!(group.delimiter.len(), 200);
+        assert!(group.repair("Fallback", now).is_empty());
+    }
+
+    #[test]
+    fn overflowing_delimiter_is_repaired_before_the_template() {
+        let now = "2022-05-16T18:30:00".parse::<NaiveDateTime>().unwrap();
+        let mut group = test_group();
+        group.title_segment = vec!["a".to_string(), "b".to_string()];
+        group.delimiter = "-".repeat(200);
+        assert_eq!(group.repair("Fallback", now).len(), 1);
+        assert_eq!(group.delimiter, DEFAULT_DELIMITER);
+        assert_eq!(group.title_segment, vec!["a".to_string(), "b".to_string()]);
+
+        group.title_segment = vec!["a".repeat(200)];
+        assert_eq!(group.repair("Fallback", now).len(), 1);
+        assert_eq!(group.title_segment, vec!["Fallback".to_string()]);
+    }
+
+    #[test]
+    fn only_permission_loss_is_reenabled() {
+        let mut group = test_group();
+        group.auto_reenable_on_promotion = true;
+        group.disable_for_permission();
+        assert!(group.should_reenable());
+
+        group.disable_manually();
+        assert!(!group.should_reenable());
+
+        group.disable_for_permission();
+        group.auto_reenable_on_promotion = false;
+        assert!(!group.should_reenable());
+    }
+
+    #[test]
+    fn refusals_are_told_apart_from_other_errors() {
+        let mut group = test_group();
+        assert!(!group.was_refused_since(None));
+        group.record_error(error_record(429, 1));
+        assert!(!group.was_refused_since(None));
+        let since = group.last_error_time();
+        group.record_error(error_record(403, 2));
+        assert!(group.was_refused_since(since));
+        assert!(!group.was_refused_since(group.last_error_time()));
+    }
+
+    #[test]
+    fn bidi_isolates_wrap_each_segment() {
+        let mut group = test_group();
+        group.title_segment = vec!["שלום".to_string(), "Hello".to_string(), "مرحبا".to_string()];
+        assert_eq!(group.assemble_title_template(), "שלום | Hello | مرحبا");
+
+        group.bidi = true;
+        assert_eq!(
+            group.assemble_title_template(),
+            "\u{2068}שלום\u{2069} | \u{2068}Hello\u{2069} | \u{2068}مرحبا\u{2069}"
+        );
+        // The isolates aren't shown to users, but take up the length budget
+        assert_eq!(group.join_title_template(), "שלום | Hello | مرحبا");
+        assert_eq!(
+            title_length(&group.assemble_title_template()),
+            title_length(&group.join_title_template()) + 6
+        );
+    }
+
+    #[test]
+    fn flattening_keeps_the_isolates_once() {
+        let mut group = test_group();
+        group.title_segment = vec!["שלום".to_string(), "{Y}".to_string()];
+        group.bidi = true;
+        let assembled = group.assemble_title_template();
+        group.flatten_title_template();
+        assert!(!group.bidi);
+        assert_eq!(group.assemble_title_template(), assembled);
+    }
+
+    const FAMILY: &str = "\u{1F468}\u{200D}\u{1F469}\u{200D}\u{1F467}\u{200D}\u{1F466}";
+    const FLAG: &str = "\u{1F1EF}\u{1F1F5}";
+    const THUMBS_UP: &str = "\u{1F44D}\u{1F3FD}";
+
+    #[test]
+    fn emoji_length_is_counted_in_utf16_units() {
+        assert_eq!(title_length("😀"), 2);
+        assert_eq!(title_length(FAMILY), 11);
+        assert_eq!(title_length(FLAG), 4);
+        assert_eq!(title_length(THUMBS_UP), 4);
+        assert_eq!(title_length("a😀中"), 4);
+    }
+
+    #[test]
+    fn emoji_sequences_are_never_split() {
+        for emoji in [FAMILY, FLAG, THUMBS_UP] {
+            // One unit short of fitting another emoji, which has to be dropped whole
+            let padding = "a".repeat(MAX_TITLE_LENGTH - title_length(emoji) + 1);
+            let truncated = truncate_title(&format!("{}{}", padding, emoji));
+            assert_eq!(truncated, padding);
+
+            let padding = "a".repeat(MAX_TITLE_LENGTH - title_length(emoji));
+            let title = format!("{}{}", padding, emoji);
+            assert_eq!(truncate_title(&format!("{}b", title)), title);
+        }
+    }
+
+    #[test]
+    fn progress_bar_fills_by_fraction() {
+        assert_eq!(progress_bar(0.0, 8), "░░░░░░░░");
+        assert_eq!(progress_bar(0.5, 8), "▓▓▓▓░░░░");
+        assert_eq!(progress_bar(1.0, 8), "▓▓▓▓▓▓▓▓");
+        assert_eq!(progress_bar(0.5, 1), "▓");
+        assert_eq!(
+            progress_bar(0.25, 32).chars().filter(|c| *c == '▓').count(),
+            8
+        );
+        // Out of range fractions are clamped
+        assert_eq!(progress_bar(-1.0, 4), "░░░░");
+        assert_eq!(progress_bar(2.0, 4), "▓▓▓▓");
+    }
+
+    #[test]
+    fn progress_bar_placeholder_takes_a_width() {
+        let group = test_group();
+        let time = local_time("UTC", "2022-05-16T12:00:00");
+        assert_eq!(
+            group.render_text("{progress_bar}", time).unwrap(),
+            "▓▓▓▓░░░░"
+        );
+        assert_eq!(
+            group.render_text("{progress_bar:day:4}", time).unwrap(),
+            "▓▓░░"
+        );
+        assert!(group.render_text("{progress_bar:0}", time).is_err());
+        assert!(group.render_text("{progress_bar:33}", time).is_err());
+    }
+
+    #[test]
+    fn runs_resume_from_the_cursor() {
+        let mut keys: Vec<String> = (0..1000)
+            .map(|i| format!("-100{}", i * 7919 % 1000))
+            .collect();
+        keys.reverse();
+        let mut sorted = keys.clone();
+        sorted.sort();
+
+        // Each run only gets through `budget` groups, then saves the next one as the cursor
+        let budget = 64;
+        let mut cursor: Option<String> = None;
+        let mut processed = Vec::new();
+        for _ in 0..20 {
+            let ordered = resume_from_cursor(keys.clone(), cursor.as_deref());
+            assert_eq!(ordered.len(), keys.len());
+            processed.extend(ordered.iter().take(budget).cloned());
+            cursor = ordered.get(budget).cloned();
+        }
+        // Every group is visited in turn, none of them is starved
+        let expected: Vec<String> = sorted.iter().cycle().take(20 * budget).cloned().collect();
+        assert_eq!(processed, expected);
+    }
+
+    #[test]
+    fn stale_cursor_resumes_from_the_next_key() {
+        let keys = vec![
+            "-1003".to_string(),
+            "-1001".to_string(),
+            "-1005".to_string(),
+        ];
+        // The group at the cursor may have been deleted in the meantime
+        assert_eq!(
+            resume_from_cursor(keys.clone(), Some("-1002")),
+            vec!["-1003", "-1005", "-1001"]
+        );
+        assert_eq!(
+            resume_from_cursor(keys.clone(), Some("-1009")),
+            vec!["-1001", "-1003", "-1005"]
+        );
+        assert_eq!(
+            resume_from_cursor(keys, None),
+            vec!["-1001", "-1003", "-1005"]
+        );
+    }
+
+    #[test]
+    fn linked_group_renders_the_source_template() {
+        let mut group = test_group();
+        group.title_segment = vec!["Own".to_string()];
+        group.delimiter = " · ".to_string();
+        group.timezone = "Asia/Tokyo".to_string();
+        group.template_source = Some(ChatId(-200));
+        group.source_segment = Some(vec!["Source".to_string(), "{H}".to_string()]);
+
+        let utc = "2022-05-16T18:30:00".parse::<NaiveDateTime>().unwrap();
+        let rendered = group.render_text(&group.assemble_title_template(), group.get_time(utc));
+        assert_eq!(rendered.unwrap(), "Source · 03");
+    }
+
+    #[test]
+    fn missing_source_falls_back_to_the_own_template() {
+        let mut group = test_group();
+        group.title_segment = vec!["Own".to_string()];
+        group.template_source = Some(ChatId(-200));
+        assert_eq!(group.assemble_title_template(), "Own");
+    }
+
+    #[test]
+    fn unlinking_keeps_the_source_template() {
+        let mut group = test_group();
+        group.title_segment = vec!["Stale".to_string()];
+        group.template_source = Some(ChatId(-200));
+        group.source_segment = Some(vec!["Source".to_string(), "{Y}".to_string()]);
+        let linked = group.assemble_title_template();
+
+        assert!(group.unlink_source("Fallback"));
+        assert_eq!(group.template_source, None);
+        assert_eq!(group.assemble_title_template(), linked);
+    }
+
+    #[test]
+    fn unlinking_a_deleted_source_keeps_the_own_template() {
+        let mut group = test_group();
+        group.title_segment = vec!["Own".to_string()];
+        group.template_source = Some(ChatId(-200));
+        assert!(!group.unlink_source("Fallback"));
+        assert_eq!(group.title_segment, vec!["Own".to_string()]);
+
+        group.template_source = Some(ChatId(-200));
+        group.title_segment.clear();
+        group.last_title = "Live".to_string();
+        assert!(!group.unlink_source("Fallback"));
+        assert_eq!(group.title_segment, vec!["Live".to_string()]);
+    }
+
+    #[test]
+    fn cursors_persist_across_invocations() {
+        let keys: Vec<String> = (0..10).map(|i| format!("-100{}", i)).collect();
+
+        // The first run runs out of time before the fifth group
+        let ordered = resume_from_cursor(keys.clone(), None);
+        let cursor = next_cursor(Some(&ordered[4]), false);
+        assert_eq!(cursor, CursorUpdate::Save("-1004".to_string()));
+
+        // The next one starts there, and runs out of time again
+        let ordered = resume_from_cursor(keys.clone(), Some("-1004"));
+        assert_eq!(ordered[..4], keys[4..8]);
+        let cursor = next_cursor(Some(&ordered[4]), true);
+        assert_eq!(cursor, CursorUpdate::Save("-1008".to_string()));
+
+        // The last one goes through the rest and the ones done before, then starts over
+        let ordered = resume_from_cursor(keys.clone(), Some("-1008"));
+        assert_eq!(ordered, [&keys[8..], &keys[..8]].concat());
+        assert_eq!(next_cursor(None, true), CursorUpdate::Clear);
+
+        // Runs getting through every group leave no cursor behind
+        assert_eq!(next_cursor(None, false), CursorUpdate::Keep);
+        // Each job keeps its own
+        assert_ne!(cursor_key(SCHEDULED_JOB), cursor_key("broadcast"));
+    }
+
+    #[test]
+    fn display_width_counts_wide_characters_twice() {
+        assert_eq!(display_width(""), 0);
+        assert_eq!(display_width("Title"), 5);
+        assert_eq!(display_width("摸鱼群"), 6);
+        assert_eq!(display_width("ＡＢ"), 4);
+        assert_eq!(display_width("摸鱼 | 05月17日"), 15);
+        assert_eq!(display_width("😀"), 2);
+        assert_eq!(display_width(FAMILY), 2);
+        assert_eq!(display_width(&format!("a{}中", FLAG)), 5);
+    }
+
+    #[test]
+    fn frozen_title_is_applied_once() {
+        let mut group = test_group();
+        group.enable = true;
+        group.title_segment = vec!["{H}".to_string()];
+        let time = local_time("UTC", "2022-05-16T18:30:00");
+        let frozen = "Closed for the holidays".to_string();
+
+        group.freeze = Some(frozen.clone());
+        let title = group.finalize_title_at(time).unwrap();
+        assert_eq!(title, FinalTitle::Apply(frozen.clone()));
+        assert_eq!(group.scheduled_update(&frozen), ScheduledUpdate::Apply);
+        group.record_applied_title(frozen.clone(), 0);
+
+        // Later ticks render the same frozen title and leave the group alone
+        for later in ["2022-05-16T19:30:00", "2022-05-17T08:00:00"] {
+            let title = group.finalize_title_at(local_time("UTC", later)).unwrap();
+            assert_eq!(title, FinalTitle::Apply(frozen.clone()));
+            assert_eq!(group.scheduled_update(&frozen), ScheduledUpdate::Unchanged);
+        }
+        assert!(group.enable);
+        assert_eq!(group.title_segment, vec!["{H}".to_string()]);
+
+        // Unfreezing goes back to the rendered template
+        group.freeze = None;
+        let title = group.finalize_title_at(time).unwrap();
+        assert_eq!(title, FinalTitle::Apply("18".to_string()));
+        assert_eq!(group.scheduled_update("18"), ScheduledUpdate::Apply);
+    }
+
+    #[test]
+    fn frozen_title_takes_precedence_over_rotation() {
+        let mut group = test_group();
+        group.rotate = true;
+        group.rotation = vec!["A".to_string(), "B".to_string()];
+        group.rotation_index = 1;
+        assert_eq!(group.fixed_title(), Some("B"));
+        group.freeze = Some("Frozen".to_string());
+        assert_eq!(group.fixed_title(), Some("Frozen"));
+    }
+
+    #[test]
+    fn adjacent_duplicates_are_collapsed_when_enabled() {
+        let mut group = test_group();
+        group.title_segment = ["A", "B", "B", "A", "A"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        assert_eq!(group.assemble_title_template(), "A | B | B | A | A");
+        group.dedupe_segments = true;
+        assert_eq!(group.assemble_title_template(), "A | B | A");
+
+        group.delimiters = vec![" - ".to_string(), " + ".to_string()];
+        assert_eq!(group.assemble_title_template(), "A - B + A");
+    }
+
+    #[test]
+    fn pushing_a_duplicate_is_detected() {
+        let mut group = test_group();
+        group.title_segment = vec!["A".to_string(), "B".to_string()];
+        assert!(group.duplicates_adjacent("B", false));
+        assert!(!group.duplicates_adjacent("A", false));
+        assert!(group.duplicates_adjacent("A", true));
+        assert!(!group.duplicates_adjacent("B", true));
+        group.title_segment.clear();
+        assert!(!group.duplicates_adjacent("A", false));
+    }
+
+    #[test]
+    fn names_are_rendered_in_the_locale() {
+        // A Tuesday
+        let time = local_time("UTC", "2022-05-17T12:00:00");
+        let mut group = test_group();
+        let mut render = |locale: TitleLocale, text: &str| {
+            group.locale = locale;
+            group.render_text(text, time).unwrap()
+        };
+        assert_eq!(
+            render(TitleLocale::En, "{B} {b} {A} {a}"),
+            "May May Tuesday Tue"
+        );
+        assert_eq!(
+            render(TitleLocale::Zh, "{B} {b} {A} {a}"),
+            "五月 5月 星期二 周二"
+        );
+        assert_eq!(render(TitleLocale::Ja, "{B} {A} {a}"), "5月 火曜日 火");
+        assert_eq!(render(TitleLocale::Fr, "{A} {d} {B}"), "mardi 17 mai");
+        assert_eq!(
+            render(TitleLocale::Fr, "{strftime:%a_%d_%b_%%A}"),
+            "mar._17_mai_%A"
+        );
+    }
+
+    #[test]
+    fn pending_title_is_committed_if_the_rename_went_through() {
+        let mut group = test_group();
+        group.last_title = "Old".to_string();
+        group.pending_title = Some("New".to_string());
+        assert!(group.reconcile_pending_title("New"));
+        assert_eq!(group.last_title, "New");
+        assert_eq!(group.pending_title, None);
+        assert!(!group.reconcile_pending_title("New"));
+    }
+
+    #[test]
+    fn pending_title_is_discarded_if_the_rename_did_not_happen() {
+        let mut group = test_group();
+        group.last_title = "Old".to_string();
+        group.pending_title = Some("New".to_string());
+        assert!(group.reconcile_pending_title("Old"));
+        assert_eq!(group.last_title, "Old");
+        assert_eq!(group.pending_title, None);
+    }
+
+    #[test]
+    fn pending_title_survives_a_crash_between_rename_and_save() {
+        // The record as written ahead, right before the rename
+        let mut group = test_group();
+        group.last_title = "Old".to_string();
+        group.pending_title = Some("New".to_string());
+        let record = serde_json::to_vec(&group).unwrap();
+
+        // The worker was killed after the rename, so the next load finds the title written ahead
+        let (mut loaded, legacy) = deserialize_group(&record).unwrap();
+        assert!(!legacy);
+        assert_eq!(loaded.last_title, "Old");
+        assert!(loaded.reconcile_pending_title("New"));
+        assert_eq!(loaded.last_title, "New");
+
+        // Saves that didn't write ahead leave nothing to reconcile
+        let record = serde_json::to_vec(&loaded).unwrap();
+        let (mut loaded, _) = deserialize_group(&record).unwrap();
+        assert!(!loaded.reconcile_pending_title("Anything"));
+        assert_eq!(loaded.last_title, "New");
+    }
+
+    #[test]
+    fn record_under_another_key_is_corrupt() {
+        let record = serde_json::to_vec(&test_group()).unwrap();
+        assert!(deserialize_group_for(&ChatId(-100), &record).is_ok());
+        let error = deserialize_group_for(&ChatId(-200), &record).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Group -200 is stored with the mismatched ID -100"
+        );
+        // Legacy records are checked as well
+        assert!(deserialize_group_for(&ChatId(-1001234567890), ORIGINAL_RECORD).is_ok());
+        assert!(deserialize_group_for(&ChatId(-100), ORIGINAL_RECORD).is_err());
+    }
+
+    #[test]
+    fn flattened_template_renders_the_same() {
+        let mut group = test_group();
+        group.title_segment = vec![
+            "摸鱼群".to_string(),
+            "{m}月{d}日".to_string(),
+            "{H}".to_string(),
+        ];
+        group.delimiters = vec![" · ".to_string(), " | ".to_string()];
+        let time = local_time("Asia/Shanghai", "2022-05-16T18:30:00");
+        let render = |group: &Group| {
+            group
+                .render_text(&group.assemble_title_template(), time)
+                .unwrap()
+        };
+        let original = render(&group);
+        assert_eq!(original, "摸鱼群 · 05月17日 | 02");
+
+        // What `/flatten` replies with, pasted into `/set_template`
+        let flattened = group.join_title_template();
+        let mut reset = group.clone();
+        reset.clear_title_template();
+        reset.push_title_template(&flattened);
+        assert_eq!(render(&reset), original);
+
+        group.flatten_title_template();
+        assert_eq!(group.title_segment, vec![flattened]);
+        assert_eq!(render(&group), original);
+    }
+
+    #[test]
+    fn template_failing_at_a_later_sample_is_rejected() {
+        let mut group = test_group();
+        // The day of the year times this overflows from the 308th day on
+        group.title_segment = vec!["{j*30000000000000000}".to_string()];
+        let now = "2022-11-02T12:00:00".parse::<NaiveDateTime>().unwrap();
+        assert!(group.render_local(group.get_time(now)).is_ok());
+
+        let (time, error) = group.validate_render(now).unwrap_err();
+        assert_eq!(time.date().naive_local(), NaiveDate::from_ymd(2022, 12, 3));
+        assert!(error.to_string().contains("Overflow"));
+
+        let earlier = "2022-09-01T12:00:00".parse::<NaiveDateTime>().unwrap();
+        assert!(group.validate_render(earlier).is_ok());
+    }
+
+    #[test]
+    fn announcements_are_pinned_before_the_previous_is_unpinned() {
+        let mut group = test_group();
+        assert_eq!(group.track_pinned_announcement(MessageId(1), true), None);
+        assert_eq!(
+            group.track_pinned_announcement(MessageId(2), true),
+            Some(MessageId(1))
+        );
+        assert_eq!(group.pinned_announcement, Some(MessageId(2)));
+        // Pinning the same message again leaves nothing to unpin
+        assert_eq!(group.track_pinned_announcement(MessageId(2), true), None);
+    }
+
+    #[test]
+    fn previous_announcement_stays_pinned_without_pin_rights() {
+        let mut group = test_group();
+        group.track_pinned_announcement(MessageId(1), true);
+        assert_eq!(group.track_pinned_announcement(MessageId(2), false), None);
+        assert_eq!(group.pinned_announcement, Some(MessageId(1)));
+        assert_eq!(
+            group.track_pinned_announcement(MessageId(3), true),
+            Some(MessageId(1))
+        );
+    }
+
+    #[test]
+    fn references_are_resolved() {
+        let mut counters = BTreeMap::new();
+        counters.insert("days".to_string(), 3);
+        let references = check_references(
+            "{Y}{Y+1}{counter:days}{counter:days}{strftime:%A}{progress_bar:10}",
+            &counters,
+            None,
+        );
+        assert_eq!(
+            references,
+            vec![
+                ("Y", Reference::BuiltIn),
+                ("Y+1", Reference::BuiltIn),
+                ("counter:days", Reference::Counter),
+                ("strftime:%A", Reference::BuiltIn),
+                ("progress_bar:10", Reference::BuiltIn),
+            ]
+        );
+    }
+
+    #[test]
+    fn dangling_references_are_reported() {
+        let references = check_references(
+            "{counter:missing}{undefined}{undefined+1}",
+            &BTreeMap::new(),
+            None,
+        );
+        assert_eq!(
+            references,
+            vec![
+                ("counter:missing", Reference::Dangling),
+                ("undefined", Reference::Dangling),
+                ("undefined+1", Reference::Dangling),
+            ]
+        );
+    }
+
+    #[test]
+    fn titles_are_truncated_at_the_length_limit() {
+        let exact = "a".repeat(MAX_TITLE_LENGTH);
+        assert_eq!(truncate_title(&exact), exact);
+        assert_eq!(truncate_title(&format!("{}b", exact)), exact);
+
+        // CJK characters take a single UTF-16 unit each
+        let cjk = "中".repeat(MAX_TITLE_LENGTH);
+        assert_eq!(truncate_title(&format!("{}文", cjk)), cjk);
+        assert_eq!(title_length(&truncate_title(&cjk)), MAX_TITLE_LENGTH);
+
+        assert_eq!(truncate_title("short"), "short");
+    }
+
+    #[test]
+    fn dates_are_converted_to_the_lunar_calendar() {
+        let lunar = |y, m, d| to_lunar(NaiveDate::from_ymd(y, m, d));
+        let date = |year, month, day, leap| {
+            Some(LunarDate {
+                year,
+                month,
+                day,
+                leap,
+            })
+        };
+        assert_eq!(lunar(1900, 1, 30), None);
+        assert_eq!(lunar(1900, 1, 31), date(1900, 1, 1, false));
+        assert_eq!(lunar(2023, 1, 22), date(2023, 1, 1, false));
+        assert_eq!(lunar(2024, 2, 9), date(2023, 12, 30, false));
+        assert_eq!(lunar(2024, 2, 10), date(2024, 1, 1, false));
+        assert_eq!(lunar(2024, 9, 17), date(2024, 8, 15, false));
+    }
+
+    #[test]
+    fn leap_months_follow_their_month() {
+        let lunar = |y, m, d| to_lunar(NaiveDate::from_ymd(y, m, d)).unwrap();
+        // 2023 has a leap second month
+        assert_eq!(lunar(2023, 3, 21).month, 2);
+        assert!(!lunar(2023, 3, 21).leap);
+        let leap = lunar(2023, 3, 22);
+        assert_eq!((leap.month, leap.day, leap.leap), (2, 1, true));
+        assert_eq!(leap.month_name(), "闰二");
+        let after = lunar(2023, 4, 20);
+        assert_eq!((after.month, after.day, after.leap), (3, 1, false));
+        // 2020 has a leap fourth month
+        let leap = lunar(2020, 5, 23);
+        assert_eq!((leap.month, leap.day, leap.leap), (4, 1, true));
+        assert_eq!(lunar(2020, 6, 21).month, 5);
+    }
+
+    #[test]
+    fn lunar_days_are_named() {
+        let name = |day| {
+            LunarDate {
+                year: 2024,
+                month: 1,
+                day,
+                leap: false,
+            }
+            .day_name()
+        };
+        assert_eq!(name(1), "初一");
+        assert_eq!(name(10), "初十");
+        assert_eq!(name(15), "十五");
+        assert_eq!(name(20), "二十");
+        assert_eq!(name(21), "廿一");
+        assert_eq!(name(30), "三十");
+    }
+
+    #[test]
+    fn segments_are_joined_with_the_exact_delimiter() {
+        let mut group = test_group();
+        group.title_segment = vec!["a".to_string(), "b".to_string()];
+        group.delimiter = " | ".to_string();
+        assert_eq!(group.assemble_title_template(), "a | b");
+        group.delimiter = String::new();
+        assert_eq!(group.assemble_title_template(), "ab");
+    }
+
+    fn refused(error_code: i32, retry_after: Option<i32>) -> TelegramResult<bool> {
+        serde_json::from_value(serde_json::json!({
+            "ok": false,
+            "error_code": error_code,
+            "description": "Refused",
+            "parameters": retry_after.map(|retry_after| serde_json::json!({ "retry_after": retry_after })),
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn rate_limits_are_waited_out() {
+        assert_eq!(retry_delay(&refused(429, Some(3)), 1), Some(3000));
+        assert_eq!(retry_delay(&refused(429, None), 1), Some(1000));
+        // Waiting longer than that would exceed the time the worker has
+        assert_eq!(
+            retry_delay(&refused(429, Some(MAX_RETRY_AFTER + 1)), 1),
+            None
+        );
+        assert_eq!(
+            retry_delay(&refused(429, Some(3)), MAX_TITLE_ATTEMPTS),
+            None
+        );
+    }
+
+    #[test]
+    fn server_errors_are_retried_with_backoff() {
+        assert_eq!(retry_delay(&refused(502, None), 1), Some(RETRY_BASE_DELAY));
+        assert_eq!(
+            retry_delay(&refused(502, None), 2),
+            Some(RETRY_BASE_DELAY * 2)
+        );
+        assert_eq!(retry_delay(&refused(502, None), MAX_TITLE_ATTEMPTS), None);
+    }
+
+    #[test]
+    fn refusals_are_not_retried() {
+        assert_eq!(retry_delay(&refused(403, None), 1), None);
+        assert_eq!(retry_delay(&refused(400, None), 1), None);
+    }
+
+    #[test]
+    fn local_time_follows_daylight_saving_transitions() {
+        let mut group = test_group();
+        group.timezone = "America/New_York".to_string();
+        let hour = |utc: &str| {
+            let time = group.get_time(utc.parse::<NaiveDateTime>().unwrap());
+            group.render_text("{strftime:%H:%M_%Z}", time).unwrap()
+        };
+        // Spring forward, 02:00 EST is skipped
+        assert_eq!(hour("2022-03-13T06:59:00"), "01:59_EST");
+        assert_eq!(hour("2022-03-13T07:00:00"), "03:00_EDT");
+        // Fall back, 01:00 happens twice
+        assert_eq!(hour("2022-11-06T05:30:00"), "01:30_EDT");
+        assert_eq!(hour("2022-11-06T06:30:00"), "01:30_EST");
+        assert_eq!(hour("2022-11-06T07:00:00"), "02:00_EST");
+    }
+
+    #[test]
+    fn stray_keys_are_skipped() {
+        let names = [
+            "group--1001234567890",
+            "group-42",
+            "grp",
+            "group-",
+            "group-abc",
+            "cursor-scheduled",
+            "backup-group--100",
+        ];
+        assert_eq!(
+            parse_group_keys(names.iter().map(|name| name.to_string())),
+            vec!["-1001234567890".to_string(), "42".to_string()]
+        );
+    }
+
+    #[test]
+    fn stable_titles_are_not_sent_again() {
+        let mut group = test_group();
+        group.last_title = "Stable".to_string();
+        assert_eq!(group.scheduled_update("Stable"), ScheduledUpdate::Unchanged);
+        assert_eq!(group.scheduled_update("Changed"), ScheduledUpdate::Apply);
+        group.enforce = true;
+        assert_eq!(
+            group.scheduled_update("Stable"),
+            ScheduledUpdate::CheckDrift
+        );
+        assert_eq!(group.scheduled_update("Changed"), ScheduledUpdate::Apply);
+    }
+
+    #[test]
+    fn original_record_is_rewritten_as_json() {
+        let (group, legacy) = deserialize_group(ORIGINAL_RECORD).unwrap();
+        assert!(legacy);
+        let record = serde_json::to_vec(&group).unwrap();
+        assert_eq!(record.first(), Some(&b'{'));
+        let (rewritten, legacy) = deserialize_group(&record).unwrap();
+        assert!(!legacy);
+        assert_eq!(
+            serde_json::to_value(&rewritten).unwrap(),
+            serde_json::to_value(&group).unwrap()
+        );
+        assert_eq!(rewritten.last_title, "摸鱼群 | 05月17日");
+    }
+
+    fn delimiters_layout_group() -> LegacyGroupV2 {
+        let mut legacy = LegacyGroupV2::from(LegacyGroupV1 {
+            enable: true,
+            id: ChatId(-100),
+            title_segment: vec!["a".to_string(), "%H".to_string()],
+            delimiter: " | ".to_string(),
+            last_title: "a · 12".to_string(),
+            timezone: "Asia/Shanghai".to_string(),
+            require_admin: true,
+            notify_user: None,
+        });
+        legacy.delimiters = vec![" · ".to_string()];
+        legacy.enforce = true;
+        legacy.number_format = NumberFormat::Cjk;
+        legacy.original_title = "Original".to_string();
+        legacy.active_hours = Some((8, 22));
+        legacy
+    }
+
+    #[test]
+    fn delimiters_layout_is_migrated() {
+        let data = bincode::serialize(&delimiters_layout_group()).unwrap();
+        let (group, legacy) = deserialize_group(&data).unwrap();
+        assert!(legacy);
+        assert_eq!(group.delimiters, vec![" · "]);
+        assert!(group.enforce);
+        assert_eq!(group.number_format, NumberFormat::Cjk);
+        assert_eq!(group.original_title, "Original");
+        assert_eq!(group.active_hours, Some((8, 22)));
+        assert_eq!(group.last_title, "a · 12");
+    }
+
+    #[test]
+    fn delimiters_layout_may_end_before_appended_fields() {
+        // Saved when `enforce` was the last field
+        let data = bincode::serialize(&(
+            true,
+            ChatId(-100),
+            vec!["a", "%H"],
+            " | ",
+            vec![" · "],
+            "a · 12",
+            "Asia/Shanghai",
+            true,
+            None::<UserId>,
+            true,
+        ))
+        .unwrap();
+        let (group, legacy) = deserialize_group(&data).unwrap();
+        assert!(legacy);
+        assert_eq!(group.delimiters, vec![" · "]);
+        assert!(group.enforce);
+        assert_eq!(group.number_format, NumberFormat::Plain);
+        assert_eq!(group.original_title, "");
+        assert_eq!(group.active_hours, None);
+
+        // Ending in the middle of `timezone`
+        assert!(deserialize_group(&data[..data.len() - 5]).is_err());
+        // Going on after the last field
+        let mut data = bincode::serialize(&delimiters_layout_group()).unwrap();
+        data.push(0);
+        assert!(deserialize_group(&data).is_err());
+    }
+
+    #[test]
+    fn version_1_record_is_upgraded_step_by_step() {
+        let legacy = LegacyGroupV1 {
+            enable: true,
+            id: ChatId(-100),
+            title_segment: vec!["a".to_string()],
+            delimiter: " · ".to_string(),
+            last_title: "a".to_string(),
+            timezone: "UTC".to_string(),
+            require_admin: true,
+            notify_user: Some(UserId(7)),
+        };
+        let data = bincode::serialize(&legacy).unwrap();
+        let stored = decode_group(&data).unwrap();
+        assert!(matches!(stored, StoredGroup::V1(_)));
+        let (group, migrated) = migrate(stored);
+        assert!(migrated);
+        assert_eq!(group.schema_version, CURRENT_SCHEMA_VERSION);
+        assert_eq!(group.notify_user, Some(UserId(7)));
+        assert_eq!(group.delimiter, " · ");
+        assert!(group.delimiters.is_empty());
+        assert_eq!(group.original_title, "");
+        assert_eq!(group.week_start, WeekStart::Monday);
+    }
+
+    #[test]
+    fn json_records_are_stamped_with_the_current_version() {
+        let mut group = test_group();
+        group.schema_version = 1;
+        let record = serde_json::to_vec(&group).unwrap();
+        let (group, migrated) = deserialize_group(&record).unwrap();
+        assert!(migrated);
+        assert_eq!(group.schema_version, CURRENT_SCHEMA_VERSION);
+
+        let record = serde_json::to_vec(&group).unwrap();
+        let (_, migrated) = deserialize_group(&record).unwrap();
+        assert!(!migrated);
+    }
+
+    #[test]
+    fn empty_template_is_reported_as_such() {
+        let mut group = test_group();
+        group.clear_title_template();
+        assert!(group.is_template_empty());
+        let error = group
+            .render_local(local_time("UTC", "2022-05-16T18:30:00"))
+            .unwrap_err();
+        assert_eq!(error.to_string(), "Template is empty");
+        // It's a mistake in the settings, the bot regaining its rights doesn't make it usable
+        group.auto_reenable_on_promotion = true;
+        group.disabled_by_permission = true;
+        assert!(!group.should_reenable());
+    }
+
+    #[test]
+    fn iso_weeks_cross_the_year_boundary() {
+        let week = |y, m, d| week_of_year(NaiveDate::from_ymd(y, m, d), WeekStart::Monday);
+        // 2021-01-01 is a Friday, still in the last week of 2020
+        assert_eq!(week(2020, 12, 31), 53);
+        assert_eq!(week(2021, 1, 1), 53);
+        assert_eq!(week(2021, 1, 3), 53);
+        assert_eq!(week(2021, 1, 4), 1);
+        // 2024-12-30 is a Monday, already in the first week of 2025
+        assert_eq!(week(2024, 12, 29), 52);
+        assert_eq!(week(2024, 12, 30), 1);
+        assert_eq!(week(2025, 1, 1), 1);
+    }
+
+    #[test]
+    fn sunday_weeks_restart_on_new_year() {
+        let week = |y, m, d| week_of_year(NaiveDate::from_ymd(y, m, d), WeekStart::Sunday);
+        assert_eq!(week(2020, 12, 31), 53);
+        assert_eq!(week(2021, 1, 1), 1);
+        assert_eq!(week(2021, 1, 2), 1);
+        assert_eq!(week(2021, 1, 3), 2);
+        assert_eq!(week(2022, 12, 31), 53);
+        assert_eq!(week(2023, 1, 1), 1);
+        assert_eq!(week(2024, 12, 31), 53);
+    }
+
+    #[test]
+    fn week_start_follows_the_group() {
+        let mut group = test_group();
+        let time = local_time("UTC", "2021-01-03T12:00:00");
+        assert_eq!(group.render_text("{week_of_year}", time).unwrap(), "53");
+        group.week_start = WeekStart::Sunday;
+        assert_eq!(group.render_text("{week_of_year}", time).unwrap(), "2");
+    }
+
+    #[test]
+    fn next_year_rolls_over_on_new_year() {
+        let group = test_group();
+        let render = |time| group.render_text("{Y}-{Y+1}-{Y-1}", time).unwrap();
+        assert_eq!(
+            render(local_time("UTC", "2022-12-31T23:59:59")),
+            "2022-2023-2021"
+        );
+        assert_eq!(
+            render(local_time("UTC", "2023-01-01T00:00:00")),
+            "2023-2024-2022"
+        );
+        // Still December 31st in UTC, but New Year already in Shanghai
+        assert_eq!(
+            render(local_time("Asia/Shanghai", "2022-12-31T16:00:00")),
+            "2023-2024-2022"
+        );
+    }
+
+    #[test]
+    fn arithmetic_placeholders_are_parsed() {
+        assert_eq!(parse_arithmetic("Y+1"), Some(("Y", '+', 1)));
+        assert_eq!(parse_arithmetic("yeshu*2"), Some(("yeshu", '*', 2)));
+        assert_eq!(
+            parse_arithmetic("days_until-10"),
+            Some(("days_until", '-', 10))
+        );
+        assert_eq!(parse_arithmetic("Y+"), None);
+        assert_eq!(parse_arithmetic("+1"), None);
+        assert_eq!(parse_arithmetic("Y"), None);
+    }
+
+    #[test]
+    fn every_specifier_formats() {
+        let group = test_group();
+        let time = local_time("Asia/Shanghai", "2022-05-16T18:30:05");
+        let specifiers = DATE_SPECIFIERS
+            .iter()
+            .chain(TIME_SPECIFIERS)
+            .chain(ON_DEMAND_SPECIFIERS);
+        for specifier in specifiers {
+            let formatted = format_strftime(&time, &format!("%{}", specifier)).unwrap();
+            assert!(!formatted.is_empty(), "%{} formats to nothing", specifier);
+            let rendered = group
+                .render_text(&format!("{{{}}}", specifier), time)
+                .unwrap();
+            assert_eq!(
+                rendered, formatted,
+                "{{{}}} differs from %{}",
+                specifier, specifier
+            );
+        }
     }
 }
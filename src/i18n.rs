@@ -0,0 +1,1073 @@
+use std::collections::HashMap;
+use std::fmt::{self, Display};
+
+use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
+
+/// Language of the replies of the bot
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub enum Language {
+    #[default]
+    Zh,
+    En,
+}
+
+impl Language {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name.to_lowercase().as_str() {
+            "zh" => Some(Language::Zh),
+            "en" => Some(Language::En),
+            _ => None,
+        }
+    }
+}
+
+impl Display for Language {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Language::Zh => write!(f, "zh"),
+            Language::En => write!(f, "en"),
+        }
+    }
+}
+
+/// Looks up the translation of a reply, the Chinese source text doubles as the key and is
+/// returned as-is when there is no translation
+pub fn translate(language: Language, text: &str) -> &str {
+    match language {
+        Language::Zh => text,
+        Language::En => ENGLISH.get(text).copied().unwrap_or(text),
+    }
+}
+
+/// Substitutes `{}` in a translated format string, `{{` and `}}` are unescaped like `format!`
+pub fn fill(template: &str, args: &[&dyn Display]) -> String {
+    let mut result = String::with_capacity(template.len());
+    let mut args = args.iter();
+    let mut chars = template.chars().peekable();
+    while let Some(c) = chars.next() {
+        match (c, chars.peek()) {
+            ('{', Some('{')) | ('}', Some('}')) => {
+                chars.next();
+                result.push(c);
+            }
+            ('{', Some('}')) => {
+                chars.next();
+                if let Some(arg) = args.next() {
+                    result.push_str(&arg.to_string());
+                }
+            }
+            _ => result.push(c),
+        }
+    }
+    result
+}
+
+/// Translates a reply to the given language, formatting it like `format!` if there are arguments
+#[macro_export]
+macro_rules! tr {
+    ($language:expr, $text:expr) => {
+        $crate::i18n::translate($language, $text)
+    };
+    ($language:expr, $text:expr, $($arg:expr),+ $(,)?) => {
+        $crate::i18n::fill(
+            $crate::i18n::translate($language, $text),
+            &[$(&$arg as &dyn std::fmt::Display),+],
+        )
+    };
+}
+
+lazy_static! {
+    static ref ENGLISH: HashMap<&'static str, &'static str> = [
+        (
+            "无效分隔符，使用该分隔符后标题将超出 {} 的长度限制",
+            "Invalid delimiter, the title would exceed the length limit of {} with it",
+        ),
+        (
+            "使用该分隔符后标题将超出 {} 的长度限制，将按照长度策略 {} 处理",
+            "The title would exceed the length limit of {} with this delimiter, it will be handled by the {} length policy",
+        ),
+        (
+            "群 {} 的标题已由 {} 更改为 {}",
+            "The title of group {} was changed from {} to {}",
+        ),
+        (
+            "无效模板，未知的占位符： {}",
+            "Invalid template, unknown placeholders: {}",
+        ),
+        (
+            "无效模板，该模板在 {} 时无法渲染： {}",
+            "Invalid template, it can't be rendered at {}: {}",
+        ),
+        (
+            "标题模板已被更改至： {}",
+            "Title template changed to: {}",
+        ),
+        (
+            "\n将在下次定时任务时应用，使用 /apply 立即应用",
+            "\nIt will be applied by the next scheduled run, use /apply to apply it now",
+        ),
+        (
+            "注意：新片段与相邻片段相同，重复的片段将被合并",
+            "Note: the new segment is the same as its neighbor, duplicated segments will be collapsed",
+        ),
+        (
+            "注意：新片段与相邻片段相同，可使用 /set_dedupe on 合并重复的片段",
+            "Note: the new segment is the same as its neighbor, use /set_dedupe on to collapse duplicated segments",
+        ),
+        (
+            r#"当前标题: {}
+           群 ID: {}
+           启用自动更改: {}
+           等待应用: {}
+           上次更新: {}
+           标题模板: {}
+           标题片段: {}
+           分隔符: {}
+           循环分隔符: {}
+           时区: {}
+           需要管理权限: {}
+           强制标题: {}
+           双向文本隔离: {}
+           数字格式: {}
+           标题语言: {}
+           超长标题处理: {}
+           计划中的模板: {}
+           模板来源: {}
+           固定标题: {}
+           计数器: {}"#,
+            r#"Current title: {}
+           Group ID: {}
+           Automatic updates: {}
+           Waiting to be applied: {}
+           Last updated: {}
+           Title template: {}
+           Title segments: {}
+           Delimiter: {}
+           Cycling delimiters: {}
+           Timezone: {}
+           Admins only: {}
+           Enforce title: {}
+           Bidi isolation: {}
+           Number format: {}
+           Title language: {}
+           Overlong titles: {}
+           Scheduled templates: {}
+           Template source: {}
+           Frozen title: {}
+           Counters: {}"#,
+        ),
+        (
+            "已启用自动标题更改，当前标题模板为： {}",
+            "Automatic title updates enabled, the current template is: {}",
+        ),
+        (
+            "自动标题更改未启用，请先使用 /enable 启用",
+            "Automatic title updates are disabled, use /enable to enable them first",
+        ),
+        (
+            "已应用标题： {}",
+            "Title applied: {}",
+        ),
+        (
+            "没有记录本群的原始标题",
+            "The original title of this group wasn't recorded",
+        ),
+        (
+            "已恢复原始标题并关闭自动更改： {}",
+            "Original title restored and automatic updates disabled: {}",
+        ),
+        (
+            "模板已恢复为原始标题并关闭自动更改，但无法更改群标题，请检查 bot 的权限： {}",
+            "The template was reset to the original title and automatic updates disabled, but the title couldn't be changed, please check the permissions of the bot: {}",
+        ),
+        (
+            "没有可以恢复的标题",
+            "There is no title to restore",
+        ),
+        (
+            "已恢复标题： {}",
+            "Title restored: {}",
+        ),
+        (
+            "\n自动更改仍在启用，模板将在下次定时任务时重新应用，可使用 /freeze 固定标题",
+            "\nAutomatic updates are still enabled, the template will be applied again by the next scheduled run, use /freeze to keep the title",
+        ),
+        (
+            "已禁用自动标题更改",
+            "Automatic title updates disabled",
+        ),
+        (
+            "无效命令，没有发现新的标题模板",
+            "Invalid command, no new title template found",
+        ),
+        (
+            "{}\n可将以上模板用于 /set_template，使用 /flatten confirm 将标题片段合并为一个",
+            "{}\nThe template above can be used with /set_template, use /flatten confirm to merge the segments into one",
+        ),
+        (
+            "本群链接了模板来源，请先使用 /unlink_source 取消链接",
+            "This group is linked to a template source, use /unlink_source to unlink it first",
+        ),
+        (
+            "简介模板已变更，当前渲染结果：\n{}",
+            "Description template changed, it currently renders as:\n{}",
+        ),
+        (
+            "\n将在下次定时任务时应用",
+            "\nIt will be applied by the next scheduled run",
+        ),
+        (
+            "无效模板，简介不能超过 {} 个字符： {}",
+            "Invalid template, the description can't be longer than {} characters: {}",
+        ),
+        (
+            "已停止更新群简介",
+            "The group description is no longer updated",
+        ),
+        (
+            "无效命令，请使用 /set_template_at YYYY-MM-DD 标题模板",
+            "Invalid command, use /set_template_at YYYY-MM-DD template",
+        ),
+        (
+            "无效命令，无法解析日期，请使用 YYYY-MM-DD 格式",
+            "Invalid command, unable to parse the date, please use the YYYY-MM-DD format",
+        ),
+        (
+            "无效命令，日期必须晚于今天",
+            "Invalid command, the date has to be after today",
+        ),
+        (
+            "标题模板将于 {} 更改至： {}",
+            "The title template will change on {} to: {}",
+        ),
+        (
+            "无效命令，没有发现新的分隔符",
+            "Invalid command, no new delimiter found",
+        ),
+        (
+            "模板在 {} 时无法渲染，未更改目标日期： {}",
+            "The template can't be rendered at {}, the target date is unchanged: {}",
+        ),
+        (
+            "目标日期已设为 {}，距今 {} 天",
+            "Target date set to {}, {} days from now",
+        ),
+        (
+            "已清除目标日期",
+            "Target date removed",
+        ),
+        (
+            "无效命令，请提供 0 到 {} 之间的分钟数，0 为每次定时任务都更新",
+            "Invalid command, please give a number of minutes between 0 and {}, 0 updates on every scheduled run",
+        ),
+        (
+            "标题将在每次定时任务时更新",
+            "The title will be updated on every scheduled run",
+        ),
+        (
+            "标题将每隔 {} 分钟更新一次",
+            "The title will be updated every {} minutes",
+        ),
+        (
+            "无效命令，没有发现新的时区名称",
+            "Invalid command, no new timezone found",
+        ),
+        (
+            "无效命令，无法解析时区名称",
+            "Invalid command, unable to parse the timezone",
+        ),
+        (
+            "时区已变更至：{}",
+            "Timezone changed to: {}",
+        ),
+        (
+            "无效命令，请使用 plain、grouped 或 cjk",
+            "Invalid command, please use plain, grouped or cjk",
+        ),
+        (
+            "无效命令，请使用 en、zh、ja 或 fr",
+            "Invalid command, please use en, zh, ja or fr",
+        ),
+        (
+            "无效命令，请使用 error、truncate 或 skip",
+            "Invalid command, please use error, truncate or skip",
+        ),
+        (
+            "超长标题处理方式已变更至：{}",
+            "Overlong titles are now handled with: {}",
+        ),
+        (
+            "{}\n长度: {} / {}",
+            "{}\nLength: {} / {}",
+        ),
+        (
+            "无法渲染模板： {}",
+            "Unable to render the template: {}",
+        ),
+        (
+            "标题超出长度限制，将被截断为： {}",
+            "The title exceeds the length limit and will be cut down to: {}",
+        ),
+        (
+            "将应用标题： {}",
+            "The title to be applied: {}",
+        ),
+        (
+            "标题超出 {} 的长度限制，将跳过更新并保留当前标题： {}",
+            "The title exceeds the length limit of {}, it will be skipped and the current title kept: {}",
+        ),
+        (
+            "无法应用标题： {}",
+            "Unable to apply the title: {}",
+        ),
+        (
+            "无效命令，请提供要测量的文本",
+            "Invalid command, please give the text to measure",
+        ),
+        (
+            "{}\n显示宽度: {}\n字符数: {}\nUTF-16 长度: {} / {}",
+            "{}\nDisplay width: {}\nCharacters: {}\nUTF-16 length: {} / {}",
+        ),
+        (
+            "日期",
+            "Date",
+        ),
+        (
+            "时间",
+            "Time",
+        ),
+        (
+            "其他",
+            "Other",
+        ),
+        (
+            "{}\n带参数的占位符:\n{{strftime:<格式>}}\n{{progress_bar[:day|year][:宽度]}}\n{{counter:<名称>}}",
+            "{}\nPlaceholders with arguments:\n{{strftime:<format>}}\n{{progress_bar[:day|year][:width]}}\n{{counter:<name>}}",
+        ),
+        (
+            "模板中没有占位符",
+            "There are no placeholders in the template",
+        ),
+        (
+            "✅ {{{}}} 内置",
+            "✅ {{{}}} built-in",
+        ),
+        (
+            "✅ {{{}}} 计数器",
+            "✅ {{{}}} counter",
+        ),
+        (
+            "❌ {{{}}} 未定义",
+            "❌ {{{}}} undefined",
+        ),
+        (
+            "{}\n共 {} 个占位符，{} 个未定义",
+            "{}\n{} placeholders, {} undefined",
+        ),
+        (
+            "无效命令，没有发现要固定的标题",
+            "Invalid command, no title to freeze found",
+        ),
+        (
+            "无效命令，标题将超出 {} 的长度限制",
+            "Invalid command, the title would exceed the length limit of {}",
+        ),
+        (
+            "标题已固定为： {}\n标题模板将保留，使用 /unfreeze 恢复",
+            "Title frozen to: {}\nThe template is kept, use /unfreeze to resume",
+        ),
+        (
+            "标题没有被固定",
+            "The title isn't frozen",
+        ),
+        (
+            "计数器 {} 的值为： {}",
+            "Counter {} is now: {}",
+        ),
+        (
+            "无效的计数器名称，只能包含字母、数字和下划线，且不超过 32 个字符",
+            "Invalid counter name, it may only contain letters, digits and underscores and be at most 32 characters long",
+        ),
+        (
+            "每个群最多只能有 {} 个计数器",
+            "A group can have at most {} counters",
+        ),
+        (
+            "无效命令，请使用 /set_named_template <名称> [模板]",
+            "Invalid command, use /set_named_template <name> [template]",
+        ),
+        (
+            "无效的模板名称，只能包含字母、数字和下划线，且不超过 32 个字符",
+            "Invalid template name, it may only contain letters, digits and underscores and be at most 32 characters long",
+        ),
+        (
+            "每个群最多只能保存 {} 个模板",
+            "A group can save at most {} templates",
+        ),
+        (
+            "已保存模板 {}： {}",
+            "Template {} saved: {}",
+        ),
+        (
+            "还没有保存的模板，请先使用 /set_named_template",
+            "There are no saved templates yet, use /set_named_template first",
+        ),
+        (
+            "未找到该模板，已保存的模板： {}",
+            "Template not found, the saved templates are: {}",
+        ),
+        (
+            "无效命令，请使用 /counter_set <名称> <整数>",
+            "Invalid command, use /counter_set <name> <integer>",
+        ),
+        (
+            "无效命令，没有发现计数器名称",
+            "Invalid command, no counter name found",
+        ),
+        (
+            "计数器 {} 不存在",
+            "Counter {} doesn't exist",
+        ),
+        (
+            "无效命令，没有发现新的标题片段",
+            "Invalid command, no new title segment found",
+        ),
+        (
+            "已订阅群 {} 的标题变更通知",
+            "Subscribed to the title changes of group {}",
+        ),
+        (
+            "无法向你发送私聊消息，请先私聊 bot 并发送 /start",
+            "Unable to send you a private message, please start a private chat with the bot and send /start first",
+        ),
+        (
+            "已订阅标题变更通知，标题更改时将通过私聊通知你",
+            "Subscribed to title changes, you will be notified in a private message",
+        ),
+        (
+            "无效命令，请提供源群组的 ID",
+            "Invalid command, please give the ID of the source group",
+        ),
+        (
+            "无效命令，不能链接到本群",
+            "Invalid command, a group can't be linked to itself",
+        ),
+        (
+            "你不是源群组的管理员",
+            "You are not an admin of the source group",
+        ),
+        (
+            "找不到源群组，请先在源群组中使用 bot",
+            "Source group not found, please use the bot in the source group first",
+        ),
+        (
+            "已链接至群 {} 的标题模板： {}",
+            "Linked to the title template of group {}: {}",
+        ),
+        (
+            "本群没有链接模板来源",
+            "This group isn't linked to a template source",
+        ),
+        (
+            "已取消链接，并保留来源的标题模板： {}",
+            "Unlinked, the title template of the source is kept: {}",
+        ),
+        (
+            "已取消链接，模板来源已不存在，保留本群的标题模板： {}",
+            "Unlinked, the template source doesn't exist anymore, the title template of this group is kept: {}",
+        ),
+        (
+            "已取消标题变更通知",
+            "Unsubscribed from title changes",
+        ),
+        (
+            "无效命令，请使用 on 或 off",
+            "Invalid command, please use on or off",
+        ),
+        (
+            "已启用强制标题，手动更改的标题将在下次定时任务时被恢复",
+            "Title enforcement enabled, manual renames will be reverted by the next scheduled run",
+        ),
+        (
+            "已关闭强制标题，手动更改的标题将保留至模板下次变化",
+            "Title enforcement disabled, manual renames are kept until the template changes",
+        ),
+        (
+            "无效命令，失败提示不能超过 {} 个字符",
+            "Invalid command, the failure message can't be longer than {} characters",
+        ),
+        (
+            "失败提示已变更至：{}",
+            "Failure message changed to: {}",
+        ),
+        (
+            "公告模板已变更，当前渲染结果：\n{}",
+            "Announcement template changed, it currently renders as:\n{}",
+        ),
+        (
+            "无效模板： {}",
+            "Invalid template: {}",
+        ),
+        (
+            "已清除公告模板",
+            "Announcement template removed",
+        ),
+        (
+            "已关闭公告",
+            "Announcements disabled",
+        ),
+        (
+            "已启用公告，标题变更后将发送并置顶公告",
+            "Announcements enabled, they will be sent and pinned after the title changes",
+        ),
+        (
+            "\n尚未设置公告模板，请使用 /set_announcement_template",
+            "\nThere is no announcement template yet, use /set_announcement_template",
+        ),
+        (
+            "\n注意：bot 没有置顶消息的权限，公告将只发送而不置顶",
+            "\nNote: the bot isn't allowed to pin messages, announcements will be sent without being pinned",
+        ),
+        (
+            "无法启用双向文本隔离，启用后标题将超出 {} 的长度限制",
+            "Unable to enable bidi isolation, the title would exceed the length limit of {} with it",
+        ),
+        (
+            "已启用双向文本隔离，每段标题的文字方向将互不影响",
+            "Bidi isolation enabled, the text direction of each segment won't affect the others",
+        ),
+        (
+            "已关闭双向文本隔离",
+            "Bidi isolation disabled",
+        ),
+        (
+            "已启用自动恢复，因权限不足而停用的自动标题更改将在 bot 重新获得权限后自动启用",
+            "Automatic re-enabling enabled, updates turned off for lack of permissions will be turned back on once the bot regains them",
+        ),
+        (
+            "已关闭自动恢复",
+            "Automatic re-enabling disabled",
+        ),
+        (
+            "最近没有发生错误",
+            "No errors happened recently",
+        ),
+        (
+            "最近的错误:\n{}",
+            "Recent errors:\n{}",
+        ),
+        (
+            "还没有更改记录",
+            "There are no changes yet",
+        ),
+        (
+            "最近的更改:\n{}",
+            "Recent changes:\n{}",
+        ),
+        (
+            "未启用缓存，管理员身份总是实时获取",
+            "Caching is disabled, admin status is always fetched live",
+        ),
+        (
+            "已清除本群的管理员缓存，下次操作时将重新获取管理员身份",
+            "The admin cache of this group was cleared, admin status will be fetched again",
+        ),
+        (
+            "还没有定时任务的运行记录",
+            "The scheduled job hasn't run yet",
+        ),
+        (
+            r#"上次定时任务: {} UTC
+           耗时: {} ms
+           已处理: {}
+           已更新: {}
+           未变化: {}
+           已禁用: {}
+           未到更新间隔: {}
+           无效: {}
+           失败: {}
+           已完成: {}"#,
+            r#"Last scheduled run: {} UTC
+           Took: {} ms
+           Processed: {}
+           Updated: {}
+           Unchanged: {}
+           Disabled: {}
+           Not due: {}
+           Invalid: {}
+           Failed: {}
+           Completed: {}"#,
+        ),
+        (
+            "bot 在本群的权限:\n{}",
+            "Permissions of the bot in this group:\n{}",
+        ),
+        (
+            "未发现问题",
+            "No problems found",
+        ),
+        (
+            "已修复:\n{}",
+            "Repaired:\n{}",
+        ),
+        (
+            "点击发送",
+            "Tap to send",
+        ),
+        (
+            "无法渲染模板",
+            "Unable to render the template",
+        ),
+        (
+            "bot 已重新获得权限，已自动恢复标题更改： {}",
+            "The bot regained its permissions, automatic title updates resumed: {}",
+        ),
+        (
+            "bot 已重新获得权限，但仍未能成功更改群标题，请检查 bot 帐号权限",
+            "The bot regained its permissions but still couldn't change the title, please check the permissions of the bot account",
+        ),
+        (
+            "复读消息",
+            "Repeat the message",
+        ),
+        (
+            "显示 bot 版本",
+            "Show the version of the bot",
+        ),
+        (
+            "列出所有命令",
+            "List all commands",
+        ),
+        (
+            "显示本群的设置",
+            "Show the settings of this group",
+        ),
+        (
+            "启用自动更改标题",
+            "Enable automatic title updates",
+        ),
+        (
+            "立即应用标题模板",
+            "Apply the title template now",
+        ),
+        (
+            "关闭自动更改标题",
+            "Disable automatic title updates",
+        ),
+        (
+            "恢复上一个标题",
+            "Restore the previous title",
+        ),
+        (
+            "恢复本群的原始标题并关闭自动更改",
+            "Restore the original title and disable automatic updates",
+        ),
+        (
+            "设置标题模板",
+            "Set the title template",
+        ),
+        (
+            "在指定日期更改标题模板",
+            "Change the title template on a date",
+        ),
+        (
+            "保存一个命名模板",
+            "Save a named template",
+        ),
+        (
+            "切换到已保存的模板",
+            "Switch to a saved template",
+        ),
+        (
+            "设置群简介模板",
+            "Set the group description template",
+        ),
+        (
+            "将所有片段合并为一个模板",
+            "Merge all segments into one template",
+        ),
+        (
+            "设置片段之间的分隔符",
+            "Set the delimiter between segments",
+        ),
+        (
+            "设置轮流使用的多个分隔符",
+            "Set several delimiters used in turn",
+        ),
+        (
+            "恢复默认分隔符",
+            "Restore the default delimiter",
+        ),
+        (
+            "设置时区",
+            "Set the timezone",
+        ),
+        (
+            "设置倒数的目标日期",
+            "Set the target date of the countdown",
+        ),
+        (
+            "设置更新间隔",
+            "Set the update interval",
+        ),
+        (
+            "恢复默认时区",
+            "Restore the default timezone",
+        ),
+        (
+            "设置数字格式",
+            "Set the number format",
+        ),
+        (
+            "设置标题语言",
+            "Set the title language",
+        ),
+        (
+            "设置超长标题的处理方式",
+            "Set how overlong titles are handled",
+        ),
+        (
+            "预览模板渲染结果",
+            "Preview the rendered template",
+        ),
+        (
+            "预览将要应用的标题",
+            "Preview the title to be applied",
+        ),
+        (
+            "测量文本的显示宽度和长度",
+            "Measure the display width and length of text",
+        ),
+        (
+            "列出所有占位符及当前的值",
+            "List all placeholders with their current values",
+        ),
+        (
+            "检查模板中未定义的占位符",
+            "Check the template for undefined placeholders",
+        ),
+        (
+            "设置计数器",
+            "Set a counter",
+        ),
+        (
+            "计数器加一",
+            "Add one to a counter",
+        ),
+        (
+            "重置计数器",
+            "Reset a counter",
+        ),
+        (
+            "固定标题",
+            "Freeze the title",
+        ),
+        (
+            "取消固定标题",
+            "Unfreeze the title",
+        ),
+        (
+            "在模板末尾添加片段",
+            "Add a segment to the end of the template",
+        ),
+        (
+            "在模板开头添加片段",
+            "Add a segment to the start of the template",
+        ),
+        (
+            "移除模板末尾的片段",
+            "Remove the segment at the end of the template",
+        ),
+        (
+            "移除模板开头的片段",
+            "Remove the segment at the start of the template",
+        ),
+        (
+            "设置是否强制恢复标题",
+            "Set whether the title is enforced",
+        ),
+        (
+            "设置重新获得权限后是否自动启用",
+            "Set whether updates resume after permissions are regained",
+        ),
+        (
+            "设置是否隔离双向文本",
+            "Set whether segments are bidi isolated",
+        ),
+        (
+            "设置是否合并重复片段",
+            "Set whether duplicated segments are collapsed",
+        ),
+        (
+            "设置无法更改标题时的提示",
+            "Set the message sent when the title can't be changed",
+        ),
+        (
+            "设置公告模板",
+            "Set the announcement template",
+        ),
+        (
+            "设置是否发送并置顶公告",
+            "Set whether announcements are sent and pinned",
+        ),
+        (
+            "列出最近的 API 错误",
+            "List recent API errors",
+        ),
+        (
+            "列出最近的更改记录",
+            "List recent changes",
+        ),
+        (
+            "显示 bot 的权限",
+            "Show the permissions of the bot",
+        ),
+        (
+            "显示上次定时任务的统计",
+            "Show the counters of the last scheduled run",
+        ),
+        (
+            "刷新管理员缓存",
+            "Refresh the admin cache",
+        ),
+        (
+            "修复无效的设置",
+            "Repair invalid settings",
+        ),
+        (
+            "订阅标题更改通知",
+            "Subscribe to title change notifications",
+        ),
+        (
+            "取消订阅标题更改通知",
+            "Unsubscribe from title change notifications",
+        ),
+        (
+            "使用另一个群的模板",
+            "Use the template of another group",
+        ),
+        (
+            "停止使用另一个群的模板",
+            "Stop using the template of another group",
+        ),
+        (
+            "发生什么事了？未能成功更改群标题，请检查 bot 帐号权限",
+            "What happened? The title couldn't be changed, please check the permissions of the bot account",
+        ),
+        (
+            "bot 不是管理员，无法更改群标题",
+            "The bot isn't an admin and can't change the title",
+        ),
+        (
+            "修改群组信息",
+            "Change group info",
+        ),
+        (
+            "删除消息",
+            "Delete messages",
+        ),
+        (
+            "封禁成员",
+            "Ban users",
+        ),
+        (
+            "邀请成员",
+            "Invite users",
+        ),
+        (
+            "置顶消息",
+            "Pin messages",
+        ),
+        (
+            "添加管理员",
+            "Add admins",
+        ),
+        (
+            "发布消息",
+            "Post messages",
+        ),
+        (
+            "编辑消息",
+            "Edit messages",
+        ),
+        (
+            "无效命令，请使用 zh 或 en",
+            "Invalid command, please use zh or en",
+        ),
+        (
+            "回复语言已变更至：{}",
+            "Reply language changed to: {}",
+        ),
+        (
+            "设置回复语言",
+            "Set the reply language",
+        ),
+        (
+            "标题模板为空，已恢复为群标题",
+            "The title template was empty and has been reset to the group title",
+        ),
+        (
+            "时区无法解析，已重置为 UTC",
+            "The timezone couldn't be parsed and has been reset to UTC",
+        ),
+        (
+            "标题超出长度限制，已重置分隔符",
+            "The title exceeded the length limit, the delimiter has been reset",
+        ),
+        (
+            "标题仍超出长度限制，已恢复为群标题",
+            "The title still exceeded the length limit and has been reset to the group title",
+        ),
+        (
+            "无效命令，请使用 /move <位置> <新位置>",
+            "Invalid command, use /move <position> <new position>",
+        ),
+        (
+            "无效命令，位置必须在 1 到 {} 之间",
+            "Invalid command, the positions have to be between 1 and {}",
+        ),
+        ("移动模板中的片段", "Move a segment of the template"),
+        ("无", "None"),
+        ("从未", "Never"),
+        (
+            "无效命令，请使用 /edit <位置> <新片段>",
+            "Invalid command, use /edit <position> <new segment>",
+        ),
+        ("替换模板中的一个片段", "Replace a segment of the template"),
+        (
+            "已启用仅限管理员，只有管理员可以更改设置",
+            "Admin only enabled, only admins can change the settings",
+        ),
+        (
+            "已关闭仅限管理员，所有成员都可以更改标题模板，/reset 和 /set_admin_only 仍仅限管理员",
+            "Admin only disabled, every member can change the title template, /reset and /set_admin_only are still limited to admins",
+        ),
+        (
+            "设置是否仅限管理员更改设置",
+            "Set whether only admins can change the settings",
+        ),
+        (
+            "无效命令，请回复该用户的消息或提供用户 ID",
+            "Invalid command, please reply to a message of the user or give their user ID",
+        ),
+        ("当前的编辑者： {}", "Current editors: {}"),
+        ("允许非管理员更改设置", "Allow a non-admin to change the settings"),
+        ("移除编辑者", "Remove an editor"),
+        (
+            "无效命令，请提供 0 到 23 之间的开始和结束小时，或使用 off 取消限制",
+            "Invalid command, please give the start and end hours between 0 and 23, or use off to remove the limit",
+        ),
+        (
+            "标题只会在 {}:00 到 {}:00 之间更新",
+            "The title will only be updated between {}:00 and {}:00",
+        ),
+        ("已取消更新时段限制", "The title can be updated at any hour"),
+        ("设置更新标题的时段", "Set the hours the title is updated in"),
+        ("导出本群的设置", "Export the settings of this group"),
+        ("无效的设置，无法解析 JSON： {}", "Invalid settings, unable to parse the JSON: {}"),
+        (
+            "无效命令，请在命令后附上 /export 导出的设置",
+            "Invalid command, please append the settings printed by /export",
+        ),
+        (
+            "已导入设置，当前标题模板为： {}",
+            "Settings imported, the current title template is: {}",
+        ),
+        ("导入本群的设置", "Import the settings of this group"),
+        ("匿名管理员", "Anonymous admin"),
+        ("频道", "Channel"),
+        (
+            "用户 ID: {}\n管理员: {}\n编辑者: {}\n需要管理权限: {}\n可以更改设置: {}",
+            "User ID: {}\nAdmin: {}\nEditor: {}\nAdmins only: {}\nCan change the settings: {}",
+        ),
+        ("显示你在本群的权限", "Show your permissions in this group"),
+        ("只有管理员可以使用该命令", "Only admins can use this command"),
+        (
+            "命令发送得太频繁了，请稍后再试",
+            "Commands are coming in too fast, please slow down",
+        ),
+        ("无法获取当前的群标题", "Unable to get the current group title"),
+        (
+            "当前的群标题为空，模板未被更改",
+            "The current group title is empty, the template is unchanged",
+        ),
+        ("将模板重置为当前的群标题", "Reset the template to the current group title"),
+        (
+            "标题模板为空，请先使用 /set_template 设置模板",
+            "The title template is empty, please set one with /set_template first",
+        ),
+        ("无效命令，请使用 sun 或 mon", "Invalid command, please use sun or mon"),
+        ("设置一周的第一天", "Set the first day of the week"),
+        ("<空>", "<empty>"),
+        (
+            "无效命令，备用标题不能超过 {} 个字符",
+            "Invalid command, the fallback title can't be longer than {} characters",
+        ),
+        ("备用标题已变更至：{}", "Fallback title changed to: {}"),
+        ("已移除备用标题", "Fallback title removed"),
+        (
+            "设置模板无法渲染时的备用标题",
+            "Set the title used when the template fails to render",
+        ),
+        (
+            "已停止自动更改标题，恢复权限后请使用 /enable 重新启用",
+            "Stopped changing the title automatically, use /enable once the bot has the rights again",
+        ),
+        (
+            "无效命令，无法解析时区名称，你是否想要：{}",
+            "Invalid command, unable to parse the timezone name, did you mean: {}",
+        ),
+        (
+            "当前时间: {}\n时区: {} (UTC{})",
+            "Current time: {}\nTimezone: {} (UTC{})",
+        ),
+        ("显示本群时区的当前时间", "Show the current time in the group's timezone"),
+        ("无效命令，没有发现新的标题", "Invalid command, no new title found"),
+        (
+            "无效命令，标题不能超过 {} 个字符",
+            "Invalid command, the title can't be longer than {} characters",
+        ),
+        (
+            "每个群最多只能添加 {} 个轮换标题",
+            "Each group can have at most {} rotation titles",
+        ),
+        ("已添加轮换标题，当前共 {} 个", "Rotation title added, there are {} now"),
+        ("\n使用 /set_rotate on 开始轮换", "\nUse /set_rotate on to start rotating"),
+        (
+            "已清空轮换标题，将恢复使用标题模板",
+            "Rotation titles cleared, the title template is used again",
+        ),
+        (
+            "轮换列表为空，请先使用 /add_rotation 添加标题",
+            "There are no rotation titles, please add some with /add_rotation first",
+        ),
+        (
+            "已启用标题轮换，每次定时任务将切换到下一个标题",
+            "Rotation enabled, every scheduled run switches to the next title",
+        ),
+        (
+            "已关闭标题轮换，将恢复使用标题模板",
+            "Rotation disabled, the title template is used again",
+        ),
+        ("添加轮换标题", "Add a title to rotate through"),
+        ("清空轮换标题", "Clear the rotation titles"),
+        ("设置是否轮换标题", "Set whether to rotate through the titles"),
+        ("测试 bot 能否更改群标题", "Test whether the bot can change the title"),
+        (
+            "测试成功，bot 可以更改群标题",
+            "Test passed, the bot can change the title",
+        ),
+        (
+            "bot 缺少「修改群组信息」权限，无法更改群标题",
+            "The bot lacks the \"Change group info\" right and cannot change the title",
+        ),
+        (
+            "Telegram 拒绝更改群标题: {}",
+            "Telegram refused to change the title: {}",
+        ),
+        ("测试失败 ({}): {}", "Test failed ({}): {}"),
+    ]
+    .into_iter()
+    .collect();
+}
@@ -1,24 +1,85 @@
 pub mod bot;
+pub mod cache;
 pub mod group;
+pub mod i18n;
 
 use cfg_if::cfg_if;
-use chrono_tz::Tz;
+use chrono::{Datelike, NaiveDate, NaiveDateTime, Timelike};
+use chrono_tz::{Tz, TZ_VARIANTS};
+use futures::stream::{self, StreamExt};
 use log::{error, info};
-use telegram_types::bot::methods::{ChatTarget, SendMessage};
-use telegram_types::bot::types::{ChatId, Message};
+use serde::{Deserialize, Serialize};
+use telegram_types::bot::inline_mode::{
+    AnswerInlineQuery, InlineQuery, InlineQueryResult, InlineQueryResultArticle,
+    InputMessageContent, InputTextMessageContent, ResultId,
+};
+use telegram_types::bot::methods::{ChatTarget, SendMessage, TelegramResult};
+use telegram_types::bot::types::{ChatId, ChatType, Message, ParseMode, User, UserId};
+use worker::kv::KvStore;
 use worker::{
     event, Date, Env, Error as WorkerError, Request, Response, Router, ScheduleContext,
     ScheduledEvent,
 };
 
-use bot::{Bot, WebhookReply};
-use group::{get_group_title, get_raw_chat_id, DataStore, Group};
+use bot::{
+    admin_cache_key, can_pin_messages, describe_permissions, gained_change_info, is_admin_status,
+    token_matches, Bot, ChatMemberUpdate, WebhookReply,
+};
+use group::{
+    check_references, check_title_length, display_width, get_group_title, get_raw_chat_id,
+    is_valid_name, next_cursor, render_template_at, title_length, to_local_time, DataStore,
+    FinalTitle, Group, GroupConfig, HistoryEntry, LengthPolicy, NumberFormat, Reference,
+    TemplateContext, TitleLocale, WeekStart, MAX_CONSECUTIVE_FAILURES, MAX_COUNTERS,
+    MAX_DESCRIPTION_LENGTH, MAX_FAILURE_MESSAGE_LENGTH, MAX_NAMED_TEMPLATES, MAX_ROTATION_ENTRIES,
+    MAX_TITLE_LENGTH, MAX_UPDATE_INTERVAL, SCHEDULED_JOB, SCHEDULED_SOURCE,
+};
+use i18n::{translate, Language};
 
+use std::borrow::Cow;
+use std::collections::BTreeMap;
 use std::num::ParseIntError;
 
 const DEFAULT_SECRET_TOKEN: &str = "API_TOKEN";
+// Sender of the messages anonymous admins send on behalf of the group, `GroupAnonymousBot`
+const ANONYMOUS_ADMIN_ID: UserId = UserId(1087968824);
 const VAR_KV_STORE: &str = "KV_STORE";
 const VAR_USERNAME: &str = "USERNAME";
+const VAR_CACHE_STORE: &str = "CACHE_KV_STORE";
+const VAR_OPERATOR_TOKEN: &str = "OPERATOR_TOKEN";
+const VAR_WEBHOOK_SECRET: &str = "WEBHOOK_SECRET";
+const VAR_DRY_RUN: &str = "DRY_RUN";
+const VAR_DEBUG_ERRORS: &str = "DEBUG_ERRORS";
+const MAX_TIMEZONE_SUGGESTIONS: usize = 5;
+const VAR_COMMAND_COOLDOWN: &str = "COMMAND_COOLDOWN";
+const VAR_COMMAND_PREFIX: &str = "COMMAND_PREFIX";
+// Seconds a chat has to wait between commands unless `COMMAND_COOLDOWN` says otherwise
+const DEFAULT_COMMAND_COOLDOWN: u64 = 3;
+// Commands that only read the settings and are never throttled
+const COOLDOWN_EXEMPT_COMMANDS: &[&str] = &[
+    "echo",
+    "start",
+    "help",
+    "status",
+    "whoami",
+    "export",
+    "now",
+    "preview",
+    "preview_final",
+    "width",
+    "list_specifiers",
+    "check_refs",
+    "errors",
+    "history",
+    "perms",
+    "metrics",
+];
+const AUTO_REENABLE_SOURCE: &str = "auto_reenable";
+const KEY_SCHEDULED_SUMMARY: &str = "scheduled-summary";
+// Milliseconds the scheduled job may spend before handing the rest over to the next run
+const SCHEDULED_TIME_BUDGET: u64 = 20_000;
+const VAR_SCHEDULED_CONCURRENCY: &str = "SCHEDULED_CONCURRENCY";
+// Groups processed at once by default, Workers allow six simultaneous outgoing connections
+const DEFAULT_SCHEDULED_CONCURRENCY: usize = 6;
 // const DEFAULT_CRON_PATH: &str = "/cron";
 
 cfg_if! {
@@ -37,27 +98,221 @@ pub fn return_message<S: AsRef<str>>(message: &Message, reply: S) -> Result<Resp
     ))
 }
 
+/// Replies with the text as a preformatted block
+pub fn return_code_block<S: AsRef<str>>(
+    message: &Message,
+    text: S,
+) -> Result<Response, WorkerError> {
+    let escaped = text
+        .as_ref()
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;");
+    Response::from_json(&WebhookReply::from(
+        SendMessage::new(
+            ChatTarget::Id(message.chat.id),
+            format!("<pre>{}</pre>", escaped),
+        )
+        .parse_mode(ParseMode::HTML)
+        .reply(message.message_id),
+    ))
+}
+
+/// Returns everything after the command token (`/command` or `/command@bot`) and the whitespace
+/// right after it, keeping any further whitespace as it may be meaningful (e.g. delimiters)
+pub fn get_raw_argument(text: &str) -> Option<&str> {
+    let text = text.trim_start();
+    let command_end = text.find(char::is_whitespace)?;
+    let mut remainder = text[command_end..].chars();
+    remainder.next();
+    Some(remainder.as_str()).filter(|argument| !argument.is_empty())
+}
+
+/// Returns the trimmed argument of the command, `None` if there is none
+pub fn get_argument(text: &str) -> Option<&str> {
+    get_raw_argument(text)
+        .map(str::trim)
+        .filter(|argument| !argument.is_empty())
+}
+
+/// Takes a delimiter wrapped in double quotes verbatim, since Telegram trims the messages. `""`
+/// is the empty delimiter.
+pub fn parse_delimiter(argument: &str) -> &str {
+    let trimmed = argument.trim();
+    match trimmed
+        .strip_prefix('"')
+        .and_then(|rest| rest.strip_suffix('"'))
+    {
+        Some(quoted) => quoted,
+        None => argument,
+    }
+}
+
+/// Shows the delimiter with its spaces, an empty one would be invisible otherwise
+fn describe_delimiter(delimiter: &str, language: Language) -> String {
+    if delimiter.is_empty() {
+        return tr!(language, "<空>").to_string();
+    }
+    format!("{:?}", delimiter)
+}
+
+/// Timezone names containing the input regardless of case, spaces are taken as underscores so
+/// `new york` finds `America/New_York`
+pub fn suggest_timezones(input: &str) -> Vec<&'static str> {
+    let needle = input.trim().replace(' ', "_").to_ascii_lowercase();
+    if needle.is_empty() {
+        return Vec::new();
+    }
+    TZ_VARIANTS
+        .iter()
+        .map(|timezone| timezone.name())
+        .filter(|name| name.to_ascii_lowercase().contains(&needle))
+        .take(MAX_TIMEZONE_SUGGESTIONS)
+        .collect()
+}
+
+pub fn parse_switch(arg: &str) -> Option<bool> {
+    match arg.trim().to_ascii_lowercase().as_str() {
+        "on" | "true" | "yes" | "1" => Some(true),
+        "off" | "false" | "no" | "0" => Some(false),
+        _ => None,
+    }
+}
+
+pub fn warn_title_too_long(message: &Message, language: Language) -> Result<Response, WorkerError> {
+    return_message(
+        message,
+        tr!(
+            language,
+            "无效分隔符，使用该分隔符后标题将超出 {} 的长度限制",
+            MAX_TITLE_LENGTH
+        ),
+    )
+}
+
 pub fn warn_group_only(message: &Message) -> Result<Response, WorkerError> {
     return_message(message, "This command is only allowed in group chats")
 }
 
+/// Language of the group the message was sent in, the default one outside of groups
+pub async fn reply_language(
+    message: &Message,
+    env: &Env,
+    bot: &Bot<'_>,
+) -> Result<Language, WorkerError> {
+    if get_group_title(&message.chat.kind).is_none() {
+        return Ok(Language::default());
+    }
+    let kv = bot.get_kv(env)?;
+    let store = DataStore::new(&kv);
+    let group = store
+        .load_group_or_create(&message.chat.id, &message.chat.kind)
+        .await;
+    Ok(group.language)
+}
+
 pub async fn check_permission(
     group: &Group,
     m: &Message,
     bot: &Bot<'_>,
+    env: &Env,
 ) -> Result<bool, WorkerError> {
-    if group.require_admin {
-        let user_id = m
-            .from
-            .clone()
-            .ok_or_else(|| {
-                WorkerError::RustError("Unable to retrieve user information".to_string())
-            })?
-            .id;
-        bot.is_admin(ChatTarget::Id(m.chat.id), user_id).await
-    } else {
-        Ok(true)
+    if !group.require_admin {
+        return Ok(true);
+    }
+    let is_editor = m
+        .from
+        .as_ref()
+        .map(|user| group.editors.contains(&user.id))
+        .unwrap_or(false);
+    if is_editor {
+        return Ok(true);
+    }
+    check_admin(m, bot, env).await
+}
+
+/// Checks whether the sender is an admin regardless of `require_admin`, for commands that
+/// shouldn't be opened up to every member
+pub async fn check_admin(m: &Message, bot: &Bot<'_>, env: &Env) -> Result<bool, WorkerError> {
+    match admin_to_check(m)? {
+        Some(user_id) => bot.is_admin_cached(env, m.chat.id, user_id).await,
+        None => Ok(true),
+    }
+}
+
+/// The sender whose admin status has to be looked up, `None` if only an admin could have sent
+/// the message
+fn admin_to_check(m: &Message) -> Result<Option<UserId>, WorkerError> {
+    match (&m.from, &m.chat.kind) {
+        // Only admins can send messages on behalf of the group
+        (Some(user), _) if user.id == ANONYMOUS_ADMIN_ID => Ok(None),
+        (Some(user), _) => Ok(Some(user.id)),
+        // Channel posts are signed by the channel rather than a user, and only admins can post
+        (None, ChatType::Channel { .. }) => Ok(None),
+        (None, _) => Err(WorkerError::RustError(
+            "Unable to retrieve user information".to_string(),
+        )),
+    }
+}
+
+/// Seconds between commands from `COMMAND_COOLDOWN`, falls back to the default if it's invalid
+fn command_cooldown(env: &Env) -> u64 {
+    match env.var(VAR_COMMAND_COOLDOWN) {
+        Ok(var) => var.to_string().trim().parse().unwrap_or_else(|_| {
+            error!("Invalid {}: {}", VAR_COMMAND_COOLDOWN, var.to_string());
+            DEFAULT_COMMAND_COOLDOWN
+        }),
+        Err(_) => DEFAULT_COMMAND_COOLDOWN,
+    }
+}
+
+/// Prefixes from `COMMAND_PREFIX`, separated by spaces, e.g. `/ !`
+fn command_prefixes(env: &Env) -> Vec<String> {
+    env.var(VAR_COMMAND_PREFIX)
+        .map(|var| {
+            var.to_string()
+                .split_whitespace()
+                .map(ToString::to_string)
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Replies to commands sent before the cooldown of the chat is over
+pub async fn slow_down(m: Message, env: Env, bot: Bot<'_>) -> Result<Response, WorkerError> {
+    let language = reply_language(&m, &env, &bot).await?;
+    return_message(&m, tr!(language, "命令发送得太频繁了，请稍后再试"))
+}
+
+fn deny(m: &Message, language: Language) -> Result<Response, WorkerError> {
+    info!("Permission denied");
+    return_message(m, tr!(language, "只有管理员可以使用该命令"))
+}
+
+/// Replies to senders that aren't allowed to change the settings, `None` if they are
+pub async fn deny_unless_permitted(
+    group: &Group,
+    m: &Message,
+    bot: &Bot<'_>,
+    env: &Env,
+) -> Result<Option<Response>, WorkerError> {
+    if check_permission(group, m, bot, env).await? {
+        return Ok(None);
+    }
+    deny(m, group.language).map(Some)
+}
+
+/// Like `deny_unless_permitted`, but editors are turned away as well
+pub async fn deny_unless_admin(
+    group: &Group,
+    m: &Message,
+    bot: &Bot<'_>,
+    env: &Env,
+) -> Result<Option<Response>, WorkerError> {
+    if check_admin(m, bot, env).await? {
+        return Ok(None);
     }
+    deny(m, group.language).map(Some)
 }
 
 fn log_request(req: &Request) {
@@ -70,34 +325,240 @@ fn log_request(req: &Request) {
     );
 }
 
+async fn notify_subscriber(bot: &Bot<'_>, group: &mut Group, old_title: &str) {
+    let (user_id, text) = match subscriber_notification(group, old_title) {
+        Some(notification) => notification,
+        None => return,
+    };
+    let result = bot
+        .send_message(ChatTarget::Id(ChatId(user_id.0)), &text)
+        .await;
+    record_notification_result(group, user_id, result);
+}
+
+/// Subscriber of the group and the message telling them about the title change, if subscribed
+fn subscriber_notification(group: &Group, old_title: &str) -> Option<(UserId, String)> {
+    let user_id = group.notify_user?;
+    let text = tr!(
+        group.language,
+        "群 {} 的标题已由 {} 更改为 {}",
+        get_raw_chat_id(&group.id),
+        old_title,
+        group.get_last_title()
+    );
+    Some((user_id, text))
+}
+
+/// Clears the subscription when the subscriber can't be reached
+fn record_notification_result(
+    group: &mut Group,
+    user_id: UserId,
+    result: Result<TelegramResult<Message>, WorkerError>,
+) {
+    match result {
+        Ok(result) if result.ok => info!("Notified subscriber {}", user_id.0),
+        // The user has blocked the bot or never started a conversation with it
+        Ok(result) if matches!(result.error_code, Some(400) | Some(403)) => {
+            info!(
+                "Unable to reach subscriber {}: {:?}, clearing subscription...",
+                user_id.0, result.description
+            );
+            group.notify_user = None;
+        }
+        Ok(result) => error!("Failed to notify subscriber: {:?}", result.description),
+        Err(e) => error!("Failed to notify subscriber: {}", e),
+    }
+}
+
+fn scheduled_failure_notice(group: &Group) -> String {
+    format!(
+        "{}\n{}",
+        group.failure_message(),
+        tr!(
+            group.language,
+            "已停止自动更改标题，恢复权限后请使用 /enable 重新启用"
+        )
+    )
+}
+
+/// Tells the group once that the scheduled job can't rename it and stopped trying
+async fn notify_scheduled_failure(bot: &Bot<'_>, group: &mut Group) {
+    if group.failure_notified {
+        return;
+    }
+    let text = scheduled_failure_notice(group);
+    match bot.send_message(ChatTarget::Id(group.id), &text).await {
+        Ok(result) if result.ok => info!("Notified group {}", get_raw_chat_id(&group.id)),
+        Ok(result) => error!(
+            "Unable to notify group {}: {:?}",
+            get_raw_chat_id(&group.id),
+            result.description
+        ),
+        Err(e) => error!(
+            "Unable to notify group {}: {}",
+            get_raw_chat_id(&group.id),
+            e
+        ),
+    }
+    // Not retried either way, the bot most likely can't post there anymore
+    group.failure_notified = true;
+}
+
+pub fn describe_user(user: &User) -> String {
+    match &user.username {
+        Some(username) => format!("@{}", username),
+        None => user.id.0.to_string(),
+    }
+}
+
+/// Records the command of the message and its sender as the reason of a change
+pub fn record_command(group: &mut Group, m: &Message, value: String) {
+    group.record_history(command_history_entry(m, value, Date::now().as_millis()));
+}
+
+fn command_history_entry(m: &Message, value: String, timestamp: u64) -> HistoryEntry {
+    let text = m.text.clone().unwrap_or_default();
+    let source = text
+        .split_whitespace()
+        .next()
+        .unwrap_or_default()
+        // Commands are letters, digits and underscores, whatever comes before is the prefix
+        .trim_start_matches(|c: char| !c.is_alphanumeric() && c != '_')
+        .split('@')
+        .next()
+        .unwrap_or_default()
+        .to_string();
+    HistoryEntry {
+        timestamp,
+        source,
+        user: m.from.as_ref().map(|user| describe_user(user)),
+        value,
+    }
+}
+
+/// Saves the modified template and marks it for the next scheduled run.
+///
+/// Workers can't defer work within a request, so instead of renaming the group (and posting a
+/// service message) on every edit, rapid edits are coalesced into a single rename by the next
+/// scheduled run, at the cost of a short delay. `/apply` applies the template right away.
+///
+/// The group is only written here, after the template passed validation, so callers shouldn't
+/// save it beforehand.
 async fn update_template(
     store: &DataStore<'_>,
     group: &mut Group,
-    bot: &Bot<'_>,
     m: &Message,
 ) -> Result<Response, WorkerError> {
-    if group.enable
-        && !group
-            .apply_template(bot, &Date::now())
-            .await
-            .unwrap_or(false)
-    {
-        group.enable = false;
-        store.save_group(group).await?;
-        return return_message(m, "发生什么事了？未能成功更改群标题，请检查 bot 帐号权限");
+    update_template_with_warning(store, group, m, None).await
+}
+
+/// The current time of the worker, in UTC
+fn utc_now() -> NaiveDateTime {
+    NaiveDateTime::from_timestamp((Date::now().as_millis() / 1000) as i64, 0)
+}
+
+/// Explains why the template of the group can't be used, if it can't
+fn check_template(group: &Group, utc_time: NaiveDateTime) -> Option<String> {
+    if group.is_template_empty() {
+        return Some(warn_empty_template(group.language).to_string());
     }
-    store.save_group(group).await?;
-    let reply = format!("标题模板已被更改至： {}", group.join_title_template());
+    let unknown = group.validate_template();
+    if !unknown.is_empty() {
+        return Some(tr!(
+            group.language,
+            "无效模板，未知的占位符： {}",
+            unknown
+                .iter()
+                .map(|key| format!("{{{}}}", key))
+                .collect::<Vec<String>>()
+                .join(", ")
+        ));
+    }
+    if let Err((time, e)) = group.validate_render(utc_time) {
+        return Some(tr!(
+            group.language,
+            "无效模板，该模板在 {} 时无法渲染： {}",
+            time.format("%Y-%m-%d %H:%M"),
+            e
+        ));
+    }
+    None
+}
+
+async fn update_template_with_warning(
+    store: &DataStore<'_>,
+    group: &mut Group,
+    m: &Message,
+    warning: Option<&str>,
+) -> Result<Response, WorkerError> {
+    let reply = match stage_template(group, m, warning, utc_now()) {
+        Ok(reply) => {
+            store.save_group(group).await?;
+            reply
+        }
+        Err(reply) => reply,
+    };
     info!("Replied: {:?}", reply);
     return_message(m, reply)
 }
 
-pub async fn echo(m: Message, _env: Env, _bot: Bot<'_>) -> Result<Response, WorkerError> {
-    let text = if let Some(msg) = m.text.clone().unwrap().split_once(' ') {
-        msg.1.to_string()
+/// Checks the edited template and marks it for the next scheduled run, so rapid edits are
+/// applied together. Returns the reply, as an error if the template was refused and the group
+/// shouldn't be saved.
+fn stage_template(
+    group: &mut Group,
+    m: &Message,
+    warning: Option<&str>,
+    utc_time: NaiveDateTime,
+) -> Result<String, String> {
+    if let Some(reply) = check_template(group, utc_time) {
+        return Err(reply);
+    }
+    if group.enable {
+        group.pending_apply = true;
+    }
+    let template = group.join_title_template();
+    let timestamp = utc_time.timestamp_millis() as u64;
+    group.record_history(command_history_entry(m, template, timestamp));
+    let mut reply = tr!(
+        group.language,
+        "标题模板已被更改至： {}",
+        group.join_title_template()
+    );
+    if group.enable {
+        reply.push_str(tr!(
+            group.language,
+            "\n将在下次定时任务时应用，使用 /apply 立即应用"
+        ));
+    }
+    if let Some(warning) = warning {
+        reply.push('\n');
+        reply.push_str(warning);
+    }
+    Ok(reply)
+}
+
+fn warn_empty_template(language: Language) -> &'static str {
+    tr!(language, "标题模板为空，请先使用 /set_template 设置模板")
+}
+
+fn warn_duplicate_segment(group: &Group) -> &'static str {
+    if group.dedupe_segments {
+        tr!(
+            group.language,
+            "注意：新片段与相邻片段相同，重复的片段将被合并"
+        )
     } else {
-        "wut?".to_string()
-    };
+        tr!(
+            group.language,
+            "注意：新片段与相邻片段相同，可使用 /set_dedupe on 合并重复的片段"
+        )
+    }
+}
+
+pub async fn echo(m: Message, _env: Env, _bot: Bot<'_>) -> Result<Response, WorkerError> {
+    let command = m.text.clone().unwrap_or_default();
+    let text = get_argument(&command).unwrap_or("wut?").to_string();
     return_message(&m, text)
 }
 
@@ -107,37 +568,122 @@ pub async fn start(m: Message, _env: Env, _bot: Bot<'_>) -> Result<Response, Wor
     return_message(&m, reply)
 }
 
+pub async fn help(m: Message, env: Env, bot: Bot<'_>) -> Result<Response, WorkerError> {
+    let language = reply_language(&m, &env, &bot).await?;
+    let prefix = bot.get_command_prefix();
+    let reply = bot
+        .list_commands()
+        .iter()
+        .map(|(command, description)| match description {
+            Some(description) => {
+                format!(
+                    "{}{} - {}",
+                    prefix,
+                    command,
+                    translate(language, description)
+                )
+            }
+            None => format!("{}{}", prefix, command),
+        })
+        .collect::<Vec<String>>()
+        .join("\n");
+    info!("Replied: {:?}", reply);
+    return_message(&m, reply)
+}
+
 pub async fn status(m: Message, env: Env, bot: Bot<'_>) -> Result<Response, WorkerError> {
     let group_title = get_group_title(&m.chat.kind);
     if group_title.is_none() {
         return warn_group_only(&m);
     }
-    let group_title = group_title.unwrap();
-
     let kv = bot.get_kv(&env)?;
     let store = DataStore::new(&kv);
     let group = store.load_group_or_create(&m.chat.id, &m.chat.kind).await;
+    let group_title = group_title.unwrap();
 
-    if !check_permission(&group, &m, &bot).await? {
-        info!("Permission denied");
-        return Response::empty();
+    if let Some(denial) = deny_unless_permitted(&group, &m, &bot, &env).await? {
+        return Ok(denial);
     }
 
-    let reply = format!(
+    // Shown in the timezone of the group, so a stalled group is easy to spot
+    let last_updated = if group.last_updated == 0 {
+        tr!(group.language, "从未").to_string()
+    } else {
+        group
+            .get_time(NaiveDateTime::from_timestamp(
+                (group.last_updated / 1000) as i64,
+                0,
+            ))
+            .format("%Y-%m-%d %H:%M:%S %Z")
+            .to_string()
+    };
+    let reply = tr!(
+        group.language,
         r#"当前标题: {}
            群 ID: {}
            启用自动更改: {}
-           标题片段: {:?}
+           等待应用: {}
+           上次更新: {}
+           标题模板: {}
+           标题片段: {}
            分隔符: {}
+           循环分隔符: {}
            时区: {}
-           需要管理权限: {}"#,
+           需要管理权限: {}
+           强制标题: {}
+           双向文本隔离: {}
+           数字格式: {}
+           标题语言: {}
+           超长标题处理: {}
+           计划中的模板: {}
+           模板来源: {}
+           固定标题: {}
+           计数器: {}"#,
         group_title,
         get_raw_chat_id(&group.id),
         group.enable,
-        group.title_segment,
-        group.delimiter,
+        group.pending_apply,
+        last_updated,
+        group.join_title_template(),
+        group
+            .title_segment
+            .iter()
+            .enumerate()
+            .map(|(index, segment)| format!("\n{}. {}", index + 1, segment))
+            .collect::<String>(),
+        describe_delimiter(&group.delimiter, group.language),
+        format!("{:?}", group.delimiters),
         group.timezone,
-        group.require_admin
+        group.require_admin,
+        group.enforce,
+        group.bidi,
+        group.number_format,
+        group.locale,
+        group.length_policy,
+        group
+            .pending_templates
+            .iter()
+            .map(|pending| format!(
+                "{} → {}",
+                NaiveDate::from_num_days_from_ce(pending.date),
+                pending.title_segment.join(&group.delimiter)
+            ))
+            .collect::<Vec<String>>()
+            .join(", "),
+        group
+            .template_source
+            .map(|source| get_raw_chat_id(&source).to_string())
+            .unwrap_or_else(|| tr!(group.language, "无").to_string()),
+        group
+            .freeze
+            .as_deref()
+            .unwrap_or_else(|| tr!(group.language, "无")),
+        group
+            .counters
+            .iter()
+            .map(|(name, value)| format!("{} = {}", name, value))
+            .collect::<Vec<String>>()
+            .join(", ")
     );
     info!("Replied: {:?}", reply);
     return_message(&m, reply)
@@ -152,22 +698,30 @@ pub async fn enable(m: Message, env: Env, bot: Bot<'_>) -> Result<Response, Work
     let store = DataStore::new(&kv);
     let mut group = store.load_group_or_create(&m.chat.id, &m.chat.kind).await;
 
-    if !check_permission(&group, &m, &bot).await? {
-        return Response::empty();
+    if let Some(denial) = deny_unless_permitted(&group, &m, &bot, &env).await? {
+        return Ok(denial);
     }
 
+    // An empty template isn't the bot's fault, so it's not treated as a failed rename
+    if group.is_template_empty() {
+        return return_message(&m, warn_empty_template(group.language));
+    }
     group.enable = true;
     if !group
-        .apply_template(&bot, &Date::now())
+        .apply_template(&bot, &store, &Date::now())
         .await
         .unwrap_or(false)
     {
-        group.enable = false;
+        group.disable_for_permission();
         store.save_group(&group).await?;
-        return return_message(&m, "发生什么事了？未能成功更改群标题，请检查 bot 帐号权限");
+        return return_message(&m, group.failure_message());
     }
+    group.disabled_by_permission = false;
+    let title = group.get_last_title().to_string();
+    record_command(&mut group, &m, title);
     store.save_group(&group).await?;
-    let reply = format!(
+    let reply = tr!(
+        group.language,
         "已启用自动标题更改，当前标题模板为： {}",
         group.join_title_template()
     );
@@ -175,7 +729,7 @@ pub async fn enable(m: Message, env: Env, bot: Bot<'_>) -> Result<Response, Work
     return_message(&m, reply)
 }
 
-pub async fn disable(m: Message, env: Env, bot: Bot<'_>) -> Result<Response, WorkerError> {
+pub async fn apply(m: Message, env: Env, bot: Bot<'_>) -> Result<Response, WorkerError> {
     let group_title = get_group_title(&m.chat.kind);
     if group_title.is_none() {
         return warn_group_only(&m);
@@ -184,150 +738,212 @@ pub async fn disable(m: Message, env: Env, bot: Bot<'_>) -> Result<Response, Wor
     let store = DataStore::new(&kv);
     let mut group = store.load_group_or_create(&m.chat.id, &m.chat.kind).await;
 
-    if !check_permission(&group, &m, &bot).await? {
-        return Response::empty();
+    if let Some(denial) = deny_unless_permitted(&group, &m, &bot, &env).await? {
+        return Ok(denial);
     }
 
-    group.enable = false;
+    if !group.enable {
+        return return_message(
+            &m,
+            tr!(group.language, "自动标题更改未启用，请先使用 /enable 启用"),
+        );
+    }
+    if group.is_template_empty() {
+        return return_message(&m, warn_empty_template(group.language));
+    }
+    if !group
+        .apply_template(&bot, &store, &Date::now())
+        .await
+        .unwrap_or(false)
+    {
+        group.disable_for_permission();
+        store.save_group(&group).await?;
+        return return_message(&m, group.failure_message());
+    }
+    let title = group.get_last_title().to_string();
+    record_command(&mut group, &m, title);
     store.save_group(&group).await?;
-    info!("Disabled for group {}", get_raw_chat_id(&group.id));
-    return_message(&m, "已禁用自动标题更改")
+    let reply = tr!(group.language, "已应用标题： {}", group.get_last_title());
+    info!("Replied: {:?}", reply);
+    return_message(&m, reply)
 }
 
-pub async fn set_template(m: Message, env: Env, bot: Bot<'_>) -> Result<Response, WorkerError> {
+pub async fn reset(m: Message, env: Env, bot: Bot<'_>) -> Result<Response, WorkerError> {
     let group_title = get_group_title(&m.chat.kind);
     if group_title.is_none() {
         return warn_group_only(&m);
     }
-    let command = m.text.clone().unwrap();
-    let title_template = command.split_once(' ');
-    if title_template.is_none() {
-        return return_message(&m, "无效命令，没有发现新的标题模板");
-    }
     let kv = bot.get_kv(&env)?;
     let store = DataStore::new(&kv);
     let mut group = store.load_group_or_create(&m.chat.id, &m.chat.kind).await;
 
-    if !check_permission(&group, &m, &bot).await? {
-        return Response::empty();
+    // Throws away the template, so it stays limited to admins
+    if let Some(denial) = deny_unless_admin(&group, &m, &bot, &env).await? {
+        return Ok(denial);
     }
 
+    if group.original_title.is_empty() {
+        return return_message(&m, tr!(group.language, "没有记录本群的原始标题"));
+    }
+    let original_title = group.original_title.clone();
     group.clear_title_template();
-    group.push_title_template(title_template.unwrap().1);
-    update_template(&store, &mut group, &bot, &m).await
+    group.push_title_template(&original_title);
+    group.disable_manually();
+    group.pending_apply = false;
+    record_command(&mut group, &m, original_title.clone());
+    let updated = group
+        .update_title(&bot, &original_title)
+        .await
+        .unwrap_or(false);
+    if updated && group.last_title != original_title {
+        group.previous_title = Some(std::mem::replace(
+            &mut group.last_title,
+            original_title.clone(),
+        ));
+    }
+    store.save_group(&group).await?;
+    let reply = if updated {
+        tr!(
+            group.language,
+            "已恢复原始标题并关闭自动更改： {}",
+            original_title
+        )
+    } else {
+        tr!(
+            group.language,
+            "模板已恢复为原始标题并关闭自动更改，但无法更改群标题，请检查 bot 的权限： {}",
+            original_title
+        )
+    };
+    info!("Replied: {:?}", reply);
+    return_message(&m, reply)
 }
 
-pub async fn set_delimiter(m: Message, env: Env, bot: Bot<'_>) -> Result<Response, WorkerError> {
+pub async fn undo(m: Message, env: Env, bot: Bot<'_>) -> Result<Response, WorkerError> {
     let group_title = get_group_title(&m.chat.kind);
     if group_title.is_none() {
         return warn_group_only(&m);
     }
-    let command = m.text.clone().unwrap();
-    let delimiter = command.split_once(' ');
-    if delimiter.is_none() {
-        return return_message(&m, "无效命令，没有发现新的分隔符");
-    }
-    let delimiter = delimiter.unwrap().1.to_string();
     let kv = bot.get_kv(&env)?;
     let store = DataStore::new(&kv);
     let mut group = store.load_group_or_create(&m.chat.id, &m.chat.kind).await;
 
-    if !check_permission(&group, &m, &bot).await? {
-        return Response::empty();
+    if let Some(denial) = deny_unless_permitted(&group, &m, &bot, &env).await? {
+        return Ok(denial);
     }
 
-    group.delimiter = delimiter;
+    let previous_title = match group.previous_title.clone() {
+        Some(previous_title) => previous_title,
+        None => return return_message(&m, tr!(group.language, "没有可以恢复的标题")),
+    };
+    if !group
+        .update_title(&bot, &previous_title)
+        .await
+        .unwrap_or(false)
+    {
+        store.save_group(&group).await?;
+        return return_message(&m, group.failure_message());
+    }
+    // Swapped, so undoing again restores the title that was just replaced
+    group.previous_title = Some(std::mem::replace(
+        &mut group.last_title,
+        previous_title.clone(),
+    ));
+    record_command(&mut group, &m, previous_title.clone());
     store.save_group(&group).await?;
-    update_template(&store, &mut group, &bot, &m).await
+    let mut reply = tr!(group.language, "已恢复标题： {}", previous_title);
+    if group.enable && group.freeze.is_none() {
+        reply.push_str(tr!(
+            group.language,
+            "\n自动更改仍在启用，模板将在下次定时任务时重新应用，可使用 /freeze 固定标题"
+        ));
+    }
+    info!("Replied: {:?}", reply);
+    return_message(&m, reply)
 }
 
-pub async fn set_timezone(m: Message, env: Env, bot: Bot<'_>) -> Result<Response, WorkerError> {
+pub async fn disable(m: Message, env: Env, bot: Bot<'_>) -> Result<Response, WorkerError> {
     let group_title = get_group_title(&m.chat.kind);
     if group_title.is_none() {
         return warn_group_only(&m);
     }
-    let command = m.text.clone().unwrap();
-    let timezone_str = command.split_once(' ');
-    if timezone_str.is_none() {
-        return return_message(&m, "无效命令，没有发现新的时区名称");
-    }
-    let timezone_str = timezone_str.unwrap().1.to_string();
-    let timezone: Result<Tz, _> = timezone_str.parse();
-    if timezone.is_err() {
-        return return_message(&m, "无效命令，无法解析时区名称");
-    }
     let kv = bot.get_kv(&env)?;
     let store = DataStore::new(&kv);
     let mut group = store.load_group_or_create(&m.chat.id, &m.chat.kind).await;
 
-    if !check_permission(&group, &m, &bot).await? {
-        return Response::empty();
+    if let Some(denial) = deny_unless_permitted(&group, &m, &bot, &env).await? {
+        return Ok(denial);
     }
 
-    group.timezone = timezone.unwrap().to_string();
-    if group.enable
-        && !group
-            .apply_template(&bot, &Date::now())
-            .await
-            .unwrap_or(false)
-    {
-        group.enable = false;
-        store.save_group(&group).await?;
-        return return_message(&m, "发生什么事了？未能成功更改群标题，请检查 bot 帐号权限");
-    }
+    group.disable_manually();
     store.save_group(&group).await?;
-    let reply = format!("时区已变更至：{}", group.timezone);
-    info!("Replied: {:?}", reply);
-    return_message(&m, reply)
+    info!("Disabled for group {}", get_raw_chat_id(&group.id));
+    return_message(&m, tr!(group.language, "已禁用自动标题更改"))
 }
 
-pub async fn push(m: Message, env: Env, bot: Bot<'_>) -> Result<Response, WorkerError> {
+/// Replaces the template with the live title of the group as its only segment
+pub async fn clear(m: Message, env: Env, bot: Bot<'_>) -> Result<Response, WorkerError> {
     let group_title = get_group_title(&m.chat.kind);
     if group_title.is_none() {
         return warn_group_only(&m);
     }
-    let command = m.text.clone().unwrap();
-    let new_template_segment = command.split_once(' ');
-    if new_template_segment.is_none() {
-        return return_message(&m, "无效命令，没有发现新的标题片段");
-    }
-    let new_template_segment = new_template_segment.unwrap().1;
     let kv = bot.get_kv(&env)?;
     let store = DataStore::new(&kv);
     let mut group = store.load_group_or_create(&m.chat.id, &m.chat.kind).await;
 
-    if !check_permission(&group, &m, &bot).await? {
-        return Response::empty();
+    // Throws away the template, so it stays limited to admins
+    if let Some(denial) = deny_unless_admin(&group, &m, &bot, &env).await? {
+        return Ok(denial);
     }
 
-    group.push_title_template(new_template_segment);
-    update_template(&store, &mut group, &bot, &m).await
+    if group.template_source.is_some() {
+        return return_message(
+            &m,
+            tr!(
+                group.language,
+                "本群链接了模板来源，请先使用 /unlink_source 取消链接"
+            ),
+        );
+    }
+    let live_title = match bot.get_chat(ChatTarget::Id(m.chat.id)).await {
+        Ok(chat) => get_group_title(&chat.kind).unwrap_or_default().to_string(),
+        Err(e) => {
+            error!("Unable to get the title of group {}: {}", m.chat.id.0, e);
+            return return_message(&m, tr!(group.language, "无法获取当前的群标题"));
+        }
+    };
+    if live_title.trim().is_empty() {
+        return return_message(&m, tr!(group.language, "当前的群标题为空，模板未被更改"));
+    }
+    group.clear_title_template();
+    group.push_title_template(&live_title);
+    update_template(&store, &mut group, &m).await
 }
 
-pub async fn push_front(m: Message, env: Env, bot: Bot<'_>) -> Result<Response, WorkerError> {
+pub async fn set_template(m: Message, env: Env, bot: Bot<'_>) -> Result<Response, WorkerError> {
     let group_title = get_group_title(&m.chat.kind);
     if group_title.is_none() {
         return warn_group_only(&m);
     }
-    let command = m.text.clone().unwrap();
-    let new_template_segment = command.split_once(' ');
-    if new_template_segment.is_none() {
-        return return_message(&m, "无效命令，没有发现新的标题片段");
-    }
-    let new_template_segment = new_template_segment.unwrap().1;
     let kv = bot.get_kv(&env)?;
     let store = DataStore::new(&kv);
     let mut group = store.load_group_or_create(&m.chat.id, &m.chat.kind).await;
+    let command = m.text.clone().unwrap_or_default();
+    let title_template = get_argument(&command);
+    if title_template.is_none() {
+        return return_message(&m, tr!(group.language, "无效命令，没有发现新的标题模板"));
+    }
 
-    if !check_permission(&group, &m, &bot).await? {
-        return Response::empty();
+    if let Some(denial) = deny_unless_permitted(&group, &m, &bot, &env).await? {
+        return Ok(denial);
     }
 
-    group.push_front_title_template(new_template_segment);
-    update_template(&store, &mut group, &bot, &m).await
+    group.clear_title_template();
+    group.push_title_template(title_template.unwrap());
+    update_template(&store, &mut group, &m).await
 }
 
-pub async fn pop(m: Message, env: Env, bot: Bot<'_>) -> Result<Response, WorkerError> {
+pub async fn flatten(m: Message, env: Env, bot: Bot<'_>) -> Result<Response, WorkerError> {
     let group_title = get_group_title(&m.chat.kind);
     if group_title.is_none() {
         return warn_group_only(&m);
@@ -335,16 +951,36 @@ pub async fn pop(m: Message, env: Env, bot: Bot<'_>) -> Result<Response, WorkerE
     let kv = bot.get_kv(&env)?;
     let store = DataStore::new(&kv);
     let mut group = store.load_group_or_create(&m.chat.id, &m.chat.kind).await;
+    let command = m.text.clone().unwrap_or_default();
 
-    if !check_permission(&group, &m, &bot).await? {
-        return Response::empty();
+    if get_argument(&command) != Some("confirm") {
+        let reply = tr!(
+            group.language,
+            "{}\n可将以上模板用于 /set_template，使用 /flatten confirm 将标题片段合并为一个",
+            group.join_title_template()
+        );
+        info!("Replied: {:?}", reply);
+        return return_message(&m, reply);
     }
 
-    group.pop_title_template();
-    update_template(&store, &mut group, &bot, &m).await
+    if let Some(denial) = deny_unless_permitted(&group, &m, &bot, &env).await? {
+        return Ok(denial);
+    }
+
+    if group.template_source.is_some() {
+        return return_message(
+            &m,
+            tr!(
+                group.language,
+                "本群链接了模板来源，请先使用 /unlink_source 取消链接"
+            ),
+        );
+    }
+    group.flatten_title_template();
+    update_template(&store, &mut group, &m).await
 }
 
-pub async fn pop_front(m: Message, env: Env, bot: Bot<'_>) -> Result<Response, WorkerError> {
+pub async fn set_description(m: Message, env: Env, bot: Bot<'_>) -> Result<Response, WorkerError> {
     let group_title = get_group_title(&m.chat.kind);
     if group_title.is_none() {
         return warn_group_only(&m);
@@ -352,50 +988,2872 @@ pub async fn pop_front(m: Message, env: Env, bot: Bot<'_>) -> Result<Response, W
     let kv = bot.get_kv(&env)?;
     let store = DataStore::new(&kv);
     let mut group = store.load_group_or_create(&m.chat.id, &m.chat.kind).await;
+    let command = m.text.clone().unwrap_or_default();
+    let template = get_argument(&command).map(|template| template.to_string());
 
-    if !check_permission(&group, &m, &bot).await? {
-        return Response::empty();
+    if let Some(denial) = deny_unless_permitted(&group, &m, &bot, &env).await? {
+        return Ok(denial);
     }
 
-    group.pop_front_title_template();
-    update_template(&store, &mut group, &bot, &m).await
+    // Without an argument the description is no longer updated
+    group.description_template = template.clone();
+    let reply = match group.render_description(&Date::now()) {
+        Some(Ok(description)) => {
+            let mut reply = tr!(
+                group.language,
+                "简介模板已变更，当前渲染结果：\n{}",
+                description
+            );
+            if group.enable {
+                reply.push_str(tr!(group.language, "\n将在下次定时任务时应用"));
+            }
+            reply
+        }
+        Some(Err(e)) => {
+            return return_message(
+                &m,
+                tr!(
+                    group.language,
+                    "无效模板，简介不能超过 {} 个字符： {}",
+                    MAX_DESCRIPTION_LENGTH,
+                    e
+                ),
+            )
+        }
+        None => tr!(group.language, "已停止更新群简介").to_string(),
+    };
+    record_command(&mut group, &m, template.unwrap_or_default());
+    store.save_group(&group).await?;
+    info!("Replied: {:?}", reply);
+    return_message(&m, reply)
 }
 
-#[event(scheduled)]
-pub async fn handle_scheduled(_req: ScheduledEvent, env: Env, _ctx: ScheduleContext) {
-    worker_logger::init_with_string("info");
-    set_panic_hook();
-
-    let bot = Bot::new_with_env(&env, DEFAULT_SECRET_TOKEN, VAR_USERNAME, VAR_KV_STORE)
-        .expect("Unable to instantiate bot");
-    let kv = bot.get_kv(&env).expect("Unable to get KVStore");
+pub async fn set_template_at(m: Message, env: Env, bot: Bot<'_>) -> Result<Response, WorkerError> {
+    let group_title = get_group_title(&m.chat.kind);
+    if group_title.is_none() {
+        return warn_group_only(&m);
+    }
+    let kv = bot.get_kv(&env)?;
     let store = DataStore::new(&kv);
-    let groups = store
-        .get_group_keys()
-        .await
-        .expect("Unable to get group keys");
-    let date = Date::now();
-    for group_name in groups {
-        let chat_id: Result<i64, ParseIntError> = group_name.parse();
-        if chat_id.is_err() {
-            info!("Group ID {} is invalid, skipping...", group_name);
-            continue;
-        }
-        let chat_id = ChatId(chat_id.unwrap());
-        let mut group = store
-            .load_group(&chat_id)
-            .await
-            .expect("Unable to load group information");
-        if !group.enable {
-            info!("Group {} is disabled, skipping...", group_name);
-            continue;
-        }
-        let _res = group.apply_template(&bot, &date).await;
-        info!("Title for group {} updated successfully", group_name);
+    let mut group = store.load_group_or_create(&m.chat.id, &m.chat.kind).await;
+    let command = m.text.clone().unwrap_or_default();
+    let arguments = get_argument(&command)
+        .and_then(|arguments| arguments.split_once(char::is_whitespace))
+        .map(|(date, title_template)| (date, title_template.trim()));
+    if arguments.is_none() {
+        return return_message(
+            &m,
+            tr!(
+                group.language,
+                "无效命令，请使用 /set_template_at YYYY-MM-DD 标题模板"
+            ),
+        );
+    }
+    let (date, title_template) = arguments.unwrap();
+    let date = NaiveDate::parse_from_str(date, "%Y-%m-%d");
+    if date.is_err() {
+        return return_message(
+            &m,
+            tr!(
+                group.language,
+                "无效命令，无法解析日期，请使用 YYYY-MM-DD 格式"
+            ),
+        );
+    }
+    let date = date.unwrap();
+
+    if let Some(denial) = deny_unless_permitted(&group, &m, &bot, &env).await? {
+        return Ok(denial);
+    }
+
+    let today = group.get_local_date(&Date::now());
+    if date <= today {
+        return return_message(&m, tr!(group.language, "无效命令，日期必须晚于今天"));
     }
+    group.schedule_template(date, vec![title_template.to_string()]);
+    store.save_group(&group).await?;
+    let reply = tr!(
+        group.language,
+        "标题模板将于 {} 更改至： {}",
+        date,
+        title_template
+    );
+    info!("Replied: {:?}", reply);
+    return_message(&m, reply)
 }
 
-pub async fn main_inner(
+pub async fn set_delimiter(m: Message, env: Env, bot: Bot<'_>) -> Result<Response, WorkerError> {
+    let group_title = get_group_title(&m.chat.kind);
+    if group_title.is_none() {
+        return warn_group_only(&m);
+    }
+    let kv = bot.get_kv(&env)?;
+    let store = DataStore::new(&kv);
+    let mut group = store.load_group_or_create(&m.chat.id, &m.chat.kind).await;
+    let command = m.text.clone().unwrap_or_default();
+    let delimiter = get_raw_argument(&command);
+    if delimiter.is_none() {
+        return return_message(&m, tr!(group.language, "无效命令，没有发现新的分隔符"));
+    }
+    let delimiter = parse_delimiter(delimiter.unwrap()).to_string();
+
+    if let Some(denial) = deny_unless_permitted(&group, &m, &bot, &env).await? {
+        return Ok(denial);
+    }
+
+    group.delimiter = delimiter;
+    group.delimiters.clear();
+    update_delimiters(&store, &mut group, &m).await
+}
+
+/// Saves the changed delimiters. They're only refused for making the title too long under the
+/// `error` length policy, the other policies still get a title out of it.
+async fn update_delimiters(
+    store: &DataStore<'_>,
+    group: &mut Group,
+    m: &Message,
+) -> Result<Response, WorkerError> {
+    if group.exceeds_length_budget(&Date::now()) {
+        if group.length_policy == LengthPolicy::Error {
+            return warn_title_too_long(m, group.language);
+        }
+        let warning = tr!(
+            group.language,
+            "使用该分隔符后标题将超出 {} 的长度限制，将按照长度策略 {} 处理",
+            MAX_TITLE_LENGTH,
+            group.length_policy
+        );
+        return update_template_with_warning(store, group, m, Some(&warning)).await;
+    }
+    update_template(store, group, m).await
+}
+
+pub async fn set_delimiters(m: Message, env: Env, bot: Bot<'_>) -> Result<Response, WorkerError> {
+    let group_title = get_group_title(&m.chat.kind);
+    if group_title.is_none() {
+        return warn_group_only(&m);
+    }
+    let kv = bot.get_kv(&env)?;
+    let store = DataStore::new(&kv);
+    let mut group = store.load_group_or_create(&m.chat.id, &m.chat.kind).await;
+    let command = m.text.clone().unwrap_or_default();
+    let delimiters = get_raw_argument(&command);
+    if delimiters.is_none() {
+        return return_message(&m, tr!(group.language, "无效命令，没有发现新的分隔符"));
+    }
+    let delimiters: Vec<String> = delimiters
+        .unwrap()
+        .split(';')
+        .map(ToString::to_string)
+        .collect();
+
+    if let Some(denial) = deny_unless_permitted(&group, &m, &bot, &env).await? {
+        return Ok(denial);
+    }
+
+    group.delimiters = delimiters;
+    update_delimiters(&store, &mut group, &m).await
+}
+
+pub async fn reset_delimiter(m: Message, env: Env, bot: Bot<'_>) -> Result<Response, WorkerError> {
+    let group_title = get_group_title(&m.chat.kind);
+    if group_title.is_none() {
+        return warn_group_only(&m);
+    }
+    let kv = bot.get_kv(&env)?;
+    let store = DataStore::new(&kv);
+    let mut group = store.load_group_or_create(&m.chat.id, &m.chat.kind).await;
+
+    if let Some(denial) = deny_unless_permitted(&group, &m, &bot, &env).await? {
+        return Ok(denial);
+    }
+
+    group.reset_delimiter();
+    update_template(&store, &mut group, &m).await
+}
+
+pub async fn set_countdown(m: Message, env: Env, bot: Bot<'_>) -> Result<Response, WorkerError> {
+    let group_title = get_group_title(&m.chat.kind);
+    if group_title.is_none() {
+        return warn_group_only(&m);
+    }
+    let kv = bot.get_kv(&env)?;
+    let store = DataStore::new(&kv);
+    let mut group = store.load_group_or_create(&m.chat.id, &m.chat.kind).await;
+    let command = m.text.clone().unwrap_or_default();
+    // Without an argument the target is removed
+    let target = match get_argument(&command) {
+        Some(date) => match NaiveDate::parse_from_str(date, "%Y-%m-%d") {
+            Ok(date) => Some(date),
+            Err(_) => {
+                return return_message(
+                    &m,
+                    tr!(
+                        group.language,
+                        "无效命令，无法解析日期，请使用 YYYY-MM-DD 格式"
+                    ),
+                )
+            }
+        },
+        None => None,
+    };
+
+    if let Some(denial) = deny_unless_permitted(&group, &m, &bot, &env).await? {
+        return Ok(denial);
+    }
+
+    group.countdown = target.map(|date| date.num_days_from_ce());
+    if let Err((time, e)) = group.validate_render(utc_now()) {
+        let reply = tr!(
+            group.language,
+            "模板在 {} 时无法渲染，未更改目标日期： {}",
+            time.format("%Y-%m-%d %H:%M"),
+            e
+        );
+        return return_message(&m, reply);
+    }
+    if group.enable {
+        group.pending_apply = true;
+    }
+    record_command(
+        &mut group,
+        &m,
+        target.map(|date| date.to_string()).unwrap_or_default(),
+    );
+    store.save_group(&group).await?;
+    let reply = match target {
+        Some(date) => {
+            let today = group.get_local_date(&Date::now());
+            tr!(
+                group.language,
+                "目标日期已设为 {}，距今 {} 天",
+                date,
+                (date - today).num_days()
+            )
+        }
+        None => tr!(group.language, "已清除目标日期").to_string(),
+    };
+    info!("Replied: {:?}", reply);
+    return_message(&m, reply)
+}
+
+pub async fn set_interval(m: Message, env: Env, bot: Bot<'_>) -> Result<Response, WorkerError> {
+    let group_title = get_group_title(&m.chat.kind);
+    if group_title.is_none() {
+        return warn_group_only(&m);
+    }
+    let kv = bot.get_kv(&env)?;
+    let store = DataStore::new(&kv);
+    let mut group = store.load_group_or_create(&m.chat.id, &m.chat.kind).await;
+    let command = m.text.clone().unwrap_or_default();
+    let interval = get_argument(&command)
+        .and_then(|interval| interval.parse::<u32>().ok())
+        .filter(|interval| *interval <= MAX_UPDATE_INTERVAL);
+    if interval.is_none() {
+        return return_message(
+            &m,
+            tr!(
+                group.language,
+                "无效命令，请提供 0 到 {} 之间的分钟数，0 为每次定时任务都更新",
+                MAX_UPDATE_INTERVAL
+            ),
+        );
+    }
+    let interval = interval.unwrap();
+
+    if let Some(denial) = deny_unless_permitted(&group, &m, &bot, &env).await? {
+        return Ok(denial);
+    }
+
+    group.update_interval = interval;
+    store.save_group(&group).await?;
+    let reply = if interval == 0 {
+        tr!(group.language, "标题将在每次定时任务时更新").to_string()
+    } else {
+        tr!(group.language, "标题将每隔 {} 分钟更新一次", interval)
+    };
+    info!("Replied: {:?}", reply);
+    return_message(&m, reply)
+}
+
+pub async fn set_active_hours(m: Message, env: Env, bot: Bot<'_>) -> Result<Response, WorkerError> {
+    let group_title = get_group_title(&m.chat.kind);
+    if group_title.is_none() {
+        return warn_group_only(&m);
+    }
+    let kv = bot.get_kv(&env)?;
+    let store = DataStore::new(&kv);
+    let mut group = store.load_group_or_create(&m.chat.id, &m.chat.kind).await;
+    let command = m.text.clone().unwrap_or_default();
+    let argument = get_argument(&command).unwrap_or_default();
+    let active_hours = if parse_switch(argument) == Some(false) {
+        Some(None)
+    } else {
+        let hours = argument
+            .split_whitespace()
+            .map(|hour| hour.parse::<u8>().ok().filter(|hour| *hour < 24))
+            .collect::<Option<Vec<u8>>>();
+        match hours.as_deref() {
+            Some([start, end]) if start != end => Some(Some((*start, *end))),
+            _ => None,
+        }
+    };
+    if active_hours.is_none() {
+        return return_message(
+            &m,
+            tr!(
+                group.language,
+                "无效命令，请提供 0 到 23 之间的开始和结束小时，或使用 off 取消限制"
+            ),
+        );
+    }
+    let active_hours = active_hours.unwrap();
+
+    if let Some(denial) = deny_unless_permitted(&group, &m, &bot, &env).await? {
+        return Ok(denial);
+    }
+
+    group.active_hours = active_hours;
+    store.save_group(&group).await?;
+    let reply = match active_hours {
+        Some((start, end)) => tr!(
+            group.language,
+            "标题只会在 {}:00 到 {}:00 之间更新",
+            start,
+            end
+        ),
+        None => tr!(group.language, "已取消更新时段限制").to_string(),
+    };
+    info!("Replied: {:?}", reply);
+    return_message(&m, reply)
+}
+
+pub async fn set_timezone(m: Message, env: Env, bot: Bot<'_>) -> Result<Response, WorkerError> {
+    let group_title = get_group_title(&m.chat.kind);
+    if group_title.is_none() {
+        return warn_group_only(&m);
+    }
+    let kv = bot.get_kv(&env)?;
+    let store = DataStore::new(&kv);
+    let mut group = store.load_group_or_create(&m.chat.id, &m.chat.kind).await;
+    let command = m.text.clone().unwrap_or_default();
+    let timezone_str = get_argument(&command);
+    if timezone_str.is_none() {
+        return return_message(&m, tr!(group.language, "无效命令，没有发现新的时区名称"));
+    }
+    let timezone_str = timezone_str.unwrap().to_string();
+    let timezone: Result<Tz, _> = timezone_str.parse();
+    if timezone.is_err() {
+        let suggestions = suggest_timezones(&timezone_str);
+        if suggestions.is_empty() {
+            return return_message(&m, tr!(group.language, "无效命令，无法解析时区名称"));
+        }
+        return return_message(
+            &m,
+            tr!(
+                group.language,
+                "无效命令，无法解析时区名称，你是否想要：{}",
+                suggestions.join(", ")
+            ),
+        );
+    }
+
+    if let Some(denial) = deny_unless_permitted(&group, &m, &bot, &env).await? {
+        return Ok(denial);
+    }
+
+    group.timezone = timezone.unwrap().to_string();
+    if group.enable {
+        group.pending_apply = true;
+    }
+    store.save_group(&group).await?;
+    let reply = tr!(group.language, "时区已变更至：{}", group.timezone);
+    info!("Replied: {:?}", reply);
+    return_message(&m, reply)
+}
+
+pub async fn reset_timezone(m: Message, env: Env, bot: Bot<'_>) -> Result<Response, WorkerError> {
+    let group_title = get_group_title(&m.chat.kind);
+    if group_title.is_none() {
+        return warn_group_only(&m);
+    }
+    let kv = bot.get_kv(&env)?;
+    let store = DataStore::new(&kv);
+    let mut group = store.load_group_or_create(&m.chat.id, &m.chat.kind).await;
+
+    if let Some(denial) = deny_unless_permitted(&group, &m, &bot, &env).await? {
+        return Ok(denial);
+    }
+
+    group.reset_timezone();
+    update_template(&store, &mut group, &m).await
+}
+
+pub async fn set_number_format(
+    m: Message,
+    env: Env,
+    bot: Bot<'_>,
+) -> Result<Response, WorkerError> {
+    let group_title = get_group_title(&m.chat.kind);
+    if group_title.is_none() {
+        return warn_group_only(&m);
+    }
+    let kv = bot.get_kv(&env)?;
+    let store = DataStore::new(&kv);
+    let mut group = store.load_group_or_create(&m.chat.id, &m.chat.kind).await;
+    let command = m.text.clone().unwrap_or_default();
+    let number_format = get_argument(&command).and_then(NumberFormat::parse);
+    if number_format.is_none() {
+        return return_message(
+            &m,
+            tr!(group.language, "无效命令，请使用 plain、grouped 或 cjk"),
+        );
+    }
+
+    if let Some(denial) = deny_unless_permitted(&group, &m, &bot, &env).await? {
+        return Ok(denial);
+    }
+
+    group.number_format = number_format.unwrap();
+    update_template(&store, &mut group, &m).await
+}
+
+pub async fn set_locale(m: Message, env: Env, bot: Bot<'_>) -> Result<Response, WorkerError> {
+    let group_title = get_group_title(&m.chat.kind);
+    if group_title.is_none() {
+        return warn_group_only(&m);
+    }
+    let kv = bot.get_kv(&env)?;
+    let store = DataStore::new(&kv);
+    let mut group = store.load_group_or_create(&m.chat.id, &m.chat.kind).await;
+    let command = m.text.clone().unwrap_or_default();
+    let locale = get_argument(&command).and_then(TitleLocale::parse);
+    if locale.is_none() {
+        return return_message(&m, tr!(group.language, "无效命令，请使用 en、zh、ja 或 fr"));
+    }
+
+    if let Some(denial) = deny_unless_permitted(&group, &m, &bot, &env).await? {
+        return Ok(denial);
+    }
+
+    group.locale = locale.unwrap();
+    update_template(&store, &mut group, &m).await
+}
+
+pub async fn set_week_start(m: Message, env: Env, bot: Bot<'_>) -> Result<Response, WorkerError> {
+    let group_title = get_group_title(&m.chat.kind);
+    if group_title.is_none() {
+        return warn_group_only(&m);
+    }
+    let kv = bot.get_kv(&env)?;
+    let store = DataStore::new(&kv);
+    let mut group = store.load_group_or_create(&m.chat.id, &m.chat.kind).await;
+    let command = m.text.clone().unwrap_or_default();
+    let week_start = get_argument(&command).and_then(WeekStart::parse);
+    if week_start.is_none() {
+        return return_message(&m, tr!(group.language, "无效命令，请使用 sun 或 mon"));
+    }
+
+    if let Some(denial) = deny_unless_permitted(&group, &m, &bot, &env).await? {
+        return Ok(denial);
+    }
+
+    group.week_start = week_start.unwrap();
+    update_template(&store, &mut group, &m).await
+}
+
+pub async fn set_language(m: Message, env: Env, bot: Bot<'_>) -> Result<Response, WorkerError> {
+    let group_title = get_group_title(&m.chat.kind);
+    if group_title.is_none() {
+        return warn_group_only(&m);
+    }
+    let kv = bot.get_kv(&env)?;
+    let store = DataStore::new(&kv);
+    let mut group = store.load_group_or_create(&m.chat.id, &m.chat.kind).await;
+    let command = m.text.clone().unwrap_or_default();
+    let language = get_argument(&command).and_then(Language::parse);
+    if language.is_none() {
+        return return_message(&m, tr!(group.language, "无效命令，请使用 zh 或 en"));
+    }
+
+    if let Some(denial) = deny_unless_permitted(&group, &m, &bot, &env).await? {
+        return Ok(denial);
+    }
+
+    group.language = language.unwrap();
+    store.save_group(&group).await?;
+    let reply = tr!(group.language, "回复语言已变更至：{}", group.language);
+    info!("Replied: {:?}", reply);
+    return_message(&m, reply)
+}
+
+pub async fn set_length_policy(
+    m: Message,
+    env: Env,
+    bot: Bot<'_>,
+) -> Result<Response, WorkerError> {
+    let group_title = get_group_title(&m.chat.kind);
+    if group_title.is_none() {
+        return warn_group_only(&m);
+    }
+    let kv = bot.get_kv(&env)?;
+    let store = DataStore::new(&kv);
+    let mut group = store.load_group_or_create(&m.chat.id, &m.chat.kind).await;
+    let command = m.text.clone().unwrap_or_default();
+    let length_policy = get_argument(&command).and_then(LengthPolicy::parse);
+    if length_policy.is_none() {
+        return return_message(
+            &m,
+            tr!(group.language, "无效命令，请使用 error、truncate 或 skip"),
+        );
+    }
+
+    if let Some(denial) = deny_unless_permitted(&group, &m, &bot, &env).await? {
+        return Ok(denial);
+    }
+
+    group.length_policy = length_policy.unwrap();
+    if group.enable {
+        group.pending_apply = true;
+    }
+    store.save_group(&group).await?;
+    let reply = tr!(
+        group.language,
+        "超长标题处理方式已变更至：{}",
+        group.length_policy
+    );
+    info!("Replied: {:?}", reply);
+    return_message(&m, reply)
+}
+
+pub async fn now(m: Message, env: Env, bot: Bot<'_>) -> Result<Response, WorkerError> {
+    let group_title = get_group_title(&m.chat.kind);
+    if group_title.is_none() {
+        return warn_group_only(&m);
+    }
+    let kv = bot.get_kv(&env)?;
+    let store = DataStore::new(&kv);
+    let group = store.load_group_or_create(&m.chat.id, &m.chat.kind).await;
+
+    let local_time = group.get_local_time(&Date::now());
+    let reply = tr!(
+        group.language,
+        "当前时间: {}\n时区: {} (UTC{})",
+        local_time.format("%Y-%m-%d %H:%M:%S %A"),
+        group.timezone,
+        local_time.format("%:z")
+    );
+    info!("Replied: {:?}", reply);
+    return_message(&m, reply)
+}
+
+pub async fn preview(m: Message, env: Env, bot: Bot<'_>) -> Result<Response, WorkerError> {
+    let group_title = get_group_title(&m.chat.kind);
+    if group_title.is_none() {
+        return warn_group_only(&m);
+    }
+    let kv = bot.get_kv(&env)?;
+    let store = DataStore::new(&kv);
+    let mut group = store.load_group_or_create(&m.chat.id, &m.chat.kind).await;
+
+    if let Some(denial) = deny_unless_permitted(&group, &m, &bot, &env).await? {
+        return Ok(denial);
+    }
+
+    // Nothing is saved and the title is left alone, so it works without the right to change it
+    let date = Date::now();
+    group.refresh_member_count(&bot, &date).await;
+    let reply = match group.render_template(&date) {
+        Ok(rendered) => tr!(
+            group.language,
+            "{}\n长度: {} / {}",
+            rendered,
+            title_length(&rendered),
+            MAX_TITLE_LENGTH
+        ),
+        Err(e) => tr!(group.language, "无法渲染模板： {}", e),
+    };
+    info!("Replied: {:?}", reply);
+    return_message(&m, reply)
+}
+
+pub async fn preview_final(m: Message, env: Env, bot: Bot<'_>) -> Result<Response, WorkerError> {
+    let group_title = get_group_title(&m.chat.kind);
+    if group_title.is_none() {
+        return warn_group_only(&m);
+    }
+    let kv = bot.get_kv(&env)?;
+    let store = DataStore::new(&kv);
+    let mut group = store.load_group_or_create(&m.chat.id, &m.chat.kind).await;
+
+    let date = Date::now();
+    group.refresh_member_count(&bot, &date).await;
+    let reply = describe_final_title(&group, group.render_template(&date));
+    info!("Replied: {:?}", reply);
+    return_message(&m, reply)
+}
+
+/// Explains what applying the rendered title would end up doing under the length policy
+fn describe_final_title(group: &Group, rendered: Result<String, WorkerError>) -> String {
+    match rendered {
+        Ok(rendered) => match group.length_policy.finalize(rendered.clone()) {
+            Ok(FinalTitle::Apply(title)) if title != rendered => {
+                tr!(group.language, "标题超出长度限制，将被截断为： {}", title)
+            }
+            Ok(FinalTitle::Apply(title)) => tr!(group.language, "将应用标题： {}", title),
+            Ok(FinalTitle::Skip(_)) => tr!(
+                group.language,
+                "标题超出 {} 的长度限制，将跳过更新并保留当前标题： {}",
+                MAX_TITLE_LENGTH,
+                group.get_last_title()
+            ),
+            Err(e) => tr!(group.language, "无法应用标题： {}", e),
+        },
+        Err(e) => tr!(group.language, "无法渲染模板： {}", e),
+    }
+}
+
+pub async fn width(m: Message, env: Env, bot: Bot<'_>) -> Result<Response, WorkerError> {
+    let command = m.text.clone().unwrap_or_default();
+    let group = if get_group_title(&m.chat.kind).is_some() {
+        let kv = bot.get_kv(&env)?;
+        let store = DataStore::new(&kv);
+        Some(store.load_group_or_create(&m.chat.id, &m.chat.kind).await)
+    } else {
+        None
+    };
+    let language = group
+        .as_ref()
+        .map(|group| group.language)
+        .unwrap_or_default();
+    // Measures the given text, or the title the group would get right now
+    let text = match (get_argument(&command), &group) {
+        (Some(text), _) => text.to_string(),
+        (None, Some(group)) => match group.render_template(&Date::now()) {
+            Ok(title) => title,
+            Err(e) => return return_message(&m, tr!(language, "无法渲染模板： {}", e)),
+        },
+        (None, None) => return return_message(&m, tr!(language, "无效命令，请提供要测量的文本")),
+    };
+    let reply = tr!(
+        language,
+        "{}\n显示宽度: {}\n字符数: {}\nUTF-16 长度: {} / {}",
+        text,
+        display_width(&text),
+        text.chars().count(),
+        title_length(&text),
+        MAX_TITLE_LENGTH
+    );
+    info!("Replied: {:?}", reply);
+    return_message(&m, reply)
+}
+
+pub async fn list_specifiers(m: Message, env: Env, bot: Bot<'_>) -> Result<Response, WorkerError> {
+    // Rendered with the settings of the group, or in UTC outside of groups
+    let (context, language) = if get_group_title(&m.chat.kind).is_some() {
+        let kv = bot.get_kv(&env)?;
+        let store = DataStore::new(&kv);
+        let group = store.load_group_or_create(&m.chat.id, &m.chat.kind).await;
+        (
+            group.generate_context(group.get_local_time(&Date::now())),
+            group.language,
+        )
+    } else {
+        let naive_date = NaiveDateTime::from_timestamp((Date::now().as_millis() / 1000) as i64, 0);
+        (
+            TemplateContext::generate(to_local_time(naive_date, Tz::UTC)),
+            Language::default(),
+        )
+    };
+    let sections = [
+        tr!(language, "日期"),
+        tr!(language, "时间"),
+        tr!(language, "其他"),
+    ]
+    .iter()
+    .zip(context.grouped())
+    .map(|(name, entries)| {
+        let entries = entries
+            .iter()
+            .map(|(key, value)| format!("{{{}}} {}", key, value))
+            .collect::<Vec<String>>();
+        format!("{}:\n{}", name, entries.join("\n"))
+    })
+    .collect::<Vec<String>>();
+    let reply = tr!(
+        language,
+        "{}\n带参数的占位符:\n{{strftime:<格式>}}\n{{progress_bar[:day|year][:宽度]}}\n{{counter:<名称>}}",
+        sections.join("\n\n")
+    );
+    info!("Replied: {:?}", reply);
+    return_message(&m, reply)
+}
+
+pub async fn check_refs(m: Message, env: Env, bot: Bot<'_>) -> Result<Response, WorkerError> {
+    let group_title = get_group_title(&m.chat.kind);
+    if group_title.is_none() {
+        return warn_group_only(&m);
+    }
+    let kv = bot.get_kv(&env)?;
+    let store = DataStore::new(&kv);
+    let group = store.load_group_or_create(&m.chat.id, &m.chat.kind).await;
+
+    let template = group.assemble_title_template();
+    let references = check_references(&template, &group.counters, group.countdown_date());
+    if references.is_empty() {
+        return return_message(&m, tr!(group.language, "模板中没有占位符"));
+    }
+    let lines = references
+        .iter()
+        .map(|(key, reference)| match reference {
+            Reference::BuiltIn => tr!(group.language, "✅ {{{}}} 内置", key),
+            Reference::Counter => tr!(group.language, "✅ {{{}}} 计数器", key),
+            Reference::Dangling => tr!(group.language, "❌ {{{}}} 未定义", key),
+        })
+        .collect::<Vec<String>>();
+    let dangling = references
+        .iter()
+        .filter(|(_, reference)| *reference == Reference::Dangling)
+        .count();
+    let reply = tr!(
+        group.language,
+        "{}\n共 {} 个占位符，{} 个未定义",
+        lines.join("\n"),
+        references.len(),
+        dangling
+    );
+    info!("Replied: {:?}", reply);
+    return_message(&m, reply)
+}
+
+pub async fn freeze(m: Message, env: Env, bot: Bot<'_>) -> Result<Response, WorkerError> {
+    let group_title = get_group_title(&m.chat.kind);
+    if group_title.is_none() {
+        return warn_group_only(&m);
+    }
+    let kv = bot.get_kv(&env)?;
+    let store = DataStore::new(&kv);
+    let mut group = store.load_group_or_create(&m.chat.id, &m.chat.kind).await;
+    let command = m.text.clone().unwrap_or_default();
+    let frozen_title = get_argument(&command);
+    if frozen_title.is_none() {
+        return return_message(&m, tr!(group.language, "无效命令，没有发现要固定的标题"));
+    }
+    let frozen_title = frozen_title.unwrap().to_string();
+    if check_title_length(&frozen_title).is_err() {
+        return return_message(
+            &m,
+            tr!(
+                group.language,
+                "无效命令，标题将超出 {} 的长度限制",
+                MAX_TITLE_LENGTH
+            ),
+        );
+    }
+
+    if let Some(denial) = deny_unless_permitted(&group, &m, &bot, &env).await? {
+        return Ok(denial);
+    }
+
+    group.freeze = Some(frozen_title.clone());
+    if group.enable {
+        group.pending_apply = true;
+    }
+    record_command(&mut group, &m, frozen_title);
+    store.save_group(&group).await?;
+    let mut reply = tr!(
+        group.language,
+        "标题已固定为： {}\n标题模板将保留，使用 /unfreeze 恢复",
+        group.freeze.as_deref().unwrap_or_default()
+    );
+    if group.enable {
+        reply.push_str(tr!(
+            group.language,
+            "\n将在下次定时任务时应用，使用 /apply 立即应用"
+        ));
+    }
+    info!("Replied: {:?}", reply);
+    return_message(&m, reply)
+}
+
+pub async fn unfreeze(m: Message, env: Env, bot: Bot<'_>) -> Result<Response, WorkerError> {
+    let group_title = get_group_title(&m.chat.kind);
+    if group_title.is_none() {
+        return warn_group_only(&m);
+    }
+    let kv = bot.get_kv(&env)?;
+    let store = DataStore::new(&kv);
+    let mut group = store.load_group_or_create(&m.chat.id, &m.chat.kind).await;
+
+    if let Some(denial) = deny_unless_permitted(&group, &m, &bot, &env).await? {
+        return Ok(denial);
+    }
+
+    if group.freeze.is_none() {
+        return return_message(&m, tr!(group.language, "标题没有被固定"));
+    }
+    group.freeze = None;
+    update_template(&store, &mut group, &m).await
+}
+
+/// Saves the changed counter and marks the title for the next scheduled run
+async fn update_counter(
+    store: &DataStore<'_>,
+    group: &mut Group,
+    m: &Message,
+    name: &str,
+) -> Result<Response, WorkerError> {
+    let value = group.counters.get(name).copied().unwrap_or_default();
+    if group.enable {
+        group.pending_apply = true;
+    }
+    record_command(group, m, format!("{} = {}", name, value));
+    store.save_group(group).await?;
+    let mut reply = tr!(group.language, "计数器 {} 的值为： {}", name, value);
+    if group.enable {
+        reply.push_str(tr!(
+            group.language,
+            "\n将在下次定时任务时应用，使用 /apply 立即应用"
+        ));
+    }
+    info!("Replied: {:?}", reply);
+    return_message(m, reply)
+}
+
+/// Checks the counter name, and that a new counter doesn't exceed `MAX_COUNTERS`
+fn validate_counter(group: &Group, name: &str) -> Result<(), String> {
+    if !is_valid_name(name) {
+        return Err(tr!(
+            group.language,
+            "无效的计数器名称，只能包含字母、数字和下划线，且不超过 32 个字符"
+        )
+        .to_string());
+    }
+    if !group.counters.contains_key(name) && group.counters.len() >= MAX_COUNTERS {
+        return Err(tr!(
+            group.language,
+            "每个群最多只能有 {} 个计数器",
+            MAX_COUNTERS
+        ));
+    }
+    Ok(())
+}
+
+pub async fn set_named_template(
+    m: Message,
+    env: Env,
+    bot: Bot<'_>,
+) -> Result<Response, WorkerError> {
+    let group_title = get_group_title(&m.chat.kind);
+    if group_title.is_none() {
+        return warn_group_only(&m);
+    }
+    let kv = bot.get_kv(&env)?;
+    let store = DataStore::new(&kv);
+    let mut group = store.load_group_or_create(&m.chat.id, &m.chat.kind).await;
+    let command = m.text.clone().unwrap_or_default();
+    let arguments =
+        get_argument(&command).map(
+            |arguments| match arguments.split_once(char::is_whitespace) {
+                Some((name, template)) => (name, Some(template.trim())),
+                None => (arguments, None),
+            },
+        );
+    if arguments.is_none() {
+        return return_message(
+            &m,
+            tr!(
+                group.language,
+                "无效命令，请使用 /set_named_template <名称> [模板]"
+            ),
+        );
+    }
+    let (name, template) = arguments.unwrap();
+    if !is_valid_name(name) {
+        return return_message(
+            &m,
+            tr!(
+                group.language,
+                "无效的模板名称，只能包含字母、数字和下划线，且不超过 32 个字符"
+            ),
+        );
+    }
+
+    if let Some(denial) = deny_unless_permitted(&group, &m, &bot, &env).await? {
+        return Ok(denial);
+    }
+
+    if !group.named_templates.contains_key(name)
+        && group.named_templates.len() >= MAX_NAMED_TEMPLATES
+    {
+        return return_message(
+            &m,
+            tr!(
+                group.language,
+                "每个群最多只能保存 {} 个模板",
+                MAX_NAMED_TEMPLATES
+            ),
+        );
+    }
+    // Without a template the current one is saved, segments included
+    let segments = match template {
+        Some(template) => vec![template.to_string()],
+        None => group.title_segment.clone(),
+    };
+    let reply = tr!(
+        group.language,
+        "已保存模板 {}： {}",
+        name,
+        segments.join(&group.delimiter)
+    );
+    group.named_templates.insert(name.to_string(), segments);
+    store.save_group(&group).await?;
+    info!("Replied: {:?}", reply);
+    return_message(&m, reply)
+}
+
+pub async fn switch_template(m: Message, env: Env, bot: Bot<'_>) -> Result<Response, WorkerError> {
+    let group_title = get_group_title(&m.chat.kind);
+    if group_title.is_none() {
+        return warn_group_only(&m);
+    }
+    let kv = bot.get_kv(&env)?;
+    let store = DataStore::new(&kv);
+    let mut group = store.load_group_or_create(&m.chat.id, &m.chat.kind).await;
+    let command = m.text.clone().unwrap_or_default();
+    let name = get_argument(&command);
+
+    if let Some(denial) = deny_unless_permitted(&group, &m, &bot, &env).await? {
+        return Ok(denial);
+    }
+
+    let segments = match name.and_then(|name| group.named_templates.get(name)) {
+        Some(segments) => segments.clone(),
+        None => {
+            let mut names = group
+                .named_templates
+                .keys()
+                .cloned()
+                .collect::<Vec<String>>();
+            names.sort();
+            let reply = if names.is_empty() {
+                tr!(
+                    group.language,
+                    "还没有保存的模板，请先使用 /set_named_template"
+                )
+                .to_string()
+            } else {
+                tr!(
+                    group.language,
+                    "未找到该模板，已保存的模板： {}",
+                    names.join(", ")
+                )
+            };
+            return return_message(&m, reply);
+        }
+    };
+    group.title_segment = segments;
+    update_template(&store, &mut group, &m).await
+}
+
+pub async fn counter_set(m: Message, env: Env, bot: Bot<'_>) -> Result<Response, WorkerError> {
+    let group_title = get_group_title(&m.chat.kind);
+    if group_title.is_none() {
+        return warn_group_only(&m);
+    }
+    let kv = bot.get_kv(&env)?;
+    let store = DataStore::new(&kv);
+    let mut group = store.load_group_or_create(&m.chat.id, &m.chat.kind).await;
+    let command = m.text.clone().unwrap_or_default();
+    let arguments = get_argument(&command)
+        .and_then(|arguments| arguments.split_once(char::is_whitespace))
+        .and_then(|(name, value)| Some((name, value.trim().parse::<i64>().ok()?)));
+    if arguments.is_none() {
+        return return_message(
+            &m,
+            tr!(
+                group.language,
+                "无效命令，请使用 /counter_set <名称> <整数>"
+            ),
+        );
+    }
+    let (name, value) = arguments.unwrap();
+
+    if let Some(denial) = deny_unless_permitted(&group, &m, &bot, &env).await? {
+        return Ok(denial);
+    }
+
+    if let Err(reply) = validate_counter(&group, name) {
+        return return_message(&m, reply);
+    }
+    group.counters.insert(name.to_string(), value);
+    update_counter(&store, &mut group, &m, name).await
+}
+
+pub async fn counter_inc(m: Message, env: Env, bot: Bot<'_>) -> Result<Response, WorkerError> {
+    let group_title = get_group_title(&m.chat.kind);
+    if group_title.is_none() {
+        return warn_group_only(&m);
+    }
+    let kv = bot.get_kv(&env)?;
+    let store = DataStore::new(&kv);
+    let mut group = store.load_group_or_create(&m.chat.id, &m.chat.kind).await;
+    let command = m.text.clone().unwrap_or_default();
+    let name = get_argument(&command);
+    if name.is_none() {
+        return return_message(&m, tr!(group.language, "无效命令，没有发现计数器名称"));
+    }
+    let name = name.unwrap();
+
+    if let Some(denial) = deny_unless_permitted(&group, &m, &bot, &env).await? {
+        return Ok(denial);
+    }
+
+    if let Err(reply) = validate_counter(&group, name) {
+        return return_message(&m, reply);
+    }
+    group.increment_counter(name);
+    update_counter(&store, &mut group, &m, name).await
+}
+
+pub async fn counter_reset(m: Message, env: Env, bot: Bot<'_>) -> Result<Response, WorkerError> {
+    let group_title = get_group_title(&m.chat.kind);
+    if group_title.is_none() {
+        return warn_group_only(&m);
+    }
+    let kv = bot.get_kv(&env)?;
+    let store = DataStore::new(&kv);
+    let mut group = store.load_group_or_create(&m.chat.id, &m.chat.kind).await;
+    let command = m.text.clone().unwrap_or_default();
+    let name = get_argument(&command);
+    if name.is_none() {
+        return return_message(&m, tr!(group.language, "无效命令，没有发现计数器名称"));
+    }
+    let name = name.unwrap();
+
+    if let Some(denial) = deny_unless_permitted(&group, &m, &bot, &env).await? {
+        return Ok(denial);
+    }
+
+    if !group.reset_counter(name) {
+        return return_message(&m, tr!(group.language, "计数器 {} 不存在", name));
+    }
+    update_counter(&store, &mut group, &m, name).await
+}
+
+pub async fn push(m: Message, env: Env, bot: Bot<'_>) -> Result<Response, WorkerError> {
+    let group_title = get_group_title(&m.chat.kind);
+    if group_title.is_none() {
+        return warn_group_only(&m);
+    }
+    let kv = bot.get_kv(&env)?;
+    let store = DataStore::new(&kv);
+    let mut group = store.load_group_or_create(&m.chat.id, &m.chat.kind).await;
+    let command = m.text.clone().unwrap_or_default();
+    let new_template_segment = get_argument(&command);
+    if new_template_segment.is_none() {
+        return return_message(&m, tr!(group.language, "无效命令，没有发现新的标题片段"));
+    }
+    let new_template_segment = new_template_segment.unwrap();
+
+    if let Some(denial) = deny_unless_permitted(&group, &m, &bot, &env).await? {
+        return Ok(denial);
+    }
+
+    let warning = if group.duplicates_adjacent(new_template_segment, false) {
+        Some(warn_duplicate_segment(&group))
+    } else {
+        None
+    };
+    group.push_title_template(new_template_segment);
+    update_template_with_warning(&store, &mut group, &m, warning).await
+}
+
+pub async fn push_front(m: Message, env: Env, bot: Bot<'_>) -> Result<Response, WorkerError> {
+    let group_title = get_group_title(&m.chat.kind);
+    if group_title.is_none() {
+        return warn_group_only(&m);
+    }
+    let kv = bot.get_kv(&env)?;
+    let store = DataStore::new(&kv);
+    let mut group = store.load_group_or_create(&m.chat.id, &m.chat.kind).await;
+    let command = m.text.clone().unwrap_or_default();
+    let new_template_segment = get_argument(&command);
+    if new_template_segment.is_none() {
+        return return_message(&m, tr!(group.language, "无效命令，没有发现新的标题片段"));
+    }
+    let new_template_segment = new_template_segment.unwrap();
+
+    if let Some(denial) = deny_unless_permitted(&group, &m, &bot, &env).await? {
+        return Ok(denial);
+    }
+
+    let warning = if group.duplicates_adjacent(new_template_segment, true) {
+        Some(warn_duplicate_segment(&group))
+    } else {
+        None
+    };
+    group.push_front_title_template(new_template_segment);
+    update_template_with_warning(&store, &mut group, &m, warning).await
+}
+
+pub async fn pop(m: Message, env: Env, bot: Bot<'_>) -> Result<Response, WorkerError> {
+    let group_title = get_group_title(&m.chat.kind);
+    if group_title.is_none() {
+        return warn_group_only(&m);
+    }
+    let kv = bot.get_kv(&env)?;
+    let store = DataStore::new(&kv);
+    let mut group = store.load_group_or_create(&m.chat.id, &m.chat.kind).await;
+
+    if let Some(denial) = deny_unless_permitted(&group, &m, &bot, &env).await? {
+        return Ok(denial);
+    }
+
+    group.pop_title_template();
+    update_template(&store, &mut group, &m).await
+}
+
+pub async fn pop_front(m: Message, env: Env, bot: Bot<'_>) -> Result<Response, WorkerError> {
+    let group_title = get_group_title(&m.chat.kind);
+    if group_title.is_none() {
+        return warn_group_only(&m);
+    }
+    let kv = bot.get_kv(&env)?;
+    let store = DataStore::new(&kv);
+    let mut group = store.load_group_or_create(&m.chat.id, &m.chat.kind).await;
+
+    if let Some(denial) = deny_unless_permitted(&group, &m, &bot, &env).await? {
+        return Ok(denial);
+    }
+
+    group.pop_front_title_template();
+    update_template(&store, &mut group, &m).await
+}
+
+pub async fn move_segment(m: Message, env: Env, bot: Bot<'_>) -> Result<Response, WorkerError> {
+    let group_title = get_group_title(&m.chat.kind);
+    if group_title.is_none() {
+        return warn_group_only(&m);
+    }
+    let kv = bot.get_kv(&env)?;
+    let store = DataStore::new(&kv);
+    let mut group = store.load_group_or_create(&m.chat.id, &m.chat.kind).await;
+    let command = m.text.clone().unwrap_or_default();
+    let positions = get_argument(&command)
+        .map(|args| {
+            args.split_whitespace()
+                .map(str::parse::<usize>)
+                .collect::<Result<Vec<usize>, ParseIntError>>()
+        })
+        .and_then(Result::ok);
+    let (from, to) = match positions.as_deref() {
+        Some([from, to]) => (*from, *to),
+        _ => {
+            return return_message(
+                &m,
+                tr!(group.language, "无效命令，请使用 /move <位置> <新位置>"),
+            )
+        }
+    };
+
+    if let Some(denial) = deny_unless_permitted(&group, &m, &bot, &env).await? {
+        return Ok(denial);
+    }
+
+    if !group.move_title_segment(from, to) {
+        let reply = tr!(
+            group.language,
+            "无效命令，位置必须在 1 到 {} 之间",
+            group.title_segment.len()
+        );
+        return return_message(&m, reply);
+    }
+    update_template(&store, &mut group, &m).await
+}
+
+pub async fn edit(m: Message, env: Env, bot: Bot<'_>) -> Result<Response, WorkerError> {
+    let group_title = get_group_title(&m.chat.kind);
+    if group_title.is_none() {
+        return warn_group_only(&m);
+    }
+    let kv = bot.get_kv(&env)?;
+    let store = DataStore::new(&kv);
+    let mut group = store.load_group_or_create(&m.chat.id, &m.chat.kind).await;
+    let command = m.text.clone().unwrap_or_default();
+    let argument = get_argument(&command)
+        .and_then(|argument| argument.split_once(char::is_whitespace))
+        .and_then(|(index, segment)| Some((index.parse::<usize>().ok()?, segment.trim())));
+    let (index, new_template_segment) = match argument {
+        Some(argument) => argument,
+        None => {
+            return return_message(
+                &m,
+                tr!(group.language, "无效命令，请使用 /edit <位置> <新片段>"),
+            )
+        }
+    };
+
+    if let Some(denial) = deny_unless_permitted(&group, &m, &bot, &env).await? {
+        return Ok(denial);
+    }
+
+    if !group.edit_title_segment(index, new_template_segment) {
+        let reply = tr!(
+            group.language,
+            "无效命令，位置必须在 1 到 {} 之间",
+            group.title_segment.len()
+        );
+        return return_message(&m, reply);
+    }
+    update_template(&store, &mut group, &m).await
+}
+
+pub async fn subscribe(m: Message, env: Env, bot: Bot<'_>) -> Result<Response, WorkerError> {
+    let group_title = get_group_title(&m.chat.kind);
+    if group_title.is_none() {
+        return warn_group_only(&m);
+    }
+    let kv = bot.get_kv(&env)?;
+    let store = DataStore::new(&kv);
+    let mut group = store.load_group_or_create(&m.chat.id, &m.chat.kind).await;
+    let group_title = group_title.unwrap();
+    let user_id = m
+        .from
+        .clone()
+        .ok_or_else(|| WorkerError::RustError("Unable to retrieve user information".to_string()))?
+        .id;
+    // Subscriptions are always limited to admins, regardless of `require_admin`
+    if !bot.is_admin(ChatTarget::Id(m.chat.id), user_id).await? {
+        return deny(&m, group.language);
+    }
+
+    let result = bot
+        .send_message(
+            ChatTarget::Id(ChatId(user_id.0)),
+            &tr!(group.language, "已订阅群 {} 的标题变更通知", group_title),
+        )
+        .await?;
+    if !result.ok {
+        return return_message(
+            &m,
+            tr!(
+                group.language,
+                "无法向你发送私聊消息，请先私聊 bot 并发送 /start"
+            ),
+        );
+    }
+    group.notify_user = Some(user_id);
+    store.save_group(&group).await?;
+    info!(
+        "User {} subscribed to group {}",
+        user_id.0,
+        get_raw_chat_id(&group.id)
+    );
+    return_message(
+        &m,
+        tr!(
+            group.language,
+            "已订阅标题变更通知，标题更改时将通过私聊通知你"
+        ),
+    )
+}
+
+pub async fn link_source(m: Message, env: Env, bot: Bot<'_>) -> Result<Response, WorkerError> {
+    let group_title = get_group_title(&m.chat.kind);
+    if group_title.is_none() {
+        return warn_group_only(&m);
+    }
+    let kv = bot.get_kv(&env)?;
+    let store = DataStore::new(&kv);
+    let mut group = store.load_group_or_create(&m.chat.id, &m.chat.kind).await;
+    let command = m.text.clone().unwrap_or_default();
+    let source_id = get_argument(&command).and_then(|id| id.parse::<i64>().ok());
+    if source_id.is_none() {
+        return return_message(&m, tr!(group.language, "无效命令，请提供源群组的 ID"));
+    }
+    let source_id = ChatId(source_id.unwrap());
+    if source_id == m.chat.id {
+        return return_message(&m, tr!(group.language, "无效命令，不能链接到本群"));
+    }
+    let user_id = m
+        .from
+        .clone()
+        .ok_or_else(|| WorkerError::RustError("Unable to retrieve user information".to_string()))?
+        .id;
+    // Linking exposes the template of the source, so the user has to be an admin in both chats
+    if !bot.is_admin(ChatTarget::Id(m.chat.id), user_id).await? {
+        return deny(&m, group.language);
+    }
+    if !bot
+        .is_admin(ChatTarget::Id(source_id), user_id)
+        .await
+        .unwrap_or(false)
+    {
+        return return_message(&m, tr!(group.language, "你不是源群组的管理员"));
+    }
+    if store.load_group(&source_id).await.is_err() {
+        return return_message(
+            &m,
+            tr!(group.language, "找不到源群组，请先在源群组中使用 bot"),
+        );
+    }
+
+    group.template_source = Some(source_id);
+    if group.enable {
+        group.pending_apply = true;
+    }
+    store.save_group(&group).await?;
+    // Reload to resolve the template of the source
+    let group = store.load_group(&m.chat.id).await?;
+    info!(
+        "Group {} linked to template source {}",
+        get_raw_chat_id(&group.id),
+        get_raw_chat_id(&source_id)
+    );
+    let reply = tr!(
+        group.language,
+        "已链接至群 {} 的标题模板： {}",
+        get_raw_chat_id(&source_id),
+        group.join_title_template()
+    );
+    info!("Replied: {:?}", reply);
+    return_message(&m, reply)
+}
+
+pub async fn unlink_source(m: Message, env: Env, bot: Bot<'_>) -> Result<Response, WorkerError> {
+    let group_title = get_group_title(&m.chat.kind);
+    if group_title.is_none() {
+        return warn_group_only(&m);
+    }
+    let kv = bot.get_kv(&env)?;
+    let store = DataStore::new(&kv);
+    let mut group = store.load_group_or_create(&m.chat.id, &m.chat.kind).await;
+    let group_title = group_title.unwrap();
+
+    if let Some(denial) = deny_unless_permitted(&group, &m, &bot, &env).await? {
+        return Ok(denial);
+    }
+
+    if group.template_source.is_none() {
+        return return_message(&m, tr!(group.language, "本群没有链接模板来源"));
+    }
+    let copied = group.unlink_source(&group_title);
+    store.save_group(&group).await?;
+    info!("Group {} unlinked", get_raw_chat_id(&group.id));
+    let reply = if copied {
+        tr!(
+            group.language,
+            "已取消链接，并保留来源的标题模板： {}",
+            group.join_title_template()
+        )
+    } else {
+        tr!(
+            group.language,
+            "已取消链接，模板来源已不存在，保留本群的标题模板： {}",
+            group.join_title_template()
+        )
+    };
+    info!("Replied: {:?}", reply);
+    return_message(&m, reply)
+}
+
+pub async fn unsubscribe(m: Message, env: Env, bot: Bot<'_>) -> Result<Response, WorkerError> {
+    let group_title = get_group_title(&m.chat.kind);
+    if group_title.is_none() {
+        return warn_group_only(&m);
+    }
+    let kv = bot.get_kv(&env)?;
+    let store = DataStore::new(&kv);
+    let mut group = store.load_group_or_create(&m.chat.id, &m.chat.kind).await;
+
+    if let Some(denial) = deny_unless_permitted(&group, &m, &bot, &env).await? {
+        return Ok(denial);
+    }
+
+    group.notify_user = None;
+    store.save_group(&group).await?;
+    info!("Unsubscribed group {}", get_raw_chat_id(&group.id));
+    return_message(&m, tr!(group.language, "已取消标题变更通知"))
+}
+
+pub async fn whoami(m: Message, env: Env, bot: Bot<'_>) -> Result<Response, WorkerError> {
+    let group_title = get_group_title(&m.chat.kind);
+    if group_title.is_none() {
+        return warn_group_only(&m);
+    }
+    let kv = bot.get_kv(&env)?;
+    let store = DataStore::new(&kv);
+    let group = store.load_group_or_create(&m.chat.id, &m.chat.kind).await;
+
+    // Answered for everyone, it's meant to explain why commands are ignored
+    let user = match &m.from {
+        Some(user) if user.id == ANONYMOUS_ADMIN_ID => {
+            tr!(group.language, "匿名管理员").to_string()
+        }
+        Some(user) => user.id.0.to_string(),
+        None => tr!(group.language, "频道").to_string(),
+    };
+    let is_admin = check_admin(&m, &bot, &env).await?;
+    let is_editor = m
+        .from
+        .as_ref()
+        .map(|user| group.editors.contains(&user.id))
+        .unwrap_or(false);
+    let reply = tr!(
+        group.language,
+        "用户 ID: {}\n管理员: {}\n编辑者: {}\n需要管理权限: {}\n可以更改设置: {}",
+        user,
+        is_admin,
+        is_editor,
+        group.require_admin,
+        is_admin || is_editor || !group.require_admin
+    );
+    info!("Replied: {:?}", reply);
+    return_message(&m, reply)
+}
+
+pub async fn set_admin_only(m: Message, env: Env, bot: Bot<'_>) -> Result<Response, WorkerError> {
+    let group_title = get_group_title(&m.chat.kind);
+    if group_title.is_none() {
+        return warn_group_only(&m);
+    }
+    let kv = bot.get_kv(&env)?;
+    let store = DataStore::new(&kv);
+    let mut group = store.load_group_or_create(&m.chat.id, &m.chat.kind).await;
+    let command = m.text.clone().unwrap_or_default();
+    let require_admin = get_argument(&command).and_then(parse_switch);
+    if require_admin.is_none() {
+        return return_message(&m, tr!(group.language, "无效命令，请使用 on 或 off"));
+    }
+    let require_admin = require_admin.unwrap();
+
+    // Otherwise anyone could take the setting back once it's off
+    if let Some(denial) = deny_unless_admin(&group, &m, &bot, &env).await? {
+        return Ok(denial);
+    }
+
+    group.require_admin = require_admin;
+    store.save_group(&group).await?;
+    info!(
+        "Admin only for group {} set to {}",
+        get_raw_chat_id(&group.id),
+        require_admin
+    );
+    if require_admin {
+        return_message(
+            &m,
+            tr!(group.language, "已启用仅限管理员，只有管理员可以更改设置"),
+        )
+    } else {
+        return_message(
+            &m,
+            tr!(
+                group.language,
+                "已关闭仅限管理员，所有成员都可以更改标题模板，/reset 和 /set_admin_only 仍仅限管理员"
+            ),
+        )
+    }
+}
+
+/// The user the command is about, either the sender of the replied message or the given ID
+fn get_target_user(m: &Message) -> Option<UserId> {
+    if let Some(user) = m
+        .reply_to_message
+        .as_ref()
+        .and_then(|reply| reply.from.as_ref())
+    {
+        return Some(user.id);
+    }
+    let command = m.text.clone().unwrap_or_default();
+    get_argument(&command)
+        .and_then(|id| id.parse::<i64>().ok())
+        .map(UserId)
+}
+
+fn list_editors(group: &Group) -> String {
+    if group.editors.is_empty() {
+        return tr!(group.language, "无").to_string();
+    }
+    group
+        .editors
+        .iter()
+        .map(|UserId(id)| id.to_string())
+        .collect::<Vec<String>>()
+        .join(", ")
+}
+
+pub async fn add_editor(m: Message, env: Env, bot: Bot<'_>) -> Result<Response, WorkerError> {
+    let group_title = get_group_title(&m.chat.kind);
+    if group_title.is_none() {
+        return warn_group_only(&m);
+    }
+    let kv = bot.get_kv(&env)?;
+    let store = DataStore::new(&kv);
+    let mut group = store.load_group_or_create(&m.chat.id, &m.chat.kind).await;
+    let user_id = get_target_user(&m);
+    if user_id.is_none() {
+        return return_message(
+            &m,
+            tr!(group.language, "无效命令，请回复该用户的消息或提供用户 ID"),
+        );
+    }
+    let user_id = user_id.unwrap();
+
+    // Editors can't appoint each other
+    if let Some(denial) = deny_unless_admin(&group, &m, &bot, &env).await? {
+        return Ok(denial);
+    }
+
+    if group.add_editor(user_id) {
+        store.save_group(&group).await?;
+    }
+    let reply = tr!(group.language, "当前的编辑者： {}", list_editors(&group));
+    info!("Replied: {:?}", reply);
+    return_message(&m, reply)
+}
+
+pub async fn remove_editor(m: Message, env: Env, bot: Bot<'_>) -> Result<Response, WorkerError> {
+    let group_title = get_group_title(&m.chat.kind);
+    if group_title.is_none() {
+        return warn_group_only(&m);
+    }
+    let kv = bot.get_kv(&env)?;
+    let store = DataStore::new(&kv);
+    let mut group = store.load_group_or_create(&m.chat.id, &m.chat.kind).await;
+    let user_id = get_target_user(&m);
+    if user_id.is_none() {
+        return return_message(
+            &m,
+            tr!(group.language, "无效命令，请回复该用户的消息或提供用户 ID"),
+        );
+    }
+    let user_id = user_id.unwrap();
+
+    if let Some(denial) = deny_unless_admin(&group, &m, &bot, &env).await? {
+        return Ok(denial);
+    }
+
+    if group.remove_editor(user_id) {
+        store.save_group(&group).await?;
+    }
+    let reply = tr!(group.language, "当前的编辑者： {}", list_editors(&group));
+    info!("Replied: {:?}", reply);
+    return_message(&m, reply)
+}
+
+pub async fn export(m: Message, env: Env, bot: Bot<'_>) -> Result<Response, WorkerError> {
+    let group_title = get_group_title(&m.chat.kind);
+    if group_title.is_none() {
+        return warn_group_only(&m);
+    }
+    let kv = bot.get_kv(&env)?;
+    let store = DataStore::new(&kv);
+    let group = store.load_group_or_create(&m.chat.id, &m.chat.kind).await;
+
+    if let Some(denial) = deny_unless_permitted(&group, &m, &bot, &env).await? {
+        return Ok(denial);
+    }
+
+    let config = serde_json::to_string_pretty(&group.export_config())
+        .map_err(|e| WorkerError::RustError(e.to_string()))?;
+    info!("Exported config of group {}", get_raw_chat_id(&group.id));
+    return_code_block(&m, config)
+}
+
+pub async fn import(m: Message, env: Env, bot: Bot<'_>) -> Result<Response, WorkerError> {
+    let group_title = get_group_title(&m.chat.kind);
+    if group_title.is_none() {
+        return warn_group_only(&m);
+    }
+    let kv = bot.get_kv(&env)?;
+    let store = DataStore::new(&kv);
+    let mut group = store.load_group_or_create(&m.chat.id, &m.chat.kind).await;
+    let command = m.text.clone().unwrap_or_default();
+    let config = match get_argument(&command).map(serde_json::from_str::<GroupConfig>) {
+        Some(Ok(config)) => config,
+        Some(Err(e)) => {
+            let reply = tr!(group.language, "无效的设置，无法解析 JSON： {}", e);
+            info!("Replied: {:?}", reply);
+            return return_message(&m, reply);
+        }
+        None => {
+            return return_message(
+                &m,
+                tr!(
+                    group.language,
+                    "无效命令，请在命令后附上 /export 导出的设置"
+                ),
+            )
+        }
+    };
+
+    // Replaces every setting at once, including who may change them
+    if let Some(denial) = deny_unless_admin(&group, &m, &bot, &env).await? {
+        return Ok(denial);
+    }
+
+    group.import_config(config);
+    if group.timezone.parse::<Tz>().is_err() {
+        return return_message(&m, tr!(group.language, "无效命令，无法解析时区名称"));
+    }
+    if let Some(reply) = check_template(&group, utc_now()) {
+        info!("Replied: {:?}", reply);
+        return return_message(&m, reply);
+    }
+    let date = Date::now();
+    if group.exceeds_length_budget(&date) {
+        return warn_title_too_long(&m, group.language);
+    }
+    if group.enable {
+        group.pending_apply = true;
+        if !group
+            .apply_template(&bot, &store, &date)
+            .await
+            .unwrap_or(false)
+        {
+            group.disable_for_permission();
+            store.save_group(&group).await?;
+            return return_message(&m, group.failure_message());
+        }
+    }
+    let template = group.join_title_template();
+    record_command(&mut group, &m, template);
+    store.save_group(&group).await?;
+    info!("Imported config of group {}", get_raw_chat_id(&group.id));
+    let reply = tr!(
+        group.language,
+        "已导入设置，当前标题模板为： {}",
+        group.join_title_template()
+    );
+    info!("Replied: {:?}", reply);
+    return_message(&m, reply)
+}
+
+pub async fn set_enforce(m: Message, env: Env, bot: Bot<'_>) -> Result<Response, WorkerError> {
+    let group_title = get_group_title(&m.chat.kind);
+    if group_title.is_none() {
+        return warn_group_only(&m);
+    }
+    let kv = bot.get_kv(&env)?;
+    let store = DataStore::new(&kv);
+    let mut group = store.load_group_or_create(&m.chat.id, &m.chat.kind).await;
+    let command = m.text.clone().unwrap_or_default();
+    let enforce = get_argument(&command).and_then(parse_switch);
+    if enforce.is_none() {
+        return return_message(&m, tr!(group.language, "无效命令，请使用 on 或 off"));
+    }
+    let enforce = enforce.unwrap();
+
+    if let Some(denial) = deny_unless_permitted(&group, &m, &bot, &env).await? {
+        return Ok(denial);
+    }
+
+    group.enforce = enforce;
+    store.save_group(&group).await?;
+    info!(
+        "Enforcement for group {} set to {}",
+        get_raw_chat_id(&group.id),
+        enforce
+    );
+    if enforce {
+        return_message(
+            &m,
+            tr!(
+                group.language,
+                "已启用强制标题，手动更改的标题将在下次定时任务时被恢复"
+            ),
+        )
+    } else {
+        return_message(
+            &m,
+            tr!(
+                group.language,
+                "已关闭强制标题，手动更改的标题将保留至模板下次变化"
+            ),
+        )
+    }
+}
+
+pub async fn set_dedupe(m: Message, env: Env, bot: Bot<'_>) -> Result<Response, WorkerError> {
+    let group_title = get_group_title(&m.chat.kind);
+    if group_title.is_none() {
+        return warn_group_only(&m);
+    }
+    let kv = bot.get_kv(&env)?;
+    let store = DataStore::new(&kv);
+    let mut group = store.load_group_or_create(&m.chat.id, &m.chat.kind).await;
+    let command = m.text.clone().unwrap_or_default();
+    let dedupe = get_argument(&command).and_then(parse_switch);
+    if dedupe.is_none() {
+        return return_message(&m, tr!(group.language, "无效命令，请使用 on 或 off"));
+    }
+
+    if let Some(denial) = deny_unless_permitted(&group, &m, &bot, &env).await? {
+        return Ok(denial);
+    }
+
+    group.dedupe_segments = dedupe.unwrap();
+    update_template(&store, &mut group, &m).await
+}
+
+pub async fn set_failure_message(
+    m: Message,
+    env: Env,
+    bot: Bot<'_>,
+) -> Result<Response, WorkerError> {
+    let group_title = get_group_title(&m.chat.kind);
+    if group_title.is_none() {
+        return warn_group_only(&m);
+    }
+    let kv = bot.get_kv(&env)?;
+    let store = DataStore::new(&kv);
+    let mut group = store.load_group_or_create(&m.chat.id, &m.chat.kind).await;
+    let command = m.text.clone().unwrap_or_default();
+    let failure_message = get_argument(&command).map(|message| message.to_string());
+    if let Some(message) = &failure_message {
+        if message.encode_utf16().count() > MAX_FAILURE_MESSAGE_LENGTH {
+            return return_message(
+                &m,
+                tr!(
+                    group.language,
+                    "无效命令，失败提示不能超过 {} 个字符",
+                    MAX_FAILURE_MESSAGE_LENGTH
+                ),
+            );
+        }
+    }
+
+    if let Some(denial) = deny_unless_permitted(&group, &m, &bot, &env).await? {
+        return Ok(denial);
+    }
+
+    // Without an argument the default message is restored
+    group.failure_message = failure_message;
+    store.save_group(&group).await?;
+    let reply = tr!(
+        group.language,
+        "失败提示已变更至：{}",
+        group.failure_message()
+    );
+    info!("Replied: {:?}", reply);
+    return_message(&m, reply)
+}
+
+pub async fn set_fallback(m: Message, env: Env, bot: Bot<'_>) -> Result<Response, WorkerError> {
+    let group_title = get_group_title(&m.chat.kind);
+    if group_title.is_none() {
+        return warn_group_only(&m);
+    }
+    let kv = bot.get_kv(&env)?;
+    let store = DataStore::new(&kv);
+    let mut group = store.load_group_or_create(&m.chat.id, &m.chat.kind).await;
+    let command = m.text.clone().unwrap_or_default();
+    let fallback_title = get_argument(&command).map(|title| title.to_string());
+    if let Some(title) = &fallback_title {
+        if check_title_length(title).is_err() {
+            return return_message(
+                &m,
+                tr!(
+                    group.language,
+                    "无效命令，备用标题不能超过 {} 个字符",
+                    MAX_TITLE_LENGTH
+                ),
+            );
+        }
+    }
+
+    if let Some(denial) = deny_unless_permitted(&group, &m, &bot, &env).await? {
+        return Ok(denial);
+    }
+
+    // Without an argument the fallback is removed
+    group.fallback_title = fallback_title;
+    store.save_group(&group).await?;
+    let reply = match &group.fallback_title {
+        Some(title) => tr!(group.language, "备用标题已变更至：{}", title),
+        None => tr!(group.language, "已移除备用标题").to_string(),
+    };
+    info!("Replied: {:?}", reply);
+    return_message(&m, reply)
+}
+
+pub async fn set_announcement_template(
+    m: Message,
+    env: Env,
+    bot: Bot<'_>,
+) -> Result<Response, WorkerError> {
+    let group_title = get_group_title(&m.chat.kind);
+    if group_title.is_none() {
+        return warn_group_only(&m);
+    }
+    let kv = bot.get_kv(&env)?;
+    let store = DataStore::new(&kv);
+    let mut group = store.load_group_or_create(&m.chat.id, &m.chat.kind).await;
+    let command = m.text.clone().unwrap_or_default();
+    let template = get_argument(&command).map(|template| template.to_string());
+
+    if let Some(denial) = deny_unless_permitted(&group, &m, &bot, &env).await? {
+        return Ok(denial);
+    }
+
+    // Without an argument the announcement is removed
+    let reply = match &template {
+        Some(template) => match group.render_text(template, group.get_local_time(&Date::now())) {
+            Ok(rendered) => tr!(
+                group.language,
+                "公告模板已变更，当前渲染结果：\n{}",
+                rendered
+            ),
+            Err(e) => return return_message(&m, tr!(group.language, "无效模板： {}", e)),
+        },
+        None => tr!(group.language, "已清除公告模板").to_string(),
+    };
+    record_command(&mut group, &m, template.clone().unwrap_or_default());
+    group.announcement_template = template;
+    store.save_group(&group).await?;
+    info!("Replied: {:?}", reply);
+    return_message(&m, reply)
+}
+
+pub async fn set_announce(m: Message, env: Env, bot: Bot<'_>) -> Result<Response, WorkerError> {
+    let group_title = get_group_title(&m.chat.kind);
+    if group_title.is_none() {
+        return warn_group_only(&m);
+    }
+    let kv = bot.get_kv(&env)?;
+    let store = DataStore::new(&kv);
+    let mut group = store.load_group_or_create(&m.chat.id, &m.chat.kind).await;
+    let command = m.text.clone().unwrap_or_default();
+    let announce = get_argument(&command).and_then(parse_switch);
+    if announce.is_none() {
+        return return_message(&m, tr!(group.language, "无效命令，请使用 on 或 off"));
+    }
+    let announce = announce.unwrap();
+
+    if let Some(denial) = deny_unless_permitted(&group, &m, &bot, &env).await? {
+        return Ok(denial);
+    }
+
+    group.announce = announce;
+    store.save_group(&group).await?;
+    if !announce {
+        return return_message(&m, tr!(group.language, "已关闭公告"));
+    }
+    let mut reply = tr!(group.language, "已启用公告，标题变更后将发送并置顶公告").to_string();
+    if group.announcement_template.is_none() {
+        reply.push_str(tr!(
+            group.language,
+            "\n尚未设置公告模板，请使用 /set_announcement_template"
+        ));
+    }
+    // Pinning needs its own right, which the bot may lack even if it can change the title
+    let me = bot.get_me().await?;
+    let member = bot
+        .get_chat_member(ChatTarget::Id(m.chat.id), me.id)
+        .await?;
+    if !can_pin_messages(&member) {
+        reply.push_str(tr!(
+            group.language,
+            "\n注意：bot 没有置顶消息的权限，公告将只发送而不置顶"
+        ));
+    }
+    info!("Replied: {:?}", reply);
+    return_message(&m, reply)
+}
+
+pub async fn add_rotation(m: Message, env: Env, bot: Bot<'_>) -> Result<Response, WorkerError> {
+    let group_title = get_group_title(&m.chat.kind);
+    if group_title.is_none() {
+        return warn_group_only(&m);
+    }
+    let kv = bot.get_kv(&env)?;
+    let store = DataStore::new(&kv);
+    let mut group = store.load_group_or_create(&m.chat.id, &m.chat.kind).await;
+    let command = m.text.clone().unwrap_or_default();
+    let title = get_argument(&command);
+    if title.is_none() {
+        return return_message(&m, tr!(group.language, "无效命令，没有发现新的标题"));
+    }
+    let title = title.unwrap().to_string();
+    if check_title_length(&title).is_err() {
+        return return_message(
+            &m,
+            tr!(
+                group.language,
+                "无效命令，标题不能超过 {} 个字符",
+                MAX_TITLE_LENGTH
+            ),
+        );
+    }
+    if group.rotation.len() >= MAX_ROTATION_ENTRIES {
+        return return_message(
+            &m,
+            tr!(
+                group.language,
+                "每个群最多只能添加 {} 个轮换标题",
+                MAX_ROTATION_ENTRIES
+            ),
+        );
+    }
+
+    if let Some(denial) = deny_unless_permitted(&group, &m, &bot, &env).await? {
+        return Ok(denial);
+    }
+
+    group.rotation.push(title);
+    store.save_group(&group).await?;
+    let mut reply = tr!(
+        group.language,
+        "已添加轮换标题，当前共 {} 个",
+        group.rotation.len()
+    );
+    if !group.rotate {
+        reply.push_str(tr!(group.language, "\n使用 /set_rotate on 开始轮换"));
+    }
+    info!("Replied: {:?}", reply);
+    return_message(&m, reply)
+}
+
+pub async fn clear_rotation(m: Message, env: Env, bot: Bot<'_>) -> Result<Response, WorkerError> {
+    let group_title = get_group_title(&m.chat.kind);
+    if group_title.is_none() {
+        return warn_group_only(&m);
+    }
+    let kv = bot.get_kv(&env)?;
+    let store = DataStore::new(&kv);
+    let mut group = store.load_group_or_create(&m.chat.id, &m.chat.kind).await;
+
+    if let Some(denial) = deny_unless_permitted(&group, &m, &bot, &env).await? {
+        return Ok(denial);
+    }
+
+    group.rotation.clear();
+    group.rotation_index = 0;
+    // Back to the template, there's nothing left to rotate through
+    group.rotate = false;
+    if group.enable {
+        group.pending_apply = true;
+    }
+    store.save_group(&group).await?;
+    return_message(
+        &m,
+        tr!(group.language, "已清空轮换标题，将恢复使用标题模板"),
+    )
+}
+
+pub async fn set_rotate(m: Message, env: Env, bot: Bot<'_>) -> Result<Response, WorkerError> {
+    let group_title = get_group_title(&m.chat.kind);
+    if group_title.is_none() {
+        return warn_group_only(&m);
+    }
+    let kv = bot.get_kv(&env)?;
+    let store = DataStore::new(&kv);
+    let mut group = store.load_group_or_create(&m.chat.id, &m.chat.kind).await;
+    let command = m.text.clone().unwrap_or_default();
+    let rotate = get_argument(&command).and_then(parse_switch);
+    if rotate.is_none() {
+        return return_message(&m, tr!(group.language, "无效命令，请使用 on 或 off"));
+    }
+    let rotate = rotate.unwrap();
+    if rotate && group.rotation.is_empty() {
+        return return_message(
+            &m,
+            tr!(
+                group.language,
+                "轮换列表为空，请先使用 /add_rotation 添加标题"
+            ),
+        );
+    }
+
+    if let Some(denial) = deny_unless_permitted(&group, &m, &bot, &env).await? {
+        return Ok(denial);
+    }
+
+    group.rotate = rotate;
+    if group.enable {
+        group.pending_apply = true;
+    }
+    store.save_group(&group).await?;
+    info!(
+        "Rotation for group {} set to {}",
+        get_raw_chat_id(&group.id),
+        rotate
+    );
+    if rotate {
+        return_message(
+            &m,
+            tr!(
+                group.language,
+                "已启用标题轮换，每次定时任务将切换到下一个标题"
+            ),
+        )
+    } else {
+        return_message(
+            &m,
+            tr!(group.language, "已关闭标题轮换，将恢复使用标题模板"),
+        )
+    }
+}
+
+pub async fn set_bidi(m: Message, env: Env, bot: Bot<'_>) -> Result<Response, WorkerError> {
+    let group_title = get_group_title(&m.chat.kind);
+    if group_title.is_none() {
+        return warn_group_only(&m);
+    }
+    let kv = bot.get_kv(&env)?;
+    let store = DataStore::new(&kv);
+    let mut group = store.load_group_or_create(&m.chat.id, &m.chat.kind).await;
+    let command = m.text.clone().unwrap_or_default();
+    let bidi = get_argument(&command).and_then(parse_switch);
+    if bidi.is_none() {
+        return return_message(&m, tr!(group.language, "无效命令，请使用 on 或 off"));
+    }
+    let bidi = bidi.unwrap();
+
+    if let Some(denial) = deny_unless_permitted(&group, &m, &bot, &env).await? {
+        return Ok(denial);
+    }
+
+    group.bidi = bidi;
+    // The isolates take up some of the length budget as well
+    if group.exceeds_length_budget(&Date::now()) {
+        return return_message(
+            &m,
+            tr!(
+                group.language,
+                "无法启用双向文本隔离，启用后标题将超出 {} 的长度限制",
+                MAX_TITLE_LENGTH
+            ),
+        );
+    }
+    if group.enable {
+        group.pending_apply = true;
+    }
+    store.save_group(&group).await?;
+    info!(
+        "Bidi isolation for group {} set to {}",
+        get_raw_chat_id(&group.id),
+        bidi
+    );
+    if bidi {
+        return_message(
+            &m,
+            tr!(
+                group.language,
+                "已启用双向文本隔离，每段标题的文字方向将互不影响"
+            ),
+        )
+    } else {
+        return_message(&m, tr!(group.language, "已关闭双向文本隔离"))
+    }
+}
+
+pub async fn set_auto_reenable(
+    m: Message,
+    env: Env,
+    bot: Bot<'_>,
+) -> Result<Response, WorkerError> {
+    let group_title = get_group_title(&m.chat.kind);
+    if group_title.is_none() {
+        return warn_group_only(&m);
+    }
+    let kv = bot.get_kv(&env)?;
+    let store = DataStore::new(&kv);
+    let mut group = store.load_group_or_create(&m.chat.id, &m.chat.kind).await;
+    let command = m.text.clone().unwrap_or_default();
+    let auto_reenable = get_argument(&command).and_then(parse_switch);
+    if auto_reenable.is_none() {
+        return return_message(&m, tr!(group.language, "无效命令，请使用 on 或 off"));
+    }
+    let auto_reenable = auto_reenable.unwrap();
+
+    if let Some(denial) = deny_unless_permitted(&group, &m, &bot, &env).await? {
+        return Ok(denial);
+    }
+
+    group.auto_reenable_on_promotion = auto_reenable;
+    store.save_group(&group).await?;
+    info!(
+        "Auto re-enabling for group {} set to {}",
+        get_raw_chat_id(&group.id),
+        auto_reenable
+    );
+    if auto_reenable {
+        return_message(
+            &m,
+            tr!(
+                group.language,
+                "已启用自动恢复，因权限不足而停用的自动标题更改将在 bot 重新获得权限后自动启用"
+            ),
+        )
+    } else {
+        return_message(&m, tr!(group.language, "已关闭自动恢复"))
+    }
+}
+
+pub async fn errors(m: Message, env: Env, bot: Bot<'_>) -> Result<Response, WorkerError> {
+    let group_title = get_group_title(&m.chat.kind);
+    if group_title.is_none() {
+        return warn_group_only(&m);
+    }
+    let kv = bot.get_kv(&env)?;
+    let store = DataStore::new(&kv);
+    let group = store.load_group_or_create(&m.chat.id, &m.chat.kind).await;
+
+    if let Some(denial) = deny_unless_permitted(&group, &m, &bot, &env).await? {
+        return Ok(denial);
+    }
+
+    if group.recent_errors.is_empty() {
+        return return_message(&m, tr!(group.language, "最近没有发生错误"));
+    }
+    let reply = group.describe_errors();
+    info!("Replied: {:?}", reply);
+    return_message(&m, tr!(group.language, "最近的错误:\n{}", reply))
+}
+
+pub async fn history(m: Message, env: Env, bot: Bot<'_>) -> Result<Response, WorkerError> {
+    let group_title = get_group_title(&m.chat.kind);
+    if group_title.is_none() {
+        return warn_group_only(&m);
+    }
+    let kv = bot.get_kv(&env)?;
+    let store = DataStore::new(&kv);
+    let group = store.load_group_or_create(&m.chat.id, &m.chat.kind).await;
+
+    if let Some(denial) = deny_unless_permitted(&group, &m, &bot, &env).await? {
+        return Ok(denial);
+    }
+
+    if group.history.is_empty() {
+        return return_message(&m, tr!(group.language, "还没有更改记录"));
+    }
+    let reply = group.describe_history();
+    info!("Replied: {:?}", reply);
+    return_message(&m, tr!(group.language, "最近的更改:\n{}", reply))
+}
+
+pub async fn refresh_admins(m: Message, env: Env, bot: Bot<'_>) -> Result<Response, WorkerError> {
+    let group_title = get_group_title(&m.chat.kind);
+    if group_title.is_none() {
+        return warn_group_only(&m);
+    }
+    let user_id = m
+        .from
+        .clone()
+        .ok_or_else(|| WorkerError::RustError("Unable to retrieve user information".to_string()))?
+        .id;
+    let language = reply_language(&m, &env, &bot).await?;
+    // Checked without the cache, so admins that were just promoted can refresh it too
+    if !bot.is_admin(ChatTarget::Id(m.chat.id), user_id).await? {
+        return deny(&m, language);
+    }
+    let cache = bot.get_cache(&env);
+    if !cache.is_enabled() {
+        return return_message(&m, tr!(language, "未启用缓存，管理员身份总是实时获取"));
+    }
+    cache.delete(&admin_cache_key(&m.chat.id)).await;
+    info!(
+        "Cleared admin cache of group {}",
+        get_raw_chat_id(&m.chat.id)
+    );
+    return_message(
+        &m,
+        tr!(
+            language,
+            "已清除本群的管理员缓存，下次操作时将重新获取管理员身份"
+        ),
+    )
+}
+
+pub async fn metrics(m: Message, env: Env, bot: Bot<'_>) -> Result<Response, WorkerError> {
+    let language = reply_language(&m, &env, &bot).await?;
+    let kv = bot.get_kv(&env)?;
+    let summary = kv
+        .get(KEY_SCHEDULED_SUMMARY)
+        .json::<ScheduledSummary>()
+        .await?;
+    let summary = match summary {
+        Some(summary) => summary,
+        None => return return_message(&m, tr!(language, "还没有定时任务的运行记录")),
+    };
+    let started_at = NaiveDateTime::from_timestamp((summary.started_at / 1000) as i64, 0);
+    let reply = tr!(
+        language,
+        r#"上次定时任务: {} UTC
+           耗时: {} ms
+           已处理: {}
+           已更新: {}
+           未变化: {}
+           已禁用: {}
+           未到更新间隔: {}
+           无效: {}
+           失败: {}
+           已完成: {}"#,
+        started_at.format("%Y-%m-%d %H:%M:%S"),
+        summary.elapsed_ms,
+        summary.processed,
+        summary.updated,
+        summary.unchanged,
+        summary.disabled,
+        summary.not_due,
+        summary.invalid,
+        summary.failed,
+        summary.complete
+    );
+    info!("Replied: {:?}", reply);
+    return_message(&m, reply)
+}
+
+pub async fn perms(m: Message, env: Env, bot: Bot<'_>) -> Result<Response, WorkerError> {
+    let group_title = get_group_title(&m.chat.kind);
+    if group_title.is_none() {
+        return warn_group_only(&m);
+    }
+    let kv = bot.get_kv(&env)?;
+    let store = DataStore::new(&kv);
+    let group = store.load_group_or_create(&m.chat.id, &m.chat.kind).await;
+
+    if let Some(denial) = deny_unless_permitted(&group, &m, &bot, &env).await? {
+        return Ok(denial);
+    }
+
+    let me = bot.get_me().await?;
+    let member = bot
+        .get_chat_member(ChatTarget::Id(m.chat.id), me.id)
+        .await?;
+    let reply = tr!(
+        group.language,
+        "bot 在本群的权限:\n{}",
+        describe_permissions(&member, group.language)
+    );
+    info!("Replied: {:?}", reply);
+    return_message(&m, reply)
+}
+
+/// Sets the title to what it already is, so admins can tell whether Telegram would let the bot
+/// change it before enabling
+pub async fn test_permission(m: Message, env: Env, bot: Bot<'_>) -> Result<Response, WorkerError> {
+    let group_title = get_group_title(&m.chat.kind);
+    if group_title.is_none() {
+        return warn_group_only(&m);
+    }
+    let kv = bot.get_kv(&env)?;
+    let store = DataStore::new(&kv);
+    // Not saved afterwards, a failed test shouldn't show up as a failure of the group
+    let mut group = store.load_group_or_create(&m.chat.id, &m.chat.kind).await;
+    let group_title = group_title.unwrap();
+
+    if let Some(denial) = deny_unless_permitted(&group, &m, &bot, &env).await? {
+        return Ok(denial);
+    }
+
+    // The title the chat has right now, so the request never changes anything
+    let reply = if group.update_title(&bot, group_title).await? {
+        tr!(group.language, "测试成功，bot 可以更改群标题").to_string()
+    } else {
+        let (error_code, description) = group
+            .recent_errors
+            .last()
+            .map(|record| (record.error_code, record.description.clone()))
+            .unwrap_or_default();
+        if description.contains("not modified") {
+            tr!(group.language, "测试成功，bot 可以更改群标题").to_string()
+        } else if matches!(error_code, 400 | 403) {
+            let me = bot.get_me().await?;
+            let member = bot
+                .get_chat_member(ChatTarget::Id(m.chat.id), me.id)
+                .await?;
+            if !is_admin_status(&member.status) {
+                tr!(group.language, "bot 不是管理员，无法更改群标题").to_string()
+            } else if member.can_change_info == Some(false) {
+                tr!(
+                    group.language,
+                    "bot 缺少「修改群组信息」权限，无法更改群标题"
+                )
+                .to_string()
+            } else {
+                tr!(group.language, "Telegram 拒绝更改群标题: {}", description)
+            }
+        } else {
+            tr!(group.language, "测试失败 ({}): {}", error_code, description)
+        }
+    };
+    info!("Replied: {:?}", reply);
+    return_message(&m, reply)
+}
+
+pub async fn repair(m: Message, env: Env, bot: Bot<'_>) -> Result<Response, WorkerError> {
+    let group_title = get_group_title(&m.chat.kind);
+    if group_title.is_none() {
+        return warn_group_only(&m);
+    }
+    let kv = bot.get_kv(&env)?;
+    let store = DataStore::new(&kv);
+    let mut group = store.load_group_or_create(&m.chat.id, &m.chat.kind).await;
+    let group_title = group_title.unwrap();
+
+    if let Some(denial) = deny_unless_admin(&group, &m, &bot, &env).await? {
+        return Ok(denial);
+    }
+
+    let repaired = group.repair(&group_title, utc_now());
+    if repaired.is_empty() {
+        return return_message(&m, tr!(group.language, "未发现问题"));
+    }
+    store.save_group(&group).await?;
+    let reply = tr!(group.language, "已修复:\n{}", repaired.join("\n"));
+    info!(
+        "Repaired group {}: {:?}",
+        get_raw_chat_id(&group.id),
+        repaired
+    );
+    return_message(&m, reply)
+}
+
+/// Renders the template of an inline query, a leading timezone like `Asia/Shanghai {H}:{M}` is
+/// used to render it, UTC is used otherwise
+pub fn render_inline_query(query: &str, utc_time: NaiveDateTime) -> Result<String, WorkerError> {
+    let query = query.trim();
+    if query.is_empty() {
+        return Err(WorkerError::RustError("Template is empty".to_string()));
+    }
+    let (timezone, template) = match query.split_once(char::is_whitespace) {
+        Some((timezone, template)) => match timezone.parse::<Tz>() {
+            Ok(timezone) => (timezone, template.trim()),
+            Err(_) => (Tz::UTC, query),
+        },
+        None => (Tz::UTC, query),
+    };
+    render_template_at(
+        template,
+        to_local_time(utc_time, timezone),
+        NumberFormat::Plain,
+        TitleLocale::En,
+        WeekStart::default(),
+        &BTreeMap::new(),
+        None,
+        None,
+    )
+}
+
+fn inline_query_article<'a>(
+    id: &str,
+    title: String,
+    description: &'a str,
+    message: String,
+) -> InlineQueryResult<'a> {
+    InlineQueryResult::Article(InlineQueryResultArticle {
+        id: ResultId(id.to_string()),
+        title: Cow::from(title),
+        input_message_content: InputMessageContent::Text(InputTextMessageContent {
+            message_text: Cow::from(message),
+            parse_mode: None,
+            disable_web_page_preview: None,
+        }),
+        reply_markup: None,
+        url: None,
+        hide_url: None,
+        description: Some(Cow::from(description)),
+        thumb_url: None,
+        thumb_width: None,
+        thumb_height: None,
+    })
+}
+
+/// The single result answering an inline query, an error is shown as a result as well
+fn inline_query_result(query: &str, utc_time: NaiveDateTime) -> InlineQueryResult<'static> {
+    match render_inline_query(query, utc_time) {
+        Ok(rendered) => inline_query_article(
+            "rendered",
+            rendered.clone(),
+            tr!(Language::default(), "点击发送"),
+            rendered,
+        ),
+        Err(e) => inline_query_article(
+            "error",
+            tr!(Language::default(), "无法渲染模板").to_string(),
+            "",
+            e.to_string(),
+        ),
+    }
+}
+
+pub async fn inline_query(
+    query: InlineQuery,
+    _env: Env,
+    _bot: Bot<'_>,
+) -> Result<Response, WorkerError> {
+    let result = inline_query_result(&query.query, utc_now());
+    Response::from_json(&WebhookReply::from(AnswerInlineQuery {
+        inline_query_id: query.id,
+        results: Cow::from(vec![result]),
+        // The result depends on the current time
+        cache_time: Some(0),
+        is_personal: None,
+        next_offset: None,
+        switch_pm_text: None,
+        switch_pm_parameter: None,
+    }))
+}
+
+pub async fn my_chat_member(
+    update: ChatMemberUpdate,
+    env: Env,
+    bot: Bot<'_>,
+) -> Result<Response, WorkerError> {
+    if !gained_change_info(&update.old_chat_member, &update.new_chat_member) {
+        return Response::empty();
+    }
+    info!("Regained the right to change info in {}", update.chat.id.0);
+    let kv = bot.get_kv(&env)?;
+    let store = DataStore::new(&kv);
+    let mut group = match store.load_group(&update.chat.id).await {
+        Ok(group) => group,
+        Err(_) => return Response::empty(),
+    };
+    if !group.should_reenable() {
+        return Response::empty();
+    }
+    group.enable = true;
+    group.disabled_by_permission = false;
+    let notice = match group.apply_template(&bot, &store, &Date::now()).await {
+        Ok(true) => {
+            group.record_history(HistoryEntry {
+                timestamp: Date::now().as_millis(),
+                source: AUTO_REENABLE_SOURCE.to_string(),
+                user: Some(describe_user(&update.from)),
+                value: group.get_last_title().to_string(),
+            });
+            tr!(
+                group.language,
+                "bot 已重新获得权限，已自动恢复标题更改： {}",
+                group.get_last_title()
+            )
+        }
+        _ => {
+            group.disable_for_permission();
+            tr!(
+                group.language,
+                "bot 已重新获得权限，但仍未能成功更改群标题，请检查 bot 帐号权限"
+            )
+            .to_string()
+        }
+    };
+    store.save_group(&group).await?;
+    info!(
+        "Re-enabled for group {}: {}",
+        get_raw_chat_id(&group.id),
+        group.enable
+    );
+    Response::from_json(&WebhookReply::from(SendMessage::new(
+        ChatTarget::Id(update.chat.id),
+        &notice,
+    )))
+}
+
+/// Counters of a scheduled run
+#[derive(Clone, Debug, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct ScheduledSummary {
+    /// When the run started
+    pub started_at: u64,
+    pub elapsed_ms: u64,
+    pub processed: usize,
+    pub updated: usize,
+    pub unchanged: usize,
+    pub disabled: usize,
+    /// Groups skipped because their update interval hasn't passed
+    #[serde(default)]
+    pub not_due: usize,
+    pub invalid: usize,
+    pub failed: usize,
+    /// Whether the run went through all groups before the time budget ran out
+    pub complete: bool,
+    /// Whether titles were only rendered and logged, nothing was changed
+    #[serde(default)]
+    pub dry_run: bool,
+}
+
+impl ScheduledSummary {
+    /// Logs the summary and keeps it as the latest one for `/metrics`
+    async fn finish(mut self, kv: &KvStore) -> ScheduledSummary {
+        self.elapsed_ms = Date::now().as_millis() - self.started_at;
+        info!(
+            "Scheduled run summary: {}",
+            serde_json::to_string(&self).unwrap_or_default()
+        );
+        // Dry runs don't replace the summary of the last real run
+        if self.dry_run {
+            return self;
+        }
+        let result = match kv.put(KEY_SCHEDULED_SUMMARY, &self) {
+            Ok(builder) => builder.execute().await,
+            Err(e) => Err(e),
+        };
+        if let Err(e) = result {
+            error!("Unable to save the scheduled run summary: {}", e);
+        }
+        self
+    }
+}
+
+/// Whether the flag in the environment is switched on, unset flags are off
+fn is_flag_set(env: &Env, name: &str) -> bool {
+    env.var(name)
+        .map(|var| parse_switch(&var.to_string()) == Some(true))
+        .unwrap_or(false)
+}
+
+/// Whether `DRY_RUN` is switched on, scheduled runs only log the titles they would set then
+fn is_dry_run(env: &Env) -> bool {
+    is_flag_set(env, VAR_DRY_RUN)
+}
+
+/// What happened to a group in a scheduled run
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum ScheduledOutcome {
+    /// The time budget ran out before the group was started
+    OutOfTime,
+    Invalid,
+    Disabled,
+    NotDue,
+    Updated,
+    Unchanged,
+    Failed,
+}
+
+impl ScheduledSummary {
+    fn record(&mut self, outcome: ScheduledOutcome) {
+        let counter = match outcome {
+            ScheduledOutcome::OutOfTime => return,
+            ScheduledOutcome::Invalid => &mut self.invalid,
+            ScheduledOutcome::Disabled => &mut self.disabled,
+            ScheduledOutcome::NotDue => &mut self.not_due,
+            ScheduledOutcome::Updated => &mut self.updated,
+            ScheduledOutcome::Unchanged => &mut self.unchanged,
+            ScheduledOutcome::Failed => &mut self.failed,
+        };
+        *counter += 1;
+        self.processed += 1;
+    }
+}
+
+/// Number of groups processed at once from `SCHEDULED_CONCURRENCY`, falls back to the default if
+/// it's invalid
+fn scheduled_concurrency(env: &Env) -> usize {
+    match env.var(VAR_SCHEDULED_CONCURRENCY) {
+        Ok(var) => match var.to_string().trim().parse() {
+            Ok(concurrency) if concurrency > 0 => concurrency,
+            _ => {
+                error!("Invalid {}: {}", VAR_SCHEDULED_CONCURRENCY, var.to_string());
+                DEFAULT_SCHEDULED_CONCURRENCY
+            }
+        },
+        Err(_) => DEFAULT_SCHEDULED_CONCURRENCY,
+    }
+}
+
+/// Renames every enabled group according to its template, in a dry run the new titles are only
+/// logged and nothing is written to Telegram or KV
+pub async fn run_scheduled(
+    bot: &Bot<'_>,
+    env: &Env,
+    dry_run: bool,
+) -> Result<ScheduledSummary, WorkerError> {
+    let kv = bot.get_kv(env)?;
+    let store = DataStore::new(&kv);
+    let (groups, resuming) = store.get_group_keys_for(SCHEDULED_JOB).await?;
+    let date = Date::now();
+    let mut summary = ScheduledSummary {
+        started_at: date.as_millis(),
+        dry_run,
+        ..Default::default()
+    };
+    let concurrency = scheduled_concurrency(env);
+    info!(
+        "Processing {} groups, {} at a time",
+        groups.len(),
+        concurrency
+    );
+    // The results come back in the order the groups were started, so everything after the first
+    // group that ran out of time did too, and the cursor can point at it
+    let mut results = stream::iter(groups)
+        .map(|group_name| {
+            let (store, date) = (&store, &date);
+            async move {
+                let outcome = process_scheduled_group(bot, store, &group_name, date, dry_run)
+                    .await
+                    .unwrap_or_else(|e| {
+                        error!("Unable to process group {}: {}", group_name, e);
+                        ScheduledOutcome::Failed
+                    });
+                (group_name, outcome)
+            }
+        })
+        .buffered(concurrency);
+    let mut stopped_at = None;
+    while let Some((group_name, outcome)) = results.next().await {
+        // Stop before the runtime kills the job, the next run picks up from here
+        if outcome == ScheduledOutcome::OutOfTime {
+            info!("Running out of time, stopping at group {}", group_name);
+            stopped_at = Some(group_name);
+            break;
+        }
+        summary.record(outcome);
+    }
+    if !dry_run {
+        let cursor = next_cursor(stopped_at.as_deref(), resuming);
+        store.update_cursor(SCHEDULED_JOB, cursor).await?;
+    }
+    summary.complete = stopped_at.is_none();
+    Ok(summary.finish(&kv).await)
+}
+
+/// Checks whether a scheduled run at `utc_time` should rename the group, activating the pending
+/// templates that are due. Returns why it's skipped otherwise.
+fn skip_scheduled_group(
+    group: &mut Group,
+    group_name: &str,
+    utc_time: NaiveDateTime,
+) -> Option<ScheduledOutcome> {
+    if !group.enable {
+        info!("Group {} is disabled, skipping...", group_name);
+        return Some(ScheduledOutcome::Disabled);
+    }
+    let local_time = group.get_time(utc_time);
+    if !group.is_active_at(local_time.hour()) {
+        info!(
+            "Group {} is outside its active hours, skipping...",
+            group_name
+        );
+        return Some(ScheduledOutcome::NotDue);
+    }
+    let template_activated = group.activate_pending_templates(local_time.date().naive_local());
+    if template_activated {
+        info!("Scheduled template activated for group {}", group_name);
+    } else if !group.is_due(utc_time.timestamp_millis() as u64) {
+        info!("Group {} is not due for an update, skipping...", group_name);
+        return Some(ScheduledOutcome::NotDue);
+    }
+    // Doesn't count towards disabling the group, renaming it was never attempted
+    if group.is_template_empty() {
+        error!("Template of group {} is empty, skipping...", group_name);
+        return Some(ScheduledOutcome::Failed);
+    }
+    None
+}
+
+/// Renames a single group in a scheduled run, the errors only affect this group
+async fn process_scheduled_group(
+    bot: &Bot<'_>,
+    store: &DataStore<'_>,
+    group_name: &str,
+    date: &Date,
+    dry_run: bool,
+) -> Result<ScheduledOutcome, WorkerError> {
+    if Date::now().as_millis() - date.as_millis() > SCHEDULED_TIME_BUDGET {
+        return Ok(ScheduledOutcome::OutOfTime);
+    }
+    let chat_id: Result<i64, ParseIntError> = group_name.parse();
+    if chat_id.is_err() {
+        info!("Group ID {} is invalid, skipping...", group_name);
+        return Ok(ScheduledOutcome::Invalid);
+    }
+    let chat_id = ChatId(chat_id.unwrap());
+    let mut group = store.load_group(&chat_id).await?;
+    if group.pending_title.is_some() && !dry_run {
+        match bot.get_chat(ChatTarget::Id(chat_id)).await {
+            Ok(chat) => {
+                let live_title = get_group_title(&chat.kind).unwrap_or_default();
+                if group.reconcile_pending_title(&live_title) {
+                    store.save_group(&group).await?;
+                }
+            }
+            Err(e) => error!("Unable to get the title of group {}: {}", group_name, e),
+        }
+    }
+    let utc_time = NaiveDateTime::from_timestamp((date.as_millis() / 1000) as i64, 0);
+    let pending_templates = group.pending_templates.len();
+    if let Some(outcome) = skip_scheduled_group(&mut group, group_name, utc_time) {
+        return Ok(outcome);
+    }
+    let template_activated = group.pending_templates.len() != pending_templates;
+    if dry_run {
+        group.refresh_member_count(bot, date).await;
+        return Ok(match group.finalize_title(date) {
+            Ok(FinalTitle::Apply(new_title)) if new_title != group.last_title => {
+                info!(
+                    "[DRY RUN] Group {} would be renamed from {} to {}",
+                    group_name, group.last_title, new_title
+                );
+                ScheduledOutcome::Updated
+            }
+            Ok(_) => {
+                info!(
+                    "[DRY RUN] Title for group {} would be unchanged",
+                    group_name
+                );
+                ScheduledOutcome::Unchanged
+            }
+            Err(e) => {
+                error!(
+                    "[DRY RUN] Unable to render title for group {}: {}",
+                    group_name, e
+                );
+                ScheduledOutcome::Failed
+            }
+        });
+    }
+    let old_title = group.get_last_title().to_string();
+    let last_error_time = group.last_error_time();
+    let pending_apply = group.pending_apply;
+    let consecutive_failures = group.consecutive_failures;
+    let rotation_index = group.rotation_index;
+    let member_count = group.member_count;
+    let outcome = match group.apply_scheduled(bot, store, date).await {
+        Ok(true) => {
+            info!("Title for group {} updated successfully", group_name);
+            group.consecutive_failures = 0;
+            ScheduledOutcome::Updated
+        }
+        Ok(false) => {
+            info!("Title for group {} unchanged, skipping...", group_name);
+            group.consecutive_failures = 0;
+            ScheduledOutcome::Unchanged
+        }
+        Err(e) => {
+            error!("Unable to update title for group {}: {}", group_name, e);
+            if group.was_refused_since(last_error_time) {
+                error!(
+                    "Telegram refused to rename group {}, disabling...",
+                    group_name
+                );
+                group.disable_for_permission();
+                notify_scheduled_failure(bot, &mut group).await;
+            } else if group.record_scheduled_failure() {
+                error!(
+                    "Group {} failed {} times in a row, disabling...",
+                    group_name, MAX_CONSECUTIVE_FAILURES
+                );
+            }
+            ScheduledOutcome::Failed
+        }
+    };
+    let last_description = group.last_description.clone();
+    if let Err(e) = group.apply_description(bot, date).await {
+        error!(
+            "Unable to update description for group {}: {}",
+            group_name, e
+        );
+    }
+    let title_changed = group.get_last_title() != old_title;
+    if title_changed {
+        group.record_history(HistoryEntry {
+            timestamp: date.as_millis(),
+            source: SCHEDULED_SOURCE.to_string(),
+            user: None,
+            value: group.get_last_title().to_string(),
+        });
+        notify_subscriber(bot, &mut group, &old_title).await;
+    }
+    if template_activated
+        || title_changed
+        || pending_apply != group.pending_apply
+        || consecutive_failures != group.consecutive_failures
+        || rotation_index != group.rotation_index
+        || member_count != group.member_count
+        || last_description != group.last_description
+        || group.last_error_time() != last_error_time
+    {
+        if let Err(e) = store.save_group(&group).await {
+            error!("Unable to save group {}: {}", group_name, e);
+        }
+    }
+    Ok(outcome)
+}
+
+/// Checks the bearer token against the operator secret, the route is hidden if it's not set
+fn check_operator(req: &Request, env: &Env) -> Result<Option<Response>, WorkerError> {
+    let expected = match env.secret(VAR_OPERATOR_TOKEN) {
+        Ok(token) => token.to_string(),
+        Err(_) => return Ok(Some(Response::error("Not Found", 404)?)),
+    };
+    let provided = req.headers().get("Authorization")?.unwrap_or_default();
+    match provided.strip_prefix("Bearer ") {
+        Some(token) if !expected.is_empty() && token_matches(&expected, token) => Ok(None),
+        _ => {
+            error!("Rejected unauthorized operator request");
+            Ok(Some(Response::error("Unauthorized", 401)?))
+        }
+    }
+}
+
+/// Checks everything the worker can't run without is configured, naming all that is missing
+fn check_env(env: &Env) -> Result<(), WorkerError> {
+    let missing = missing_env(
+        |name| env.secret(name).is_ok(),
+        |name| env.var(name).ok().map(|var| var.to_string()),
+        |name| env.kv(name).is_ok(),
+    );
+    if missing.is_empty() {
+        return Ok(());
+    }
+    Err(WorkerError::RustError(format!(
+        "Missing configuration, check wrangler.toml and the secrets: {}",
+        missing.join(", ")
+    )))
+}
+
+/// Names the required secrets, variables and bindings that aren't there, given lookups of each
+fn missing_env<S, V, K>(has_secret: S, var: V, has_kv: K) -> Vec<String>
+where
+    S: Fn(&str) -> bool,
+    V: Fn(&str) -> Option<String>,
+    K: Fn(&str) -> bool,
+{
+    let mut missing = Vec::new();
+    if !has_secret(DEFAULT_SECRET_TOKEN) {
+        missing.push(format!("secret {}", DEFAULT_SECRET_TOKEN));
+    }
+    if var(VAR_USERNAME).is_none() {
+        missing.push(format!("variable {}", VAR_USERNAME));
+    }
+    match var(VAR_KV_STORE) {
+        Some(kv_store) => {
+            if !has_kv(&kv_store) {
+                missing.push(format!(
+                    "KV namespace binding {} (named by {})",
+                    kv_store, VAR_KV_STORE
+                ));
+            }
+        }
+        None => missing.push(format!("variable {}", VAR_KV_STORE)),
+    }
+    missing
+}
+
+#[event(scheduled)]
+pub async fn handle_scheduled(_req: ScheduledEvent, env: Env, _ctx: ScheduleContext) {
+    worker_logger::init_with_string("info");
+    set_panic_hook();
+
+    let bot = check_env(&env)
+        .and_then(|_| Bot::new_with_env(&env, DEFAULT_SECRET_TOKEN, VAR_USERNAME, VAR_KV_STORE));
+    let mut bot = match bot {
+        Ok(bot) => bot,
+        Err(e) => {
+            error!("Skipping the scheduled run: {}", e);
+            return;
+        }
+    };
+    bot.set_cache_store(env.var(VAR_CACHE_STORE).ok().map(|var| var.to_string()));
+    if let Err(e) = run_scheduled(&bot, &env, is_dry_run(&env)).await {
+        error!("Scheduled run failed: {}", e);
+    }
+}
+
+pub async fn main_inner(
     req: Request,
     env: Env,
     _ctx: worker::Context,
@@ -405,19 +3863,122 @@ pub async fn main_inner(
     set_panic_hook();
 
     // Bot
+    check_env(&env)?;
     let mut bot = Bot::new_with_env(&env, DEFAULT_SECRET_TOKEN, VAR_USERNAME, VAR_KV_STORE)?;
-    bot.register_command("echo", echo);
-    bot.register_command("start", start);
-    bot.register_command("status", status);
-    bot.register_command("enable", enable);
-    bot.register_command("disable", disable);
-    bot.register_command("set_template", set_template);
-    bot.register_command("set_delimiter", set_delimiter);
-    bot.register_command("set_timezone", set_timezone);
-    bot.register_command("push", push);
-    bot.register_command("push_front", push_front);
-    bot.register_command("pop", pop);
-    bot.register_command("pop_front", pop_front);
+    bot.set_cache_store(env.var(VAR_CACHE_STORE).ok().map(|var| var.to_string()));
+    bot.set_webhook_secret(
+        env.secret(VAR_WEBHOOK_SECRET)
+            .ok()
+            .map(|var| var.to_string()),
+    );
+    bot.set_command_prefixes(&command_prefixes(&env));
+    bot.set_cooldown(command_cooldown(&env));
+    bot.exempt_from_cooldown(COOLDOWN_EXEMPT_COMMANDS);
+    bot.register_throttled(slow_down);
+    bot.register_command_with_desc("echo", "复读消息", echo);
+    bot.register_command_with_desc("start", "显示 bot 版本", start);
+    bot.register_command_with_desc("help", "列出所有命令", help);
+    bot.register_command_with_desc("status", "显示本群的设置", status);
+    bot.register_command_with_desc("whoami", "显示你在本群的权限", whoami);
+    bot.register_command_with_desc("export", "导出本群的设置", export);
+    bot.register_command_with_desc("import", "导入本群的设置", import);
+    bot.register_command_with_desc("enable", "启用自动更改标题", enable);
+    bot.register_command_with_desc("apply", "立即应用标题模板", apply);
+    bot.register_command_with_desc("disable", "关闭自动更改标题", disable);
+    bot.register_command_with_desc("undo", "恢复上一个标题", undo);
+    bot.register_command_with_desc("reset", "恢复本群的原始标题并关闭自动更改", reset);
+    bot.register_command_with_desc("set_template", "设置标题模板", set_template);
+    bot.register_command_with_desc("set_template_at", "在指定日期更改标题模板", set_template_at);
+    bot.register_command_with_desc("set_named_template", "保存一个命名模板", set_named_template);
+    bot.register_command_with_desc("switch_template", "切换到已保存的模板", switch_template);
+    bot.register_command_with_desc("set_description", "设置群简介模板", set_description);
+    bot.register_command_with_desc("clear", "将模板重置为当前的群标题", clear);
+    bot.register_command_with_desc("flatten", "将所有片段合并为一个模板", flatten);
+    bot.register_command_with_desc("set_delimiter", "设置片段之间的分隔符", set_delimiter);
+    bot.register_command_with_desc("set_delimiters", "设置轮流使用的多个分隔符", set_delimiters);
+    bot.register_command_with_desc("reset_delimiter", "恢复默认分隔符", reset_delimiter);
+    bot.register_command_with_desc("set_timezone", "设置时区", set_timezone);
+    bot.register_command_with_desc("set_countdown", "设置倒数的目标日期", set_countdown);
+    bot.register_command_with_desc("set_interval", "设置更新间隔", set_interval);
+    bot.register_command_with_desc("set_active_hours", "设置更新标题的时段", set_active_hours);
+    bot.register_command_with_desc("reset_timezone", "恢复默认时区", reset_timezone);
+    bot.register_command_with_desc("set_number_format", "设置数字格式", set_number_format);
+    bot.register_command_with_desc("set_locale", "设置标题语言", set_locale);
+    bot.register_command_with_desc("set_week_start", "设置一周的第一天", set_week_start);
+    bot.register_command_with_desc("set_language", "设置回复语言", set_language);
+    bot.register_command_with_desc(
+        "set_length_policy",
+        "设置超长标题的处理方式",
+        set_length_policy,
+    );
+    bot.register_command_with_desc("now", "显示本群时区的当前时间", now);
+    bot.register_command_with_desc("preview", "预览模板渲染结果", preview);
+    bot.register_command_with_desc("preview_final", "预览将要应用的标题", preview_final);
+    bot.register_command_with_desc("width", "测量文本的显示宽度和长度", width);
+    bot.register_command_with_desc(
+        "list_specifiers",
+        "列出所有占位符及当前的值",
+        list_specifiers,
+    );
+    bot.register_command_with_desc("check_refs", "检查模板中未定义的占位符", check_refs);
+    bot.register_command_with_desc("counter_set", "设置计数器", counter_set);
+    bot.register_command_with_desc("counter_inc", "计数器加一", counter_inc);
+    bot.register_command_with_desc("counter_reset", "重置计数器", counter_reset);
+    bot.register_command_with_desc("freeze", "固定标题", freeze);
+    bot.register_command_with_desc("unfreeze", "取消固定标题", unfreeze);
+    bot.register_command_with_desc("push", "在模板末尾添加片段", push);
+    bot.register_command_with_desc("push_front", "在模板开头添加片段", push_front);
+    bot.register_command_with_desc("pop", "移除模板末尾的片段", pop);
+    bot.register_command_with_desc("pop_front", "移除模板开头的片段", pop_front);
+    bot.register_command_with_desc("move", "移动模板中的片段", move_segment);
+    bot.register_command_with_desc("edit", "替换模板中的一个片段", edit);
+    bot.register_command_with_desc(
+        "set_admin_only",
+        "设置是否仅限管理员更改设置",
+        set_admin_only,
+    );
+    bot.register_command_with_desc("add_editor", "允许非管理员更改设置", add_editor);
+    bot.register_command_with_desc("remove_editor", "移除编辑者", remove_editor);
+    bot.register_command_with_desc("set_enforce", "设置是否强制恢复标题", set_enforce);
+    bot.register_command_with_desc(
+        "set_auto_reenable",
+        "设置重新获得权限后是否自动启用",
+        set_auto_reenable,
+    );
+    bot.register_command_with_desc("add_rotation", "添加轮换标题", add_rotation);
+    bot.register_command_with_desc("clear_rotation", "清空轮换标题", clear_rotation);
+    bot.register_command_with_desc("set_rotate", "设置是否轮换标题", set_rotate);
+    bot.register_command_with_desc("set_bidi", "设置是否隔离双向文本", set_bidi);
+    bot.register_command_with_desc("set_dedupe", "设置是否合并重复片段", set_dedupe);
+    bot.register_command_with_desc(
+        "set_failure_message",
+        "设置无法更改标题时的提示",
+        set_failure_message,
+    );
+    bot.register_command_with_desc("set_fallback", "设置模板无法渲染时的备用标题", set_fallback);
+    bot.register_command_with_desc(
+        "set_announcement_template",
+        "设置公告模板",
+        set_announcement_template,
+    );
+    bot.register_command_with_desc("set_announce", "设置是否发送并置顶公告", set_announce);
+    bot.register_command_with_desc("errors", "列出最近的 API 错误", errors);
+    bot.register_command_with_desc("history", "列出最近的更改记录", history);
+    bot.register_command_with_desc("perms", "显示 bot 的权限", perms);
+    bot.register_command_with_desc(
+        "test_permission",
+        "测试 bot 能否更改群标题",
+        test_permission,
+    );
+    bot.register_command_with_desc("metrics", "显示上次定时任务的统计", metrics);
+    bot.register_command_with_desc("refresh_admins", "刷新管理员缓存", refresh_admins);
+    bot.register_command_with_desc("repair", "修复无效的设置", repair);
+    bot.register_command_with_desc("subscribe", "订阅标题更改通知", subscribe);
+    bot.register_inline_query(inline_query);
+    bot.register_my_chat_member(my_chat_member);
+    bot.register_command_with_desc("unsubscribe", "取消订阅标题更改通知", unsubscribe);
+    bot.register_command_with_desc("link_source", "使用另一个群的模板", link_source);
+    bot.register_command_with_desc("unlink_source", "停止使用另一个群的模板", unlink_source);
 
     // Router
     let router = Router::with_data(bot).get_async("/", |req, ctx| async move {
@@ -430,18 +3991,512 @@ pub async fn main_inner(
     let router = router.post_async("/updates", |mut req, ctx| async move {
         Bot::process_update(&mut req, ctx).await
     });
+    let router = router.post_async("/run-scheduled", |req, ctx| async move {
+        if let Some(rejection) = check_operator(&req, &ctx.env)? {
+            return Ok(rejection);
+        }
+        info!("Running the scheduled job on request");
+        let dry_run = is_dry_run(&ctx.env);
+        Response::from_json(&run_scheduled(&ctx.data, &ctx.env, dry_run).await?)
+    });
 
     // Run
     router.run(req, env).await
 }
 
+/// Body returned for errors when `DEBUG_ERRORS` is switched on
+#[derive(Serialize)]
+struct ErrorReply {
+    error: String,
+}
+
 #[event(fetch)]
 pub async fn main(req: Request, env: Env, ctx: worker::Context) -> Result<Response, WorkerError> {
+    let debug_errors = is_flag_set(&env, VAR_DEBUG_ERRORS);
     match main_inner(req, env, ctx).await {
         Ok(res) => Ok(res),
         Err(e) => {
             error!("Error occurred: {}", e);
+            // The details are only exposed when debugging, they may leak internals otherwise
+            if debug_errors {
+                return Ok(Response::from_json(&ErrorReply {
+                    error: e.to_string(),
+                })
+                .expect("Bruh, what just happened?"));
+            }
             Ok(Response::from_html("Internal Server Error").expect("Bruh, what just happened?"))
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_group() -> Group {
+        Group::new(
+            &ChatId(-100),
+            &ChatType::Group {
+                title: "Title".to_string(),
+                username: None,
+                all_members_are_administrators: false,
+            },
+        )
+    }
+
+    fn telegram_result(ok: bool, error_code: Option<i32>) -> TelegramResult<Message> {
+        TelegramResult {
+            ok,
+            description: None,
+            error_code,
+            result: None,
+            parameters: None,
+        }
+    }
+
+    #[test]
+    fn subscriber_is_notified_of_the_change() {
+        let mut group = test_group();
+        assert!(subscriber_notification(&group, "Old").is_none());
+        group.notify_user = Some(UserId(7));
+        let (user_id, text) = subscriber_notification(&group, "Old").unwrap();
+        assert_eq!(user_id, UserId(7));
+        assert!(text.contains("-100"));
+        assert!(text.contains("Old"));
+        assert!(text.contains("Title"));
+    }
+
+    #[test]
+    fn unreachable_subscriber_is_unsubscribed() {
+        let mut group = test_group();
+        group.notify_user = Some(UserId(7));
+        record_notification_result(&mut group, UserId(7), Ok(telegram_result(true, None)));
+        assert_eq!(group.notify_user, Some(UserId(7)));
+        // Transient failures keep the subscription
+        record_notification_result(&mut group, UserId(7), Ok(telegram_result(false, Some(429))));
+        let error = Err(WorkerError::RustError("reset".to_string()));
+        record_notification_result(&mut group, UserId(7), error);
+        assert_eq!(group.notify_user, Some(UserId(7)));
+        record_notification_result(&mut group, UserId(7), Ok(telegram_result(false, Some(403))));
+        assert_eq!(group.notify_user, None);
+    }
+
+    #[test]
+    fn arguments_skip_the_command_and_bot_name() {
+        assert_eq!(get_argument("/echo hi"), Some("hi"));
+        assert_eq!(get_argument("/echo@title_bot hi"), Some("hi"));
+        assert_eq!(get_argument("/echo    hi  "), Some("hi"));
+        assert_eq!(get_argument("  /echo\thi there"), Some("hi there"));
+    }
+
+    #[test]
+    fn missing_arguments_are_none() {
+        assert_eq!(get_argument("/echo"), None);
+        assert_eq!(get_argument("/echo@title_bot"), None);
+        assert_eq!(get_argument("/echo    "), None);
+        assert_eq!(get_raw_argument("/echo"), None);
+        assert_eq!(get_raw_argument("/echo "), None);
+    }
+
+    #[test]
+    fn raw_arguments_keep_their_spaces() {
+        assert_eq!(get_raw_argument("/set_delimiter  · "), Some(" · "));
+        assert_eq!(get_raw_argument("/set_delimiter@title_bot   "), Some("  "));
+        assert_eq!(
+            parse_delimiter(get_raw_argument("/set_delimiter \" \"").unwrap()),
+            " "
+        );
+        assert_eq!(
+            parse_delimiter(get_raw_argument("/set_delimiter \"\"").unwrap()),
+            ""
+        );
+    }
+
+    fn article_of(result: InlineQueryResult<'_>) -> (String, String, String) {
+        match result {
+            InlineQueryResult::Article(article) => {
+                let message = match article.input_message_content {
+                    InputMessageContent::Text(content) => content.message_text.to_string(),
+                    _ => panic!("not a text message"),
+                };
+                (article.id.0, article.title.to_string(), message)
+            }
+            _ => panic!("not an article"),
+        }
+    }
+
+    #[test]
+    fn inline_query_is_rendered() {
+        let now = "2022-05-16T18:30:00".parse::<NaiveDateTime>().unwrap();
+        let (id, title, message) = article_of(inline_query_result("{H}:{M}", now));
+        assert_eq!(id, "rendered");
+        assert_eq!(title, "18:30");
+        assert_eq!(message, "18:30");
+        let (_, title, _) = article_of(inline_query_result("Asia/Shanghai  今天{m}月{d}日", now));
+        assert_eq!(title, "今天05月17日");
+    }
+
+    #[test]
+    fn invalid_inline_query_is_an_error_result() {
+        let now = "2022-05-16T18:30:00".parse::<NaiveDateTime>().unwrap();
+        for query in ["", "   ", "{strftime:%Q}"] {
+            let (id, title, _) = article_of(inline_query_result(query, now));
+            assert_eq!(id, "error");
+            assert_eq!(title, "无法渲染模板");
+        }
+    }
+
+    #[test]
+    fn scheduled_run_skips_groups_that_are_not_due() {
+        let now = "2022-12-20T01:00:00".parse::<NaiveDateTime>().unwrap();
+        let mut group = test_group();
+        group.enable = true;
+        group.update_interval = 60;
+        group.last_updated = now.timestamp_millis() as u64;
+        assert_eq!(
+            skip_scheduled_group(&mut group, "-100", now),
+            Some(ScheduledOutcome::NotDue)
+        );
+        group.active_hours = Some((9, 17));
+        group.pending_apply = true;
+        assert_eq!(
+            skip_scheduled_group(&mut group, "-100", now),
+            Some(ScheduledOutcome::NotDue)
+        );
+        group.timezone = "Asia/Shanghai".to_string();
+        assert_eq!(skip_scheduled_group(&mut group, "-100", now), None);
+        group.enable = false;
+        assert_eq!(
+            skip_scheduled_group(&mut group, "-100", now),
+            Some(ScheduledOutcome::Disabled)
+        );
+    }
+
+    #[test]
+    fn scheduled_run_activates_due_templates() {
+        let now = "2022-12-20T01:00:00".parse::<NaiveDateTime>().unwrap();
+        let mut group = test_group();
+        group.enable = true;
+        group.update_interval = 60;
+        group.last_updated = now.timestamp_millis() as u64;
+        group.schedule_template(
+            NaiveDate::from_ymd(2022, 12, 20),
+            vec!["Holiday".to_string()],
+        );
+        assert_eq!(skip_scheduled_group(&mut group, "-100", now), None);
+        assert_eq!(group.title_segment, vec!["Holiday".to_string()]);
+
+        group.clear_title_template();
+        group.pending_apply = true;
+        assert_eq!(
+            skip_scheduled_group(&mut group, "-100", now),
+            Some(ScheduledOutcome::Failed)
+        );
+    }
+
+    #[test]
+    fn scheduled_summary_counts_the_outcomes() {
+        let mut summary = ScheduledSummary::default();
+        for outcome in [
+            ScheduledOutcome::Updated,
+            ScheduledOutcome::Updated,
+            ScheduledOutcome::NotDue,
+            ScheduledOutcome::Failed,
+            ScheduledOutcome::OutOfTime,
+        ] {
+            summary.record(outcome);
+        }
+        assert_eq!(summary.processed, 4);
+        assert_eq!(summary.updated, 2);
+        assert_eq!(summary.not_due, 1);
+        assert_eq!(summary.failed, 1);
+    }
+
+    #[test]
+    fn final_title_follows_the_length_policy() {
+        let mut group = test_group();
+        group.last_title = "Current".to_string();
+        let long_title = "a".repeat(MAX_TITLE_LENGTH + 1);
+        let describe =
+            |group: &Group, title: &str| describe_final_title(group, Ok(title.to_string()));
+
+        assert_eq!(describe(&group, "Short"), "将应用标题： Short");
+        group.length_policy = LengthPolicy::Error;
+        assert!(describe(&group, &long_title).starts_with("无法应用标题： "));
+        group.length_policy = LengthPolicy::Truncate;
+        assert_eq!(
+            describe(&group, &long_title),
+            format!(
+                "标题超出长度限制，将被截断为： {}",
+                group::truncate_title(&long_title)
+            )
+        );
+        group.length_policy = LengthPolicy::Skip;
+        assert_eq!(
+            describe(&group, &long_title),
+            format!(
+                "标题超出 {} 的长度限制，将跳过更新并保留当前标题： Current",
+                MAX_TITLE_LENGTH
+            )
+        );
+        assert_eq!(describe(&group, "Short"), "将应用标题： Short");
+    }
+
+    #[test]
+    fn final_title_reports_render_errors() {
+        let group = test_group();
+        let error = WorkerError::RustError("Template is empty".to_string());
+        assert_eq!(
+            describe_final_title(&group, Err(error)),
+            "无法渲染模板： Template is empty"
+        );
+    }
+
+    fn test_message(text: &str, username: Option<&str>) -> Message {
+        serde_json::from_value(serde_json::json!({
+            "message_id": 1,
+            "date": 0,
+            "chat": {"id": -100, "type": "group", "title": "Title"},
+            "from": {"id": 42, "is_bot": false, "first_name": "Alice", "username": username},
+            "text": text,
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn commands_are_recorded_with_their_sender() {
+        let entry = command_history_entry(
+            &test_message("/set_template@title_bot 新年快乐", Some("alice")),
+            "新年快乐".to_string(),
+            1_704_110_400_000,
+        );
+        assert_eq!(entry.source, "set_template");
+        assert_eq!(entry.user.as_deref(), Some("@alice"));
+        let entry = command_history_entry(&test_message("!push {Y}", None), "{Y}".to_string(), 0);
+        assert_eq!(entry.source, "push");
+        assert_eq!(entry.user.as_deref(), Some("42"));
+
+        let mut group = test_group();
+        group.timezone = "Asia/Shanghai".to_string();
+        group.record_history(HistoryEntry {
+            timestamp: 1_704_100_000_000,
+            source: SCHEDULED_SOURCE.to_string(),
+            user: None,
+            value: "旧年".to_string(),
+        });
+        group.record_history(command_history_entry(
+            &test_message("/set_template 新年快乐", Some("alice")),
+            "新年快乐".to_string(),
+            1_704_110_400_000,
+        ));
+        assert_eq!(
+            group.describe_history(),
+            format!(
+                "2024-01-01 20:00 set_template by @alice → 新年快乐\n2024-01-01 17:06 {} → 旧年",
+                SCHEDULED_SOURCE
+            )
+        );
+    }
+
+    #[test]
+    fn custom_failure_message_is_used() {
+        let mut group = test_group();
+        assert_eq!(group.failure_message(), group::DEFAULT_FAILURE_MESSAGE);
+        assert!(scheduled_failure_notice(&group).starts_with(group::DEFAULT_FAILURE_MESSAGE));
+        group.language = Language::En;
+        assert_ne!(group.failure_message(), group::DEFAULT_FAILURE_MESSAGE);
+
+        group.failure_message = Some("Please make me an admin again".to_string());
+        assert_eq!(group.failure_message(), "Please make me an admin again");
+        assert_eq!(
+            scheduled_failure_notice(&group).lines().next(),
+            Some("Please make me an admin again")
+        );
+    }
+
+    #[test]
+    fn scheduled_summary_tallies_a_mix_of_groups() {
+        let now = "2022-12-20T01:00:00".parse::<NaiveDateTime>().unwrap();
+        let enabled = || {
+            let mut group = test_group();
+            group.enable = true;
+            group.update_interval = 60;
+            group.last_updated = now.timestamp_millis() as u64;
+            group
+        };
+        let disabled = test_group();
+        let not_due = enabled();
+        let mut quiet = enabled();
+        quiet.active_hours = Some((9, 17));
+        let mut due = enabled();
+        due.pending_apply = true;
+        let mut empty = enabled();
+        empty.pending_apply = true;
+        empty.clear_title_template();
+
+        let mut summary = ScheduledSummary::default();
+        for mut group in [disabled, not_due, quiet, due, empty] {
+            // Groups that get through the checks are renamed in a real run
+            let outcome =
+                skip_scheduled_group(&mut group, "-100", now).unwrap_or(ScheduledOutcome::Updated);
+            summary.record(outcome);
+        }
+        assert_eq!(
+            summary,
+            ScheduledSummary {
+                processed: 5,
+                updated: 1,
+                disabled: 1,
+                not_due: 2,
+                failed: 1,
+                ..Default::default()
+            }
+        );
+    }
+
+    #[test]
+    fn counters_are_changed_and_rendered() {
+        let mut group = test_group();
+        group.title_segment = vec!["{counter:days} 天无事故".to_string()];
+        let time = to_local_time("2022-05-16T18:30:00".parse().unwrap(), Tz::UTC);
+        let render = |group: &Group| {
+            group
+                .render_text(&group.assemble_title_template(), time)
+                .unwrap()
+        };
+        // Undefined counters render as 0
+        assert_eq!(render(&group), "0 天无事故");
+
+        group.counters.insert("days".to_string(), 41);
+        group.increment_counter("days");
+        assert_eq!(render(&group), "42 天无事故");
+        group.increment_counter("new");
+        assert_eq!(group.counters.get("new"), Some(&1));
+        group.counters.insert("max".to_string(), i64::MAX);
+        group.increment_counter("max");
+        assert_eq!(group.counters.get("max"), Some(&i64::MAX));
+
+        assert!(group.reset_counter("days"));
+        assert_eq!(render(&group), "0 天无事故");
+        assert!(!group.reset_counter("missing"));
+        assert!(!group.counters.contains_key("missing"));
+    }
+
+    #[test]
+    fn counter_names_and_count_are_validated() {
+        let mut group = test_group();
+        assert!(validate_counter(&group, "days_2").is_ok());
+        assert!(validate_counter(&group, "").is_err());
+        assert!(validate_counter(&group, "no-dash").is_err());
+        assert!(validate_counter(&group, &"a".repeat(33)).is_err());
+        for i in 0..MAX_COUNTERS {
+            group.counters.insert(format!("c{}", i), 0);
+        }
+        assert!(validate_counter(&group, "one_more").is_err());
+        assert!(validate_counter(&group, "c0").is_ok());
+    }
+
+    #[test]
+    fn anonymous_admins_are_not_looked_up() {
+        let mut m = test_message("/enable", None);
+        assert_eq!(admin_to_check(&m).unwrap(), Some(UserId(42)));
+        m.from.as_mut().unwrap().id = ANONYMOUS_ADMIN_ID;
+        assert_eq!(admin_to_check(&m).unwrap(), None);
+        m.from = None;
+        assert!(admin_to_check(&m).is_err());
+    }
+
+    #[test]
+    fn empty_template_is_skipped_without_disabling() {
+        let now = "2022-12-20T01:00:00".parse::<NaiveDateTime>().unwrap();
+        let mut group = test_group();
+        group.enable = true;
+        group.clear_title_template();
+        assert_eq!(
+            skip_scheduled_group(&mut group, "-100", now),
+            Some(ScheduledOutcome::Failed)
+        );
+        assert!(group.enable);
+        assert_eq!(group.consecutive_failures, 0);
+        assert_eq!(
+            check_template(&group, now).as_deref(),
+            Some(warn_empty_template(group.language))
+        );
+    }
+
+    #[test]
+    fn missing_env_is_named() {
+        let vars = |name: &str| match name {
+            VAR_USERNAME => Some("title_bot".to_string()),
+            VAR_KV_STORE => Some("TITLE_BOT".to_string()),
+            _ => None,
+        };
+        assert!(missing_env(|_| true, vars, |name| name == "TITLE_BOT").is_empty());
+        assert_eq!(
+            missing_env(|_| false, vars, |_| false),
+            vec![
+                format!("secret {}", DEFAULT_SECRET_TOKEN),
+                format!("KV namespace binding TITLE_BOT (named by {})", VAR_KV_STORE),
+            ]
+        );
+        assert_eq!(
+            missing_env(|_| true, |_| None, |_| true),
+            vec![
+                format!("variable {}", VAR_USERNAME),
+                format!("variable {}", VAR_KV_STORE),
+            ]
+        );
+    }
+
+    #[test]
+    fn rapid_edits_are_applied_together() {
+        let utc_time = "2022-05-16T18:30:00".parse::<NaiveDateTime>().unwrap();
+        let now = utc_time.timestamp_millis() as u64;
+        let mut group = test_group();
+        group.enable = true;
+        group.update_interval = 60;
+        group.last_updated = now;
+        assert!(!group.is_due(now));
+
+        for segment in ["a", "b", "c"] {
+            group.push_title_template(segment);
+            let reply = stage_template(&mut group, &test_message("/push", None), None, utc_time);
+            assert!(reply.unwrap().ends_with("使用 /apply 立即应用"));
+        }
+        assert_eq!(group.history.len(), 3);
+        // The next run is due right away and applies all edits at once
+        assert!(group.is_due(now));
+        let title = match group.finalize_title_at(group.get_time(utc_time)).unwrap() {
+            FinalTitle::Apply(title) => title,
+            other => panic!("unexpected {:?}", other),
+        };
+        assert_eq!(
+            group.scheduled_update(&title),
+            group::ScheduledUpdate::Apply
+        );
+        group.record_applied_title(title.clone(), now);
+        assert!(!group.is_due(now + 60_000));
+        assert_eq!(
+            group.scheduled_update(&title),
+            group::ScheduledUpdate::Unchanged
+        );
+    }
+
+    #[test]
+    fn refused_edits_are_not_staged() {
+        let utc_time = "2022-05-16T18:30:00".parse::<NaiveDateTime>().unwrap();
+        let mut group = test_group();
+        group.enable = true;
+        group.push_title_template("{undefined}");
+        assert!(stage_template(&mut group, &test_message("/push", None), None, utc_time).is_err());
+        assert!(!group.pending_apply);
+        assert!(group.history.is_empty());
+
+        // Disabled groups aren't marked, and aren't told it will be applied
+        group.pop_title_template();
+        group.enable = false;
+        let reply = stage_template(&mut group, &test_message("/pop", None), Some("!"), utc_time);
+        assert!(reply.unwrap().ends_with("\n!"));
+        assert!(!group.pending_apply);
+    }
+}